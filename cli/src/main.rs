@@ -17,18 +17,38 @@ use async_std::{
     os::unix::fs::OpenOptionsExt,
     path::{Path, PathBuf},
 };
-use clap::{App, Arg, ArgMatches};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use futures::{
     channel::{mpsc, oneshot},
-    executor, join,
+    executor,
+    io::AllowStdIo,
+    join,
     prelude::*,
 };
+use futures_codec::{FramedRead, FramedWrite};
 use i18n_embed::DesktopLanguageRequester;
 use pbr::{MultiBar, Pipe, ProgressBar, Units};
-use popsicle::{mnt, Progress, Task};
+use popsicle::{
+    checksums,
+    codec::{self, Control, ControlDecoder, Format, Message, PopsicleEncoder},
+    hash::{self, Algorithm, Checksum, ChecksumMismatch, DigestCache, Digests, HashCancel},
+    http,
+    inspect::ImageReport,
+    mnt, service,
+    signature::{self, Keys},
+    Backend, BackendKind, Device, ImageSize, ImageSource, MessageProgress, Progress, RemoteImage,
+    SafetyPolicy, Task, TaskControl, UnmountFlags,
+};
 use std::{
-    io::{self, Write},
-    process, thread,
+    fs,
+    io::{self, Read, Write},
+    os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    },
+    process,
+    sync::Mutex,
+    thread,
 };
 
 fn main() {
@@ -38,6 +58,20 @@ fn main() {
     let arg_image = fl!("arg-image");
     let arg_disks = fl!("arg-disks");
 
+    // Every hash algorithm has an option, such as `--sha256`, named after the algorithm.
+    let algorithm_names: Vec<String> =
+        Algorithm::ALL.iter().map(|algorithm| algorithm.name().to_ascii_lowercase()).collect();
+    let algorithm_names: Vec<&str> = algorithm_names.iter().map(String::as_str).collect();
+    let checksum_descs: Vec<String> = Algorithm::ALL
+        .iter()
+        .map(|algorithm| fl!("arg-checksum-desc", algorithm = algorithm.name()))
+        .collect();
+    let checksum_args: Vec<Arg> = algorithm_names
+        .iter()
+        .zip(&checksum_descs)
+        .map(|(name, desc)| checksum_arg(name, desc))
+        .collect();
+
     let matches = App::new(env!("CARGO_PKG_NAME"))
         .about(env!("CARGO_PKG_DESCRIPTION"))
         .version(env!("CARGO_PKG_VERSION"))
@@ -46,9 +80,153 @@ fn main() {
         .arg(Arg::with_name("all").help(&fl!("arg-all-desc")).short("a").long("all"))
         .arg(Arg::with_name("check").help(&fl!("arg-check-desc")).short("c").long("check"))
         .arg(Arg::with_name("unmount").help(&fl!("arg-unmount-desc")).short("u").long("unmount"))
+        .arg(Arg::with_name("lazy").help(&fl!("arg-lazy-desc")).long("lazy").requires("unmount"))
+        .arg(Arg::with_name("force").help(&fl!("arg-force-desc")).long("force").requires("unmount"))
         .arg(Arg::with_name("yes").help(&fl!("arg-yes-desc")).short("y").long("yes"))
+        .arg(
+            Arg::with_name("allow-unsafe").help(&fl!("arg-allow-unsafe-desc")).long("allow-unsafe"),
+        )
+        .arg(
+            Arg::with_name("max-size")
+                .help(&fl!("arg-max-size-desc"))
+                .long("max-size")
+                .value_name("GIB")
+                .takes_value(true),
+        )
+        .args(&checksum_args)
+        .arg(
+            Arg::with_name("checksum-file")
+                .help(&fl!("arg-checksum-file-desc"))
+                .long("checksum-file")
+                .value_name("FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("keyring")
+                .help(&fl!("arg-keyring-desc"))
+                .long("keyring")
+                .value_name("FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("minisign-key")
+                .help(&fl!("arg-minisign-key-desc"))
+                .long("minisign-key")
+                .value_name("FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("no-checksum-discovery")
+                .help(&fl!("arg-no-checksum-discovery-desc"))
+                .long("no-checksum-discovery"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .help(&fl!("arg-format-desc"))
+                .long("format")
+                .possible_values(&["ron", "jsonl"])
+                .default_value("ron"),
+        )
+        .arg(
+            Arg::with_name("control")
+                .help(&fl!("arg-control-desc"))
+                .long("control")
+                .value_name("PATH")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("backend")
+                .help(&fl!("arg-backend-desc"))
+                .long("backend")
+                .possible_values(&["direct", "udisks2"])
+                .default_value("direct")
+                .global(true),
+        )
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(SubCommand::with_name("list").about(&*fl!("list-desc")))
+        .subcommand(
+            SubCommand::with_name("dbus-service").about(&*fl!("dbus-service-desc")),
+        )
+        .subcommand(
+            SubCommand::with_name("hash")
+                .about(&*fl!("hash-desc"))
+                .arg(Arg::with_name("image").help(&fl!("arg-image-desc")).required(true))
+                .arg(
+                    Arg::with_name("algorithm")
+                        .help(&fl!("arg-algorithm-desc"))
+                        .long("algorithm")
+                        .short("A")
+                        .possible_values(&algorithm_names)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .default_value("sha256"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("info")
+                .about(&*fl!("info-desc"))
+                .arg(Arg::with_name("image").help(&fl!("arg-image-desc")).required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("serve")
+                .about(&*fl!("serve-desc"))
+                .arg(
+                    Arg::with_name("listen")
+                        .help(&fl!("arg-listen-desc"))
+                        .long("listen")
+                        .value_name("ADDRESS")
+                        .default_value("127.0.0.1:8076"),
+                )
+                .arg(
+                    Arg::with_name("images")
+                        .help(&fl!("arg-images-desc"))
+                        .long("images")
+                        .value_name("DIR")
+                        .default_value("."),
+                ),
+        )
         .get_matches();
 
+    if let ("list", Some(list_matches)) = matches.subcommand() {
+        if let Err(why) = executor::block_on(list(list_matches)) {
+            exit_with(why);
+        }
+
+        return;
+    }
+
+    if let ("dbus-service", Some(service_matches)) = matches.subcommand() {
+        if let Err(why) = dbus_service(service_matches) {
+            exit_with(why);
+        }
+
+        return;
+    }
+
+    if let ("hash", Some(hash_matches)) = matches.subcommand() {
+        if let Err(why) = hash_image(hash_matches) {
+            exit_with(why);
+        }
+
+        return;
+    }
+
+    if let ("info", Some(info_matches)) = matches.subcommand() {
+        if let Err(why) = image_info(info_matches) {
+            exit_with(why);
+        }
+
+        return;
+    }
+
+    if let ("serve", Some(serve_matches)) = matches.subcommand() {
+        if let Err(why) = http_service(serve_matches) {
+            exit_with(why);
+        }
+
+        return;
+    }
+
     let (rtx, rrx) = oneshot::channel::<anyhow::Result<()>>();
 
     let result = executor::block_on(async move {
@@ -62,21 +240,262 @@ fn main() {
     });
 
     if let Err(why) = result {
-        eprintln!("popsicle: {}", why);
-        for source in why.chain().skip(1) {
-            epintln!("    " (fl!("error-caused-by")) ": " (source))
+        exit_with(why);
+    }
+}
+
+/// The exit status when the image does not match an expected checksum.
+const EXIT_CHECKSUM_MISMATCH: i32 = 3;
+
+fn exit_with(why: anyhow::Error) -> ! {
+    eprintln!("popsicle: {}", why);
+    for source in why.chain().skip(1) {
+        epintln!("    " (fl!("error-caused-by")) ": " (source))
+    }
+
+    process::exit(if is_checksum_mismatch(&why) { EXIT_CHECKSUM_MISMATCH } else { 1 });
+}
+
+/// Whether the error was caused by a checksum mismatch, which may have been found while an
+/// image was verified up front, or while it was streamed from a URL.
+fn is_checksum_mismatch(why: &anyhow::Error) -> bool {
+    why.chain().any(|cause| {
+        cause.is::<ChecksumMismatch>()
+            || cause
+                .downcast_ref::<io::Error>()
+                .and_then(io::Error::get_ref)
+                .map_or(false, |inner| inner.is::<ChecksumMismatch>())
+    })
+}
+
+fn checksum_arg<'a>(name: &'a str, help: &'a str) -> Arg<'a, 'a> {
+    Arg::with_name(name).help(help).long(name).value_name("HEX").takes_value(true)
+}
+
+/// Lists every detected USB drive, with the details needed to tell them apart.
+async fn list(matches: &ArgMatches<'_>) -> anyhow::Result<()> {
+    let backend = backend(matches)?;
+
+    let mut disk_args = Vec::new();
+    backend.usb_disks(&mut disk_args).await.with_context(|| fl!("error-disks-fetch"))?;
+
+    let mounts = mnt::get_submounts(Path::new("/")).with_context(|| fl!("error-reading-mounts"))?;
+
+    for path in disk_args {
+        match Device::new(&*path, &mounts) {
+            Ok(device) => {
+                pintln!(
+                    (device_description(&device))
+                    if let Some(ref serial) = device.serial {
+                        "\n    " (fl!("device-serial")) ": " (serial)
+                    }
+                    "\n    " (fl!("device-bus")) ": " (device.bus)
+                    if let Some(speed) = device.usb_speed {
+                        " (" (speed) " Mbit/s)"
+                    }
+                    if let Some(ref port) = device.port {
+                        "\n    " (fl!("device-port")) ": " (port)
+                    }
+                    for partition in &device.partitions {
+                        "\n    " (partition.path.display()) ": "
+                        (bytesize::to_string(partition.size, true))
+                        for mount in &partition.mounts {
+                            " [" (mount.display()) "]"
+                        }
+                    }
+                );
+            }
+            Err(why) => {
+                epintln!((path.display()) ": " (why));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Serves the `com.system76.Popsicle1` D-Bus API on the session bus, until the connection to
+/// the bus is lost.
+fn dbus_service(matches: &ArgMatches<'_>) -> anyhow::Result<()> {
+    let backend = backend(matches)?;
+    let connection =
+        dbus::blocking::Connection::new_session().with_context(|| fl!("error-dbus-connect"))?;
+
+    let devices = service::SystemDevices::new(backend, SafetyPolicy::default());
+    service::serve(connection, devices).with_context(|| fl!("error-dbus-service"))
+}
+
+/// Prints the digests of an image, which are computed from a single read of it.
+fn hash_image(matches: &ArgMatches<'_>) -> anyhow::Result<()> {
+    let image_path = matches.value_of("image").expect("image is required");
+    let algorithms = matches
+        .values_of("algorithm")
+        .expect("algorithm has a default")
+        .map(str::parse::<Algorithm>)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let digests = hash_with_progress(image_path, &algorithms, true)?;
+    for (algorithm, digest) in digests.iter() {
+        println!("{} ({}) = {}", algorithm, image_path, digest);
+    }
+
+    Ok(())
+}
+
+/// Prints what an image contains, and which firmware it may be booted by.
+fn image_info(matches: &ArgMatches<'_>) -> anyhow::Result<()> {
+    let image_path = matches.value_of("image").expect("image is required");
+    let report = ImageReport::new(image_path)
+        .with_context(|| fl!("error-image-open", image_path = image_path))?;
+
+    pintln!(
+        (fl!("info-container")) ": " (report.container)
+        if let Some(ref os) = report.os {
+            "\n" (fl!("info-os")) ": " (os)
+        }
+        if let Some(ref iso) = report.iso {
+            if !iso.volume.is_empty() { "\n" (fl!("info-volume")) ": " (iso.volume) }
+            if !iso.publisher.is_empty() { "\n" (fl!("info-publisher")) ": " (iso.publisher) }
+            if !iso.application.is_empty() {
+                "\n" (fl!("info-application")) ": " (iso.application)
+            }
         }
+        if let Some(table) = report.partition_table {
+            "\n" (fl!("info-partition-table")) ": " (table)
+        }
+        for partition in &report.partitions {
+            "\n    " (partition.number) ": " (partition.kind) " "
+            (bytesize::to_string(partition.size, true))
+            if let Some(ref name) = partition.name { " \"" (name) "\"" }
+            if partition.kind.is_efi_system() { " (" (fl!("info-efi-system")) ")" }
+        }
+        if let Some(boot) = report.boot {
+            "\n" (fl!("info-boot")) ": " (boot)
+        }
+    );
+
+    Ok(())
+}
+
+/// Hashes a local image, unless `cached` is set and its digests are cached, displaying a
+/// progress bar if stderr is a TTY.
+fn hash_with_progress(
+    image_path: &str,
+    algorithms: &[Algorithm],
+    cached: bool,
+) -> anyhow::Result<Digests> {
+    let size = fs::metadata(image_path)
+        .map(|metadata| metadata.len())
+        .with_context(|| fl!("error-image-metadata", image_path = image_path))?;
+
+    let mut bar = if atty::is(atty::Stream::Stderr) {
+        let mut bar = ProgressBar::on(io::stderr(), size);
+        bar.set_units(Units::Bytes);
+        bar.message(&format!("H {}: ", image_path));
+        Some(bar)
+    } else {
+        None
+    };
+
+    let path = std::path::Path::new(image_path);
+    let cancel = HashCancel::default();
+    let progress = |hashed| {
+        if let Some(ref mut bar) = bar {
+            bar.set(hashed);
+        }
+    };
+
+    // Digests are remembered across runs, until the image is changed.
+    let digests = if cached {
+        let cache = Mutex::new(DigestCache::open_default());
+        hash::hash_file_cached(path, algorithms, &cache, &cancel, progress)?
+    } else {
+        hash::hash_file(path, algorithms, &cancel, progress)?
+    };
 
-        process::exit(1);
+    if let Some(mut bar) = bar {
+        bar.finish();
     }
+
+    Ok(digests)
 }
 
-async fn popsicle(
-    rtx: oneshot::Sender<anyhow::Result<()>>,
-    matches: ArgMatches<'_>,
-) -> anyhow::Result<()> {
-    let image_path =
-        matches.value_of(&fl!("arg-image")).with_context(|| fl!("error-image-not-set"))?;
+/// Serves the HTTP API on the `--listen` address until the listener fails.
+fn http_service(matches: &ArgMatches<'_>) -> anyhow::Result<()> {
+    let backend = backend(matches)?;
+    let address = matches.value_of("listen").expect("listen has a default");
+    let images = matches.value_of("images").expect("images has a default");
+
+    let devices = service::SystemDevices::new(backend, SafetyPolicy::default());
+    let server = http::Server::bind(address, images.into(), devices)
+        .with_context(|| fl!("error-http-listen", address = address))?;
+
+    if let Some(address) = server.local_addr() {
+        epintln!((fl!("serve-listening", address = address.to_string())));
+    }
+
+    server.run();
+    Ok(())
+}
+
+/// The device backend selected with `--backend`.
+fn backend(matches: &ArgMatches<'_>) -> anyhow::Result<Backend> {
+    let kind = matches.value_of("backend").unwrap_or("direct").parse::<BackendKind>()?;
+    Backend::new(kind).with_context(|| fl!("error-backend"))
+}
+
+/// Describes a device by its vendor, model, path, and size.
+fn device_description(device: &Device) -> String {
+    let mut description =
+        [device.label(), " ".into(), bytesize::to_string(device.size, true)].concat();
+
+    if device.removable {
+        description.push_str(" (");
+        description.push_str(&fl!("device-removable"));
+        description.push(')');
+    }
+
+    description
+}
+
+/// Opens the image, which is streamed as it is flashed if it is a URL, along with the size of
+/// the image and of the data that it contains, and the digests that it was verified with.
+async fn open_image(
+    matches: &ArgMatches<'_>,
+    image_path: &str,
+) -> anyhow::Result<(Box<dyn ImageSource>, u64, ImageSize, Digests)> {
+    if popsicle::is_url(image_path) {
+        let mut image = RemoteImage::open(image_path)
+            .with_context(|| fl!("error-image-open", image_path = image_path.clone()))?;
+
+        for checksum in expected_checksums(matches, image_path)? {
+            image = image.with_checksum(checksum);
+        }
+
+        // Measuring the data of a compressed image seeks through it, and every seek of a
+        // remote image is another request, so only the size of the file is known.
+        let file = image.size().unwrap_or(0);
+        let data_size = ImageSize { file, compression: None, data: 0, exact: false };
+
+        // Remote images are verified as they are streamed, after the stream has begun.
+        let image: Box<dyn ImageSource> = Box::new(AllowStdIo::new(image));
+        return Ok((image, data_size.file, data_size, Digests::default()));
+    }
+
+    let mut checksums = expected_checksums(matches, image_path)?;
+    if checksums.is_empty() && !matches.is_present("no-checksum-discovery") {
+        if let Some(discovered) = checksums::discover(std::path::Path::new(image_path)) {
+            epintln!((fl!("checksum-file-found", path = discovered.path.display().to_string())));
+            check_signature(matches, &discovered.path)?;
+            checksums = discovered.checksums;
+        }
+    }
+
+    let digests = if checksums.is_empty() {
+        Digests::default()
+    } else {
+        verify_image(image_path, &checksums)?
+    };
 
     let image = OpenOptions::new()
         .custom_flags(libc::O_SYNC)
@@ -91,11 +510,119 @@ async fn popsicle(
         .map(|x| x.len())
         .with_context(|| fl!("error-image-metadata", image_path = image_path.clone()))?;
 
+    let data_size = ImageSize::new(image_path)
+        .with_context(|| fl!("error-image-metadata", image_path = image_path.clone()))?;
+
+    Ok((Box::new(image), image_size, data_size, digests))
+}
+
+/// The checksums given with `--sha256` and its siblings, and those which `--checksum-file`
+/// lists for the image.
+fn expected_checksums(
+    matches: &ArgMatches<'_>,
+    image_path: &str,
+) -> anyhow::Result<Vec<Checksum>> {
+    let mut checksums = Vec::new();
+
+    for &algorithm in &Algorithm::ALL {
+        let name = algorithm.name().to_ascii_lowercase();
+        if let Some(digest) = matches.value_of(&name) {
+            checksums.push(Checksum::new(algorithm, digest)?);
+        }
+    }
+
+    if let Some(path) = matches.value_of("checksum-file") {
+        let file_name = if popsicle::is_url(image_path) {
+            image_path.split(|c| c == '?' || c == '#').next().unwrap_or(image_path)
+        } else {
+            image_path
+        };
+
+        let file_name = file_name.rsplit('/').next().unwrap_or(file_name);
+        let path = std::path::Path::new(path);
+        check_signature(matches, path)?;
+        checksums.extend(checksums::checksums_for(path, file_name)?);
+    }
+
+    Ok(checksums)
+}
+
+/// Verifies the signature of a checksum file, refusing it if the signature is not valid, or if
+/// it is not signed by a configured key, and warning if it could not be checked.
+fn check_signature(
+    matches: &ArgMatches<'_>,
+    checksum_file: &std::path::Path,
+) -> anyhow::Result<()> {
+    let mut keys = Keys::from_config();
+    if let Some(keyring) = matches.value_of("keyring") {
+        // gpgv looks for a keyring without a slash in its name in `~/.gnupg`.
+        let keyring =
+            fs::canonicalize(keyring).with_context(|| fl!("error-keyring", path = keyring))?;
+        keys.keyring = Some(keyring);
+    }
+
+    if let Some(key) = matches.value_of("minisign-key") {
+        keys.minisign_key = Some(key.into());
+    }
+
+    let path = checksum_file.display().to_string();
+    match signature::check(checksum_file, &keys) {
+        Ok(Some(signer)) => {
+            epintln!((fl!("checksum-file-signed", path = path, signer = signer.to_string())));
+        }
+        Ok(None) => epintln!((fl!("warning-checksum-file-unsigned", path = path))),
+        Err(why) if why.is_rejected() => return Err(why.into()),
+        Err(why) => epintln!((fl!("warning-signature-unverified", why = why.to_string()))),
+    }
+
+    Ok(())
+}
+
+/// Verifies a local image against the expected checksums before anything is written,
+/// returning its digests.
+///
+/// The image is always read, rather than trusting digests from the cache, which anyone who may
+/// write to the user's cache directory could have put there.
+fn verify_image(image_path: &str, checksums: &[Checksum]) -> anyhow::Result<Digests> {
+    let algorithms: Vec<Algorithm> = checksums.iter().map(Checksum::algorithm).collect();
+    let digests = hash_with_progress(image_path, &algorithms, false)?;
+    for checksum in checksums {
+        checksum.verify(&digests)?;
+    }
+
+    Ok(digests)
+}
+
+async fn popsicle(
+    rtx: oneshot::Sender<anyhow::Result<()>>,
+    matches: ArgMatches<'_>,
+) -> anyhow::Result<()> {
+    let image_path =
+        matches.value_of(&fl!("arg-image")).with_context(|| fl!("error-image-not-set"))?;
+
+    let (image, image_size, data_size, digests) = open_image(&matches, image_path).await?;
+
+    if !data_size.exact {
+        epintln!((fl!("warning-image-size-unknown", image_path = image_path.clone())));
+    }
+
+    // Pipes are not inspected, as what is read from them would be missing from the image.
+    let inspectable = fs::metadata(image_path).map_or(false, |metadata| {
+        metadata.file_type().is_file() || metadata.file_type().is_block_device()
+    });
+
+    if inspectable {
+        let report = ImageReport::new(image_path);
+        if report.map_or(false, |report| report.is_optical_only()) {
+            epintln!((fl!("warning-iso-not-hybrid", image_path = image_path.clone())));
+        }
+    }
+
+    let backend = backend(&matches)?;
+
     let mut disk_args = Vec::new();
     if matches.is_present("all") {
-        popsicle::usb_disk_devices(&mut disk_args)
-            .await
-            .with_context(|| fl!("error-disks-fetch"))?;
+        backend.usb_disks(&mut disk_args).await.with_context(|| fl!("error-disks-fetch"))?;
     } else if let Some(disks) = matches.values_of("DISKS") {
         disk_args.extend(disks.map(String::from).map(PathBuf::from).map(Box::from));
     }
@@ -104,13 +631,40 @@ async fn popsicle(
         return Err(anyhow!(fl!("error-no-disks-specified")));
     }
 
-    let mounts = mnt::get_submounts(Path::new("/"))
-        .with_context(|| fl!("error-reading-mounts"))?;
+    let mounts = mnt::get_submounts(Path::new("/")).with_context(|| fl!("error-reading-mounts"))?;
+
+    let unmount = if matches.is_present("unmount") {
+        Some(UnmountFlags { lazy: matches.is_present("lazy"), force: matches.is_present("force") })
+    } else {
+        None
+    };
+
+    let mut policy = SafetyPolicy {
+        allow_unsafe: matches.is_present("allow-unsafe"),
+        ..SafetyPolicy::default()
+    };
+    if let Some(max_size) = matches.value_of("max-size") {
+        let gib = max_size
+            .parse::<u64>()
+            .with_context(|| fl!("error-max-size-invalid", max_size = max_size))?;
+        let bytes = gib
+            .checked_mul(1 << 30)
+            .ok_or_else(|| anyhow!(fl!("error-max-size-invalid", max_size = max_size)))?;
+        policy.max_size = if gib == 0 { None } else { Some(bytes) };
+    }
+
+    let format = matches.value_of("format").unwrap_or("ron").parse::<Format>()?;
 
-    let disks =
-        popsicle::disks_from_args(disk_args.into_iter(), &mounts, matches.is_present("unmount"))
-            .await
-            .with_context(|| fl!("error-opening-disks"))?;
+    let disks = popsicle::disks_from_args(
+        disk_args.into_iter(),
+        &mounts,
+        unmount,
+        &policy,
+        &data_size,
+        &backend,
+    )
+    .await
+    .with_context(|| fl!("error-opening-disks"))?;
 
     let is_tty = atty::is(atty::Stream::Stdout);
 
@@ -118,7 +672,12 @@ async fn popsicle(
         epint!(
             (fl!("question", image_path = image_path)) "\n"
             for (path, _) in &disks {
-                " - " (path.display()) "\n"
+                " - "
+                match Device::new(&**path, &mounts) {
+                    Ok(device) => { (device_description(&device)) }
+                    Err(_) => { (path.display()) }
+                }
+                "\n"
             }
             (fl!("yn")) ": "
         );
@@ -134,6 +693,8 @@ async fn popsicle(
     }
 
     let check = matches.is_present("check");
+    let control_source = matches.value_of("control");
+    let mut control_socket = None;
 
     // If this is a TTY, display a progress bar. If not, display machine-readable info.
     if is_tty {
@@ -149,7 +710,11 @@ async fn popsicle(
                 ..message(&format!("W {}: ", disk_path.display()));
             });
 
-            task.subscribe(disk, disk_path, pb);
+            task.subscribe_path(disk_path.to_path_buf().into(), disk, disk_path, pb);
+        }
+
+        if let Some(source) = control_source {
+            control_socket = listen_for_controls(source, format, task.control())?;
         }
 
         thread::spawn(|| {
@@ -166,56 +731,86 @@ async fn popsicle(
         let mut task = Task::new(image, check);
 
         for (disk_path, disk) in disks {
-            let pb = MachineProgress::new(paths.len(), etx.clone());
-            paths.push(disk_path.clone());
-            task.subscribe(disk, disk_path, pb);
+            let path: std::path::PathBuf = disk_path.to_path_buf().into();
+            let pb = MessageProgress::new(path.clone(), etx.clone());
+            paths.push(path.clone());
+            task.subscribe_path(path, disk, (), pb);
         }
 
         drop(etx);
 
+        if let Some(source) = control_source {
+            control_socket = listen_for_controls(source, format, task.control())?;
+        }
+
         let task = async move {
             let buf = &mut [0u8; 64 * 1024];
             let _ = rtx.send(task.process(buf).await);
         };
 
-        join!(machine_output(erx, &paths, image_size), task);
+        join!(machine_output(erx, &paths, image_size, &digests, format), task);
+    }
+
+    if let Some(socket) = control_socket {
+        let _ = fs::remove_file(socket);
     }
 
     Ok(())
 }
 
-/// An event for creating a machine-readable output
-pub enum Event {
-    Message(usize, Box<str>),
-    Finished(usize),
-    Set(usize, u64),
-}
+/// Applies control messages from stdin when `source` is `-`, or else from every connection to
+/// a Unix socket created at `source`, whose path is returned so that it may be removed.
+///
+/// A socket at `source` which nothing listens on, as one left behind by a run which exited
+/// early, is replaced.
+fn listen_for_controls(
+    source: &str,
+    format: Format,
+    control: TaskControl,
+) -> anyhow::Result<Option<std::path::PathBuf>> {
+    if source == "-" {
+        thread::spawn(move || apply_controls(io::stdin(), format, &control));
+        return Ok(None);
+    }
 
-/// Tracks progress
-#[derive(new)]
-pub struct MachineProgress {
-    id: usize,
+    let is_socket = fs::symlink_metadata(source)
+        .map_or(false, |metadata| metadata.file_type().is_socket());
 
-    handle: mpsc::UnboundedSender<Event>,
-}
+    if is_socket && UnixStream::connect(source).is_err() {
+        let _ = fs::remove_file(source);
+    }
 
-impl Progress for MachineProgress {
-    type Device = Box<Path>;
+    let listener =
+        UnixListener::bind(source).with_context(|| fl!("error-control-socket", path = source))?;
 
-    fn message(&mut self, _path: &Box<Path>, kind: &str, message: &str) {
-        let _ = self.handle.unbounded_send(Event::Message(
-            self.id,
-            if message.is_empty() { kind.into() } else { [kind, " ", message].concat().into() },
-        ));
-    }
+    thread::spawn(move || {
+        for stream in listener.incoming().filter_map(Result::ok) {
+            let control = control.clone();
+            thread::spawn(move || apply_controls(stream, format, &control));
+        }
+    });
 
-    fn finish(&mut self) {
-        let _ = self.handle.unbounded_send(Event::Finished(self.id));
-    }
+    Ok(Some(source.into()))
+}
 
-    fn set(&mut self, written: u64) {
-        let _ = self.handle.unbounded_send(Event::Set(self.id, written));
-    }
+fn apply_controls<R: Read>(reader: R, format: Format, control: &TaskControl) {
+    executor::block_on(async move {
+        let mut stream = FramedRead::new(AllowStdIo::new(reader), ControlDecoder::new(format));
+        while let Some(message) = stream.next().await {
+            match message {
+                Ok(message) => {
+                    if !control.apply(&message) {
+                        if let Control::Cancel(device) = message {
+                            let device = device.display().to_string();
+                            eprintln!("{}", fl!("warning-control-unknown-device", device = device));
+                        }
+                    }
+                }
+                Err(codec::Error::Read(_)) => break,
+                Err(why) => eprintln!("popsicle: {}", why),
+            }
+        }
+    });
 }
 
 #[derive(new)]
@@ -241,33 +836,21 @@ impl Progress for InteractiveProgress {
 
 /// Writes a machine-friendly output, when this program is being piped into another.
 async fn machine_output(
-    mut rx: mpsc::UnboundedReceiver<Event>,
-    paths: &[Box<Path>],
+    mut rx: mpsc::UnboundedReceiver<Message>,
+    paths: &[std::path::PathBuf],
     image_size: u64,
+    digests: &Digests,
+    format: Format,
 ) {
-    let stdout = io::stdout();
-    let stdout = &mut stdout.lock();
-
-    let _ = wite!(
-        stdout,
-        "Size(" (image_size) ")\n"
-        for path in paths {
-            "Device(\"" (path.display()) "\")\n"
-        }
-    );
+    let mut stdout = FramedWrite::new(AllowStdIo::new(io::stdout()), PopsicleEncoder::new(format));
 
-    while let Some(event) = rx.next().await {
-        match event {
-            Event::Message(id, message) => {
-                let _ = witeln!(stdout, "Message(\"" (paths[id].display()) "\",\"" (message) "\")");
-            }
-            Event::Finished(id) => {
-                let _ = witeln!(stdout, "Finished(\"" (paths[id].display()) "\")");
-            }
-            Event::Set(id, written) => {
-                let _ = witeln!(stdout, "Set(\"" (paths[id].display()) "\"," (written) ")");
-            }
-        }
+    let preamble = MessageProgress::preamble(image_size, paths);
+    for message in preamble.into_iter().chain(MessageProgress::digests(digests)) {
+        let _ = stdout.send(message).await;
+    }
+
+    while let Some(message) = rx.next().await {
+        let _ = stdout.send(message).await;
     }
 }
 
@@ -1,64 +1,91 @@
 use crate::flash::{FlashError, FlashRequest};
-use crate::hash::hasher;
 
 use crossbeam_channel::{Receiver, Sender};
 use dbus_udisks2::{DiskDevice, Disks, UDisks2};
-use md5::Md5;
-use sha1::Sha1;
-use sha2::Sha256;
-use std::collections::HashMap;
-use std::io;
-use std::path::PathBuf;
-use std::sync::Arc;
+use popsicle::checksums;
+use popsicle::hash::{self, Algorithm, Checksum, DigestCache, HashCancel, HashError};
+use popsicle::signature::{self, Keys, SignatureError, Signer};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
 
 pub enum UiEvent {
     SetImageLabel(PathBuf),
+    /// The checksum file which was found next to an image, if there is one.
+    SetPublishedChecksum(PathBuf, Option<PublishedChecksum>),
     RefreshDevices(Box<[Arc<DiskDevice>]>),
-    SetHash(io::Result<String>),
+    SetHash(Result<String, HashError>),
+    /// The fraction of the image which has been hashed.
+    HashProgress(f64),
+    HashCancelled,
     Flash(JoinHandle<anyhow::Result<(anyhow::Result<()>, Vec<Result<(), FlashError>>)>>),
     Reset,
 }
 
+/// A checksum file which was published with an image, and who it was signed by.
+pub struct PublishedChecksum {
+    pub path: PathBuf,
+    /// The checksum of the strongest algorithm which the file lists for the image.
+    pub checksum: Checksum,
+    pub signature: Result<Option<Signer>, SignatureError>,
+}
+
 pub enum BackgroundEvent {
-    GenerateHash(PathBuf, &'static str),
+    /// Looks for a checksum file next to the image, and verifies its signature.
+    DiscoverChecksum(PathBuf),
+    GenerateHash(PathBuf, Algorithm),
+    CancelHash,
     Flash(FlashRequest),
     RefreshDevices,
 }
 
 pub fn background_thread(events_tx: Sender<UiEvent>, events_rx: Receiver<BackgroundEvent>) {
     thread::spawn(move || {
-        let mut hashed: HashMap<(PathBuf, &'static str), String> = HashMap::new();
+        // Digests are remembered across runs, until the image is changed.
+        let hashed = Arc::new(Mutex::new(DigestCache::open_default()));
+        let mut hashing: Option<HashCancel> = None;
 
         let mut device_paths = Vec::new();
 
         loop {
             match events_rx.recv() {
-                Ok(BackgroundEvent::GenerateHash(path, kind)) => {
-                    // Check if the cache already contains this hash, and return it.
-                    if let Some(result) = hashed.get(&(path.clone(), kind)) {
-                        let _ = events_tx.send(UiEvent::SetHash(Ok(result.clone())));
-                        continue;
+                Ok(BackgroundEvent::DiscoverChecksum(path)) => {
+                    // Verifying a signature waits for gpgv or minisign.
+                    let events_tx = events_tx.clone();
+                    thread::spawn(move || {
+                        let published = discover_checksum(&path);
+                        let _ = events_tx.send(UiEvent::SetPublishedChecksum(path, published));
+                    });
+                }
+                Ok(BackgroundEvent::GenerateHash(path, algorithm)) => {
+                    // A hash of the previous image is no longer wanted.
+                    if let Some(cancel) = hashing.take() {
+                        cancel.cancel();
                     }
 
-                    // Hash the file at the given path.
-                    let result = match kind {
-                        "MD5" => hasher::<Md5>(&path),
-                        "SHA256" => hasher::<Sha256>(&path),
-                        "SHA1" => hasher::<Sha1>(&path),
-                        _ => Err(io::Error::new(
-                            io::ErrorKind::InvalidInput,
-                            "hash kind not supported",
-                        )),
-                    };
-
-                    // If successful, cache the result.
-                    if let Ok(ref result) = result {
-                        hashed.insert((path.clone(), kind), result.clone());
+                    let cancel = HashCancel::default();
+                    hashing = Some(cancel.clone());
+
+                    let events_tx = events_tx.clone();
+                    let hashed = hashed.clone();
+                    thread::spawn(move || {
+                        let result = hash_image(&path, algorithm, &hashed, &cancel, &events_tx);
+
+                        // Send this result back to the main thread, unless it was superseded.
+                        match result {
+                            Err(HashError::Cancelled) => (),
+                            result => {
+                                let _ = events_tx.send(UiEvent::SetHash(result));
+                            }
+                        }
+                    });
+                }
+                Ok(BackgroundEvent::CancelHash) => {
+                    if let Some(cancel) = hashing.take() {
+                        cancel.cancel();
                     }
 
-                    // Send this result back to the main thread.
-                    let _ = events_tx.send(UiEvent::SetHash(result));
+                    let _ = events_tx.send(UiEvent::HashCancelled);
                 }
                 Ok(BackgroundEvent::RefreshDevices) => {
                     // Fetch the current list of USB devices from popsicle.
@@ -88,6 +115,38 @@ pub fn background_thread(events_tx: Sender<UiEvent>, events_rx: Receiver<Backgro
     });
 }
 
+/// Looks for a checksum file next to the image, and checks its signature against the keys in
+/// the user's configuration.
+fn discover_checksum(image: &Path) -> Option<PublishedChecksum> {
+    let discovered = checksums::discover(image)?;
+    let signature = signature::check(&discovered.path, &Keys::from_config());
+    let checksum = discovered.strongest().clone();
+    Some(PublishedChecksum { path: discovered.path, checksum, signature })
+}
+
+/// Hashes the image, unless its digest is cached, reporting each percent of progress to the
+/// main thread.
+fn hash_image(
+    path: &Path,
+    algorithm: Algorithm,
+    cache: &Mutex<DigestCache>,
+    cancel: &HashCancel,
+    events_tx: &Sender<UiEvent>,
+) -> Result<String, HashError> {
+    let size = path.metadata().map_or(0, |metadata| metadata.len());
+    let mut last_percent = 0;
+
+    let digests = hash::hash_file_cached(path, &[algorithm], cache, cancel, |hashed| {
+        let percent = if size == 0 { 100 } else { hashed * 100 / size };
+        if percent != last_percent {
+            last_percent = percent;
+            let _ = events_tx.send(UiEvent::HashProgress(percent as f64 / 100.0));
+        }
+    })?;
+
+    Ok(digests.get(algorithm).expect("digest was computed").to_owned())
+}
+
 fn refresh_devices() -> anyhow::Result<Box<[Arc<DiskDevice>]>> {
     let udisks = UDisks2::new()?;
     let devices = Disks::new(&udisks).devices;
@@ -1,7 +1,8 @@
-use crate::app::events::{BackgroundEvent, UiEvent};
+use crate::app::events::{BackgroundEvent, PublishedChecksum, UiEvent};
 use crate::app::state::State;
 use crate::app::widgets::OpenDialog;
 use crate::app::{App, GtkUi};
+use crate::fl;
 use crate::misc;
 use gtk::prelude::*;
 use std::path::{Path, PathBuf};
@@ -9,11 +10,9 @@ use std::path::{Path, PathBuf};
 impl App {
     pub fn connect_image_chooser(&self) {
         let state = self.state.clone();
-        let ui = self.ui.clone();
         self.ui.content.image_view.chooser.connect_clicked(move |_| {
             if let Some(path) = OpenDialog::new(None).run() {
                 let _ = state.ui_event_tx.send(UiEvent::SetImageLabel(path));
-                set_hash_widget(&state, &ui);
             }
         });
     }
@@ -24,6 +23,11 @@ impl App {
         self.ui.content.image_view.check.connect_clicked(move |_| {
             set_hash_widget(&state, &ui);
         });
+
+        let state = self.state.clone();
+        self.ui.content.image_view.hash_cancel.connect_clicked(move |_| {
+            let _ = state.back_event_tx.send(BackgroundEvent::CancelHash);
+        });
     }
 
     pub fn connect_image_drag_and_drop(&self) {
@@ -39,7 +43,6 @@ impl App {
                         && path.exists()
                     {
                         let _ = state.ui_event_tx.send(UiEvent::SetImageLabel(path.to_path_buf()));
-                        set_hash_widget(&state, &ui);
                     }
                 }
             }
@@ -47,19 +50,45 @@ impl App {
     }
 }
 
-fn set_hash_widget(state: &State, ui: &GtkUi) {
-    let hash = &ui.content.image_view.hash;
-
+pub(super) fn set_hash_widget(state: &State, ui: &GtkUi) {
     let path = state.image_path.borrow();
-    let kind = match hash.active() {
-        Some(1) => "SHA256",
-        Some(2) => "SHA1",
-        Some(3) => "MD5",
-        _ => return,
+    let algorithm = match ui.content.image_view.hash_algorithm() {
+        Some(algorithm) => algorithm,
+        None => return,
     };
 
     ui.content.image_view.chooser_container.set_visible_child_name("checksum");
     ui.content.image_view.set_hash_sensitive(false);
+    ui.content.image_view.set_hash_progress(0.0);
 
-    let _ = state.back_event_tx.send(BackgroundEvent::GenerateHash(PathBuf::from(&*path), kind));
+    let _ =
+        state.back_event_tx.send(BackgroundEvent::GenerateHash(PathBuf::from(&*path), algorithm));
+}
+
+/// Fills in the checksum from a checksum file that was published with the image, unless the
+/// file's signature is not valid, or it is not signed by a configured key, and shows who signed
+/// it.
+pub(super) fn expect_published_checksum(ui: &GtkUi, published: PublishedChecksum) {
+    let image_view = &ui.content.image_view;
+    let file = published.path.display().to_string();
+    let trusted = match published.signature {
+        Ok(Some(signer)) => {
+            let message = fl!("checksums-signed", file = file, signer = signer.to_string());
+            image_view.set_signature(Some(&message), false);
+            true
+        }
+        Ok(None) => {
+            image_view.set_signature(Some(&fl!("checksums-unsigned", file = file)), true);
+            true
+        }
+        Err(why) => {
+            image_view
+                .set_signature(Some(&fl!("checksums-untrusted", why = why.to_string())), true);
+            !why.is_rejected()
+        }
+    };
+
+    if trusted {
+        image_view.set_expected_hash(&published.checksum);
+    }
 }
@@ -3,6 +3,7 @@ mod images;
 
 use crate::app::events::{BackgroundEvent, UiEvent};
 use crate::app::state::ActiveView;
+use crate::app::widgets;
 use crate::app::App;
 use crate::fl;
 use crate::flash::{FlashRequest, FlashStatus, FlashTask};
@@ -10,7 +11,8 @@ use crate::misc;
 use atomic::Atomic;
 use crossbeam_channel::TryRecvError;
 use gtk::{self, prelude::*};
-use iso9660::ISO9660;
+use popsicle::inspect::ImageReport;
+use popsicle::DEFAULT_MAX_SIZE;
 use std::fmt::Write;
 use std::fs::File;
 use std::sync::atomic::Ordering;
@@ -45,7 +47,25 @@ impl App {
         self.ui.header.connect_next(move || {
             let next = match state.active_view.get() {
                 ActiveView::Images => ActiveView::Devices,
-                ActiveView::Devices => ActiveView::Flashing,
+                ActiveView::Devices => {
+                    // The helper refuses devices above its size limit, unless it is lifted.
+                    let large = {
+                        let devices = state.available_devices.borrow();
+                        ui.content
+                            .devices_view
+                            .is_active_ids()
+                            .any(|id| devices[id].parent.size > DEFAULT_MAX_SIZE)
+                    };
+
+                    let limit = bytesize::to_string(DEFAULT_MAX_SIZE, true);
+                    let warning = fl!("large-devices", limit = limit);
+                    if large && !widgets::confirm(&warning, &fl!("large-devices-flash")) {
+                        return;
+                    }
+
+                    state.no_size_limit.set(large);
+                    ActiveView::Flashing
+                }
                 _ => {
                     gtk::main_quit();
                     return;
@@ -79,28 +99,58 @@ impl App {
 
                     ui.content.image_view.chooser_container.set_visible_child_name("chooser");
                 }
+                Ok(UiEvent::HashProgress(fraction)) => {
+                    ui.content.image_view.set_hash_progress(fraction);
+                }
+                Ok(UiEvent::HashCancelled) => {
+                    ui.content.image_view.set_hash_sensitive(true);
+                    ui.content.image_view.chooser_container.set_visible_child_name("chooser");
+                }
                 Ok(UiEvent::SetImageLabel(path)) => {
                     if let Ok(file) = File::open(&path) {
                         let image_size = file.metadata().ok().map_or(0, |m| m.len());
 
-                        let warning = if is_windows_iso(&file) {
-                            Some(fl!("win-isos-not-supported"))
-                        } else {
-                            None
+                        let report = ImageReport::new(&path).ok();
+
+                        let warning = match report {
+                            Some(ref report) if report.is_windows() => {
+                                Some(fl!("win-isos-not-supported"))
+                            }
+                            Some(ref report) if report.is_optical_only() => {
+                                Some(fl!("iso-not-hybrid"))
+                            }
+                            _ => None,
                         };
 
                         ui.content.image_view.set_image(
                             &path,
                             image_size,
+                            report.as_ref(),
                             warning.as_ref().map(|x| x.as_str()),
                         );
                         ui.content.image_view.set_hash_sensitive(true);
                         ui.header.next.set_sensitive(true);
 
+                        // The image is hashed once its checksum file has been looked for.
+                        ui.content.image_view.clear_expected_hash();
+                        ui.content.image_view.set_signature(None, false);
+                        let discover = BackgroundEvent::DiscoverChecksum(path.clone());
+                        let _ = state.back_event_tx.send(discover);
+
                         state.image_size.store(image_size, Ordering::SeqCst);
                         *state.image_path.borrow_mut() = path;
                     }
                 }
+                Ok(UiEvent::SetPublishedChecksum(path, published)) => {
+                    // Another image may have been chosen while this one's was looked for.
+                    if *state.image_path.borrow() == path {
+                        if let Some(published) = published {
+                            images::expect_published_checksum(&ui, published);
+                        }
+
+                        images::set_hash_widget(&state, &ui);
+                    }
+                }
                 Ok(UiEvent::RefreshDevices(devices)) => {
                     let size = state.image_size.load(Ordering::SeqCst);
                     ui.content.devices_view.refresh(&devices, size);
@@ -133,7 +183,7 @@ impl App {
                 }
                 ActiveView::Flashing => match state.image.borrow_mut().take() {
                     // When the flashing view is active, and an image has not started flashing.
-                    Some(image) => {
+                    Some(_image) => {
                         let summary_grid = &ui.content.flash_view.progress_list;
                         summary_grid.foreach(|w| summary_grid.remove(w));
                         let mut destinations = Vec::new();
@@ -182,8 +232,9 @@ impl App {
 
                         let _ =
                             state.back_event_tx.send(BackgroundEvent::Flash(FlashRequest::new(
-                                image,
+                                state.image_path.borrow().clone(),
                                 destinations,
+                                state.no_size_limit.get(),
                                 flash_status.clone(),
                                 progress.clone(),
                                 finished.clone(),
@@ -354,10 +405,3 @@ impl App {
         });
     }
 }
-
-fn is_windows_iso(file: &File) -> bool {
-    if let Ok(fs) = ISO9660::new(file) {
-        return fs.publisher_identifier() == "MICROSOFT CORPORATION";
-    }
-    false
-}
@@ -4,8 +4,24 @@ use bytesize;
 use gtk::prelude::*;
 use gtk::*;
 use pango::{AttrColor, AttrList, EllipsizeMode};
+use popsicle::checksums;
+use popsicle::hash::{Algorithm, Checksum};
+use popsicle::inspect::ImageReport;
 use std::path::Path;
 
+/// The algorithms which may be selected, in the order of the hash combo box, after "None".
+const HASH_ALGORITHMS: [Algorithm; 9] = [
+    Algorithm::Sha256,
+    Algorithm::Sha1,
+    Algorithm::Md5,
+    Algorithm::Sha512,
+    Algorithm::Sha384,
+    Algorithm::Sha3_256,
+    Algorithm::Sha3_512,
+    Algorithm::Blake2b,
+    Algorithm::Blake3,
+];
+
 pub struct ImageView {
     pub view: View,
     pub check: Button,
@@ -14,6 +30,9 @@ pub struct ImageView {
     pub image_path: Label,
     pub hash: ComboBoxText,
     pub hash_label: Entry,
+    pub hash_progress: ProgressBar,
+    pub hash_cancel: Button,
+    pub signature: Label,
 }
 
 impl ImageView {
@@ -39,30 +58,42 @@ impl ImageView {
             ..pack_start(&image_path, false, false, 0);
         };
 
-        let spinner = Spinner::new();
-        spinner.start();
-
-        let spinner_label = cascade! {
+        let hash_progress_label = cascade! {
             Label::new(Some(&fl!("generating-checksum")));
             ..style_context().add_class("bold");
         };
 
-        let spinner_box = cascade! {
+        let hash_progress = cascade! {
+            ProgressBar::new();
+            ..set_margin_top(6);
+            ..set_margin_bottom(6);
+        };
+
+        let hash_cancel = cascade! {
+            Button::with_label(&fl!("cancel"));
+            ..set_halign(Align::Center);
+        };
+
+        let progress_box = cascade! {
             Box::new(Orientation::Vertical, 0);
-            ..pack_start(&spinner, false, false, 0);
-            ..pack_start(&spinner_label, false, false, 0);
+            ..pack_start(&hash_progress_label, false, false, 0);
+            ..pack_start(&hash_progress, false, false, 0);
+            ..pack_start(&hash_cancel, false, false, 0);
+            ..set_margin_start(48);
+            ..set_margin_end(48);
         };
 
         let hash = cascade! {
             ComboBoxText::new();
             ..append_text(&fl!("none"));
-            ..append_text("SHA256");
-            ..append_text("SHA1");
-            ..append_text("MD5");
             ..set_active(Some(0));
             ..set_sensitive(false);
         };
 
+        for algorithm in &HASH_ALGORITHMS {
+            hash.append_text(algorithm.name());
+        }
+
         let hash_label = cascade! {
             Entry::new();
             ..set_sensitive(false);
@@ -79,15 +110,32 @@ impl ImageView {
             ..set_sensitive(false);
         };
 
-        let hash_label_clone = hash_label.clone();
         let check_clone = check.clone();
         hash.connect_changed(move |combo_box| {
-            let sensitive = match combo_box.active_text() {
-                Some(text) if text.as_str() != "None" => true,
-                _ => false,
+            check_clone.set_sensitive(selected_algorithm(combo_box).is_some());
+        });
+
+        // Select the algorithm of a pasted checksum, which may also be a line of a checksum
+        // file, and keep only its digest.
+        let hash_clone = hash.clone();
+        hash_label.connect_changed(move |entry| {
+            let text = entry.text();
+            let checksum = match checksums::detect(&text) {
+                Some(checksum) => checksum,
+                None => return,
             };
-            hash_label_clone.set_sensitive(sensitive);
-            check_clone.set_sensitive(sensitive);
+
+            // Digests of several algorithms share each length, so a bare digest is left to the
+            // algorithm that was selected, if it has the same length.
+            let bare = text.trim().eq_ignore_ascii_case(checksum.digest());
+            let selected = selected_algorithm(&hash_clone).map(Algorithm::digest_len);
+            if !bare || selected != Some(checksum.algorithm().digest_len()) {
+                select_algorithm(&hash_clone, checksum.algorithm());
+            }
+
+            if !bare {
+                entry.set_text(checksum.digest());
+            }
         });
 
         let combo_container = cascade! {
@@ -105,10 +153,18 @@ impl ImageView {
             ..set_border_width(6);
         };
 
+        let signature = cascade! {
+            Label::new(None);
+            ..set_use_markup(true);
+            ..set_ellipsize(EllipsizeMode::Middle);
+            ..set_margin_start(6);
+            ..set_margin_end(6);
+        };
+
         let chooser_container = cascade! {
             Stack::new();
             ..add_named(&button_box, "chooser");
-            ..add_named(&spinner_box, "checksum");
+            ..add_named(&progress_box, "checksum");
             ..set_visible_child_name("chooser");
             ..set_margin_top(12);
             ..set_margin_bottom(24);
@@ -121,14 +177,67 @@ impl ImageView {
             |right_panel| {
                 right_panel.pack_start(&chooser_container, true, false, 0);
                 right_panel.pack_start(&hash_container, false, false, 0);
+                right_panel.pack_start(&signature, false, false, 0);
             },
         );
 
-        ImageView { view, check, chooser_container, chooser, image_path, hash, hash_label }
+        ImageView {
+            view,
+            check,
+            chooser_container,
+            chooser,
+            image_path,
+            hash,
+            hash_label,
+            hash_progress,
+            hash_cancel,
+            signature,
+        }
     }
 
     pub fn set_hash_sensitive(&self, sensitive: bool) {
         self.hash.set_sensitive(sensitive);
+        self.hash_label.set_sensitive(sensitive);
+    }
+
+    /// The algorithm which is selected in the hash combo box.
+    pub fn hash_algorithm(&self) -> Option<Algorithm> {
+        selected_algorithm(&self.hash)
+    }
+
+    /// Selects the checksum's algorithm, and fills in the digest which the image is expected
+    /// to have, such as one from a checksum file which was found next to the image.
+    pub fn set_expected_hash(&self, checksum: &Checksum) {
+        select_algorithm(&self.hash, checksum.algorithm());
+        self.hash_label.set_attributes(&AttrList::new());
+        self.hash_label.set_text(checksum.digest());
+    }
+
+    /// Forgets the digest which the previous image was expected to have, and its algorithm,
+    /// so that a new image is not checked against it.
+    pub fn clear_expected_hash(&self) {
+        self.hash.set_active(Some(0));
+        self.hash_label.set_attributes(&AttrList::new());
+        self.hash_label.set_text("");
+    }
+
+    /// Describes who signed the checksum file which the expected hash came from, or warns
+    /// that it could not be trusted.
+    pub fn set_signature(&self, message: Option<&str>, warning: bool) {
+        let message = glib::markup_escape_text(message.unwrap_or(""));
+        if warning {
+            let subject = fl!("warning");
+            self.signature.set_markup(&format!(
+                "<span foreground='red'><b>{}</b> {}</span>",
+                subject, message
+            ));
+        } else {
+            self.signature.set_markup(&message);
+        }
+    }
+
+    pub fn set_hash_progress(&self, fraction: f64) {
+        self.hash_progress.set_fraction(fraction);
     }
 
     pub fn set_hash(&self, hash: &str) {
@@ -147,13 +256,43 @@ impl ImageView {
         }
     }
 
-    pub fn set_image(&self, path: &Path, size: u64, warning: Option<&str>) {
+    /// Describes the chosen image, with the system it contains and how it boots, if it was
+    /// inspected.
+    pub fn set_image(
+        &self,
+        path: &Path,
+        size: u64,
+        report: Option<&ImageReport>,
+        warning: Option<&str>,
+    ) {
         let size_str = bytesize::to_string(size, true);
         let mut label: String = match path.file_name() {
             Some(name) => format!("<b>{}</b>\n{}", name.to_string_lossy(), size_str),
             None => format!("<b>{}</b>", fl!("cannot-select-directories")),
         };
 
+        if let Some(report) = report {
+            let mut details = report.container.to_string();
+            if let Some(table) = report.partition_table {
+                let table = table.to_string();
+                details = fl!("image-partition-table", details = details, table = table);
+            }
+
+            match report.boot {
+                Some(boot) if boot.bios || boot.uefi => {
+                    details = fl!("image-boot", details = details, boot = boot.to_string());
+                }
+                Some(_) => details = fl!("image-not-bootable", details = details),
+                None => (),
+            }
+
+            if let Some(ref os) = report.os {
+                label += &format!("\n{}", glib::markup_escape_text(&os.to_string()));
+            }
+
+            label += &format!("\n{}", glib::markup_escape_text(&details));
+        }
+
         if let Some(warning) = warning {
             let subject = fl!("warning");
             label += &format!("\n<span foreground='red'><b>{}</b>: {}</span>", subject, warning);
@@ -162,3 +301,14 @@ impl ImageView {
         self.image_path.set_markup(&label);
     }
 }
+
+fn selected_algorithm(hash: &ComboBoxText) -> Option<Algorithm> {
+    let index = hash.active()?.checked_sub(1)?;
+    HASH_ALGORITHMS.get(index as usize).copied()
+}
+
+fn select_algorithm(hash: &ComboBoxText, algorithm: Algorithm) {
+    if let Some(index) = HASH_ALGORITHMS.iter().position(|&a| a == algorithm) {
+        hash.set_active(Some(index as u32 + 1));
+    }
+}
@@ -41,3 +41,21 @@ impl Drop for OpenDialog {
         self.0.destroy();
     }
 }
+
+/// Warns with `message`, returning whether the user chose to continue with `action`.
+pub fn confirm(message: &str, action: &str) -> bool {
+    let dialog = MessageDialog::new(
+        None::<&Window>,
+        DialogFlags::MODAL,
+        MessageType::Warning,
+        ButtonsType::None,
+        message,
+    );
+
+    dialog.add_button(&fl!("cancel"), ResponseType::Cancel);
+    dialog.add_button(action, ResponseType::Accept);
+    let response = dialog.run();
+    dialog.close();
+
+    response == ResponseType::Accept
+}
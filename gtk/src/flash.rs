@@ -1,21 +1,44 @@
+use anyhow::{anyhow, Context};
 use atomic::Atomic;
-use dbus::arg::{OwnedFd, RefArg, Variant};
-use dbus::blocking::{Connection, Proxy};
 use dbus_udisks2::DiskDevice;
-use futures::executor;
-use libc;
-use popsicle::{Progress, Task};
-use std::cell::Cell;
-use std::collections::HashMap;
+use futures::{executor, io::AllowStdIo, prelude::*};
+use futures_codec::{FramedRead, FramedWrite};
+use popsicle::codec::{Control, ControlEncoder, Decoded, PopsicleDecoder};
+use popsicle::{DeviceStatus, ProgressModel};
 use std::fmt::{self, Debug, Display, Formatter};
-use std::fs::File;
-use std::os::unix::io::FromRawFd;
-use std::str;
-use std::sync::atomic::Ordering;
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::{ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use std::thread;
 use std::time::Duration;
 
-type UDisksOptions = HashMap<&'static str, Variant<Box<dyn RefArg>>>;
+/// Where the privileged helper is installed, which must match the path in its polkit policy.
+fn helper_path() -> &'static str {
+    option_env!("POPSICLE_HELPER").unwrap_or("/usr/libexec/popsicle-helper")
+}
+
+/// The last error which the helper reported, along with its causes, such as why a device was
+/// refused, or else the first line which was written by `pkexec`.
+fn helper_error(stderr: &str) -> Option<String> {
+    let lines: Vec<&str> = stderr.lines().filter(|line| !line.trim().is_empty()).collect();
+    let start = match lines.iter().rposition(|line| line.starts_with("popsicle-helper: ")) {
+        Some(start) => start,
+        None => return lines.first().map(|line| (*line).to_owned()),
+    };
+
+    let mut why = lines[start].trim_start_matches("popsicle-helper: ").to_owned();
+    for cause in
+        lines[start + 1..].iter().filter_map(|line| line.trim().strip_prefix("caused by: "))
+    {
+        why.push_str(": ");
+        why.push_str(cause);
+    }
+
+    Some(why)
+}
 
 #[derive(Clone, Copy, PartialEq)]
 pub enum FlashStatus {
@@ -25,8 +48,10 @@ pub enum FlashStatus {
 }
 
 pub struct FlashRequest {
-    source: Option<File>,
+    image: PathBuf,
     destinations: Vec<Arc<DiskDevice>>,
+    /// Lifts the helper's limit on the size of devices.
+    no_size_limit: bool,
     status: Arc<Atomic<FlashStatus>>,
     progress: Arc<Vec<Atomic<u64>>>,
     finished: Arc<Vec<Atomic<bool>>>,
@@ -38,12 +63,6 @@ pub struct FlashTask {
     pub finished: Arc<Vec<Atomic<bool>>>,
 }
 
-struct FlashProgress<'a> {
-    request: &'a FlashRequest,
-    id: usize,
-    errors: &'a [Cell<Result<(), FlashError>>],
-}
-
 #[derive(Clone, Debug)]
 pub struct FlashError {
     kind: String,
@@ -58,39 +77,22 @@ impl Display for FlashError {
 
 impl std::error::Error for FlashError {}
 
-impl<'a> Progress for FlashProgress<'a> {
-    type Device = ();
-
-    fn message(&mut self, _device: &(), kind: &str, message: &str) {
-        self.errors[self.id]
-            .set(Err(FlashError { kind: kind.to_string(), message: message.to_string() }));
-    }
-
-    fn finish(&mut self) {
-        self.request.finished[self.id].store(true, Ordering::SeqCst);
-    }
-
-    fn set(&mut self, value: u64) {
-        self.request.progress[self.id].store(value, Ordering::SeqCst);
-    }
-}
-
 impl FlashRequest {
     pub fn new(
-        source: File,
+        image: PathBuf,
         destinations: Vec<Arc<DiskDevice>>,
+        no_size_limit: bool,
         status: Arc<Atomic<FlashStatus>>,
         progress: Arc<Vec<Atomic<u64>>>,
         finished: Arc<Vec<Atomic<bool>>>,
     ) -> FlashRequest {
-        FlashRequest { source: Some(source), destinations, status, progress, finished }
+        FlashRequest { image, destinations, no_size_limit, status, progress, finished }
     }
 
-    pub fn write(mut self) -> anyhow::Result<(anyhow::Result<()>, Vec<Result<(), FlashError>>)> {
+    pub fn write(self) -> anyhow::Result<(anyhow::Result<()>, Vec<Result<(), FlashError>>)> {
         self.status.store(FlashStatus::Active, Ordering::SeqCst);
 
-        let source = self.source.take().unwrap();
-        let res = self.write_inner(source);
+        let res = self.write_inner();
 
         for atomic in self.finished.iter() {
             atomic.store(true, Ordering::SeqCst);
@@ -101,76 +103,128 @@ impl FlashRequest {
         res
     }
 
-    fn write_inner<'a>(
-        &'a self,
-        source: File,
-    ) -> anyhow::Result<(anyhow::Result<()>, Vec<Result<(), FlashError>>)> {
-        // Unmount the devices beforehand.
-        for device in &self.destinations {
-            let _ = udisks_unmount(&device.parent.path);
-            for partition in &device.partitions {
-                let _ = udisks_unmount(&partition.path);
-            }
-        }
-
-        // Then open them for writing to.
-        let mut files = Vec::new();
-        for device in &self.destinations {
-            let file = udisks_open(&device.parent.path)?;
-            files.push(file);
+    /// Flashes the image with the privileged helper, following its progress from its output.
+    fn write_inner(&self) -> anyhow::Result<(anyhow::Result<()>, Vec<Result<(), FlashError>>)> {
+        let paths: Vec<PathBuf> = self
+            .destinations
+            .iter()
+            .map(|device| {
+                fs::canonicalize(&device.parent.device)
+                    .unwrap_or_else(|_| device.parent.device.clone())
+            })
+            .collect();
+
+        let mut command = Command::new("pkexec");
+        command.arg(helper_path()).arg("flash");
+        if self.no_size_limit {
+            command.arg("--max-size").arg("0");
         }
 
-        let mut errors = vec![Ok(()); files.len()];
-        let errors_cells = Cell::from_mut(&mut errors as &mut [_]).as_slice_of_cells();
+        let mut helper = command
+            .arg(&self.image)
+            .args(&paths)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("failed to launch the flashing helper with pkexec")?;
+
+        let stdin = helper.stdin.take().expect("helper stdin is piped");
+        let stdout = helper.stdout.take().expect("helper stdout is piped");
+        let mut stderr = helper.stderr.take().expect("helper stderr is piped");
+
+        let stderr = thread::spawn(move || {
+            let mut output = String::new();
+            let _ = stderr.read_to_string(&mut output);
+            output
+        });
+
+        let done = Arc::new(AtomicBool::new(false));
+        let canceller = self.cancel_when_killed(stdin, done.clone());
+
+        let model = executor::block_on(self.follow(stdout, &paths));
+
+        let exit = helper.wait().context("failed to wait for the flashing helper")?;
+        done.store(true, Ordering::SeqCst);
+        let _ = canceller.join();
+        let stderr = stderr.join().unwrap_or_default();
+
+        let errors = paths
+            .iter()
+            .map(|path| match model.device(path).map(|device| &device.status) {
+                Some(DeviceStatus::Succeeded) => Ok(()),
+                Some(DeviceStatus::Failed(why)) => {
+                    Err(FlashError { kind: "E".into(), message: why.clone() })
+                }
+                Some(DeviceStatus::Cancelled) => {
+                    Err(FlashError { kind: "C".into(), message: "cancelled".into() })
+                }
+                Some(DeviceStatus::Active) | None => Err(FlashError {
+                    kind: "E".into(),
+                    message: "the flashing helper exited before finishing".into(),
+                }),
+            })
+            .collect();
+
+        let result = if exit.success() {
+            Ok(())
+        } else {
+            match helper_error(&stderr) {
+                Some(why) => Err(anyhow!("{}", why)),
+                None => Err(anyhow!("the flashing helper failed: {}", exit)),
+            }
+        };
 
-        // How many bytes to write at a given time.
-        let mut bucket = [0u8; 64 * 1024];
+        Ok((result, errors))
+    }
 
-        let mut task = Task::new(source.into(), false);
-        for (i, file) in files.into_iter().enumerate() {
-            let progress = FlashProgress { request: &self, errors: errors_cells, id: i };
-            task.subscribe(file.into(), (), progress);
+    /// Updates the progress of each destination from the messages written by the helper.
+    async fn follow(&self, stdout: ChildStdout, paths: &[PathBuf]) -> ProgressModel {
+        let decoder = PopsicleDecoder::default().lenient();
+        let mut stream = FramedRead::new(AllowStdIo::new(stdout), decoder);
+        let mut model = ProgressModel::default();
+
+        while let Some(item) = stream.next().await {
+            match item {
+                Ok(Decoded::Message(message)) => {
+                    if model.apply(&message).is_none() {
+                        continue;
+                    }
+
+                    for (id, path) in paths.iter().enumerate() {
+                        if let Some(device) = model.device(path) {
+                            self.progress[id].store(device.written, Ordering::SeqCst);
+                            self.finished[id].store(device.is_finished(), Ordering::SeqCst);
+                        }
+                    }
+                }
+                Ok(Decoded::Malformed(why)) => eprintln!("popsicle-gtk: {}", why),
+                Err(_) => break,
+            }
         }
 
-        let res = executor::block_on(task.process(&mut bucket));
-
-        Ok((res, errors))
+        model
     }
-}
-
-fn udisks_unmount(dbus_path: &str) -> anyhow::Result<()> {
-    let connection = Connection::new_system()?;
-
-    let dbus_path = ::dbus::strings::Path::new(dbus_path).map_err(anyhow::Error::msg)?;
-
-    let proxy = Proxy::new("org.freedesktop.UDisks2", dbus_path, Duration::new(25, 0), &connection);
-
-    let mut options = UDisksOptions::new();
-    options.insert("force", Variant(Box::new(true)));
-    let res: Result<(), _> =
-        proxy.method_call("org.freedesktop.UDisks2.Filesystem", "Unmount", (options,));
 
-    if let Err(err) = res {
-        if err.name() != Some("org.freedesktop.UDisks2.Error.NotMounted") {
-            return Err(anyhow::Error::new(err));
-        }
+    /// Asks the helper to cancel every device once the flash is being killed.
+    fn cancel_when_killed(
+        &self,
+        stdin: ChildStdin,
+        done: Arc<AtomicBool>,
+    ) -> thread::JoinHandle<()> {
+        let status = self.status.clone();
+
+        thread::spawn(move || {
+            while !done.load(Ordering::SeqCst) {
+                if status.load(Ordering::SeqCst) == FlashStatus::Killing {
+                    let mut stdin =
+                        FramedWrite::new(AllowStdIo::new(stdin), ControlEncoder::default());
+                    let _ = executor::block_on(stdin.send(Control::CancelAll));
+                    return;
+                }
+
+                thread::sleep(Duration::from_millis(100));
+            }
+        })
     }
-
-    Ok(())
-}
-
-fn udisks_open(dbus_path: &str) -> anyhow::Result<File> {
-    let connection = Connection::new_system()?;
-
-    let dbus_path = ::dbus::strings::Path::new(dbus_path).map_err(anyhow::Error::msg)?;
-
-    let proxy =
-        Proxy::new("org.freedesktop.UDisks2", &dbus_path, Duration::new(25, 0), &connection);
-
-    let mut options = UDisksOptions::new();
-    options.insert("flags", Variant(Box::new(libc::O_SYNC)));
-    let res: (OwnedFd,) =
-        proxy.method_call("org.freedesktop.UDisks2.Block", "OpenDevice", ("rw", options))?;
-
-    Ok(unsafe { File::from_raw_fd(res.0.into_fd()) })
 }
@@ -6,7 +6,6 @@ extern crate cascade;
 mod app;
 mod flash;
 mod gresource;
-mod hash;
 mod localize;
 mod misc;
 
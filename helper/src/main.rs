@@ -0,0 +1,404 @@
+//! Privileged helper which flashes images on behalf of the unprivileged GTK front end.
+//!
+//! The helper is launched through `pkexec`, and accepts a single command:
+//!
+//! ```text
+//! popsicle-helper flash [--check] [--max-size GIB] IMAGE DEVICE...
+//! ```
+//!
+//! The image is opened with the identity of the user who invoked `pkexec`, so that the helper
+//! cannot be used to read files which that user could not. Each device must be a whole-disk
+//! block device in `/dev` which passes the library's safety checks. `--max-size` replaces their
+//! limit on the size of devices, in GiB, with 0 for no limit. Progress is written to
+//! stdout with the `codec` IPC protocol, and `Control` messages are read from stdin.
+
+#[macro_use]
+extern crate anyhow;
+
+use anyhow::Context;
+use async_std::{fs::File, path::PathBuf as AsyncPathBuf};
+use futures::{channel::mpsc, executor, io::AllowStdIo, join, prelude::*};
+use futures_codec::{FramedRead, FramedWrite};
+use popsicle::{
+    codec::{self, ControlDecoder, PopsicleEncoder},
+    mnt, Backend, ImageSize, MessageProgress, SafetyPolicy, Task, TaskControl, UnmountFlags,
+};
+use std::{
+    env,
+    ffi::{CStr, CString, OsString},
+    fs,
+    io::{self, Seek, SeekFrom},
+    mem,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    process, ptr, thread,
+};
+
+const USAGE: &str = "usage: popsicle-helper flash [--check] [--max-size GIB] IMAGE DEVICE...";
+
+/// The most devices which may be flashed at once.
+const MAX_DEVICES: usize = 32;
+
+/// A validated request to flash an image.
+#[derive(Debug)]
+struct Flash {
+    image: PathBuf,
+    devices: Vec<PathBuf>,
+    check: bool,
+    policy: SafetyPolicy,
+}
+
+fn main() {
+    let flash = match parse(env::args_os().skip(1)) {
+        Ok(flash) => flash,
+        Err(why) => {
+            eprintln!("popsicle-helper: {}\n{}", why, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(why) = executor::block_on(run(flash)) {
+        eprintln!("popsicle-helper: {}", why);
+        for source in why.chain().skip(1) {
+            eprintln!("    caused by: {}", source);
+        }
+
+        process::exit(1);
+    }
+}
+
+/// Accepts only the `flash` command, with absolute paths to an image and to devices in `/dev`,
+/// and a limit on the size of the devices.
+fn parse<I: Iterator<Item = OsString>>(mut args: I) -> Result<Flash, String> {
+    match args.next() {
+        Some(ref command) if command == "flash" => (),
+        Some(command) => return Err(format!("unknown command '{}'", command.to_string_lossy())),
+        None => return Err("no command given".into()),
+    }
+
+    let mut check = false;
+    let mut policy = SafetyPolicy::default();
+    let mut paths = Vec::new();
+
+    while let Some(arg) = args.next() {
+        if arg == "--check" {
+            check = true;
+        } else if arg == "--max-size" {
+            let size = args.next().ok_or("no size given to '--max-size'")?;
+            let invalid = || format!("invalid maximum size '{}'", size.to_string_lossy());
+            let gib = size.to_str().and_then(|gib| gib.parse::<u64>().ok()).ok_or_else(invalid)?;
+            let bytes = gib.checked_mul(1 << 30).ok_or_else(invalid)?;
+            policy.max_size = if gib == 0 { None } else { Some(bytes) };
+        } else if arg.as_bytes().starts_with(b"-") {
+            return Err(format!("unknown option '{}'", arg.to_string_lossy()));
+        } else {
+            paths.push(PathBuf::from(arg));
+        }
+    }
+
+    let mut paths = paths.into_iter();
+    let image = paths.next().ok_or("no image given")?;
+    let devices: Vec<PathBuf> = paths.collect();
+
+    if !image.is_absolute() {
+        return Err(format!("image path '{}' is not absolute", image.display()));
+    }
+
+    if devices.is_empty() {
+        return Err("no devices given".into());
+    }
+
+    if devices.len() > MAX_DEVICES {
+        return Err(format!("at most {} devices may be flashed at once", MAX_DEVICES));
+    }
+
+    for (id, device) in devices.iter().enumerate() {
+        if !device.starts_with("/dev") || device.components().any(|c| c.as_os_str() == "..") {
+            return Err(format!("'{}' is not a device in /dev", device.display()));
+        }
+
+        if devices[..id].contains(device) {
+            return Err(format!("'{}' was given more than once", device.display()));
+        }
+    }
+
+    Ok(Flash { image, devices, check, policy })
+}
+
+async fn run(flash: Flash) -> anyhow::Result<()> {
+    let uid = env::var("PKEXEC_UID")
+        .ok()
+        .and_then(|uid| uid.parse::<libc::uid_t>().ok())
+        .context("the helper must be run through pkexec")?;
+
+    let mut image = open_as_user(&flash.image, uid)
+        .with_context(|| format!("unable to open image at '{}'", flash.image.display()))?;
+
+    let metadata = image.metadata().context("unable to fetch image metadata")?;
+    if !metadata.is_file() {
+        return Err(anyhow!("'{}' is not a file", flash.image.display()));
+    }
+
+    let image_size = metadata.len();
+    let data_size =
+        ImageSize::from_reader(&mut image, image_size).context("unable to read image")?;
+
+    image.seek(SeekFrom::Start(0)).context("unable to read image")?;
+
+    let mounts = mnt::get_submounts(Path::new("/")).context("error reading mounts")?;
+
+    let disk_args = flash.devices.iter().map(|path| AsyncPathBuf::from(path).into_boxed_path());
+    let disks = popsicle::disks_from_args(
+        disk_args,
+        &mounts,
+        Some(UnmountFlags::default()),
+        &flash.policy,
+        &data_size,
+        &Backend::Direct,
+    )
+    .await
+    .context("failed to open disks")?;
+
+    let (tx, rx) = mpsc::unbounded();
+    let mut paths = Vec::new();
+    let mut task = Task::new(File::from(image), flash.check);
+
+    for (disk_path, disk) in disks {
+        let path: PathBuf = disk_path.to_path_buf().into();
+        let progress = MessageProgress::new(path.clone(), tx.clone());
+        paths.push(path.clone());
+        task.subscribe_path(path, disk, (), progress);
+    }
+
+    drop(tx);
+
+    let control = task.control();
+    thread::spawn(move || apply_controls(&control));
+
+    let output = async move {
+        let mut stdout =
+            FramedWrite::new(AllowStdIo::new(io::stdout()), PopsicleEncoder::default());
+
+        for message in MessageProgress::preamble(image_size, &paths) {
+            let _ = stdout.send(message).await;
+        }
+
+        let mut rx = rx;
+        while let Some(message) = rx.next().await {
+            let _ = stdout.send(message).await;
+        }
+    };
+
+    let task = async move {
+        let buf = &mut [0u8; 64 * 1024];
+        task.process(buf).await
+    };
+
+    let (_, result) = join!(output, task);
+    result
+}
+
+/// Applies the control messages that the front end writes to stdin.
+fn apply_controls(control: &TaskControl) {
+    executor::block_on(async move {
+        let stdin = AllowStdIo::new(io::stdin());
+        let mut stream = FramedRead::new(stdin, ControlDecoder::default());
+        while let Some(message) = stream.next().await {
+            match message {
+                Ok(message) => {
+                    control.apply(&message);
+                }
+                Err(codec::Error::Read(_)) => break,
+                Err(why) => eprintln!("popsicle-helper: {}", why),
+            }
+        }
+    });
+}
+
+/// Opens a file with the user and group IDs, and supplementary groups, of the user `uid`.
+///
+/// The effective IDs of the helper are restored before returning.
+fn open_as_user(path: &Path, uid: libc::uid_t) -> io::Result<fs::File> {
+    let (gid, name) = user_entry(uid)?;
+    let groups = group_list(&name, gid)?;
+
+    let saved_groups = current_groups()?;
+    let (saved_uid, saved_gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+
+    check(unsafe { libc::setgroups(groups.len(), groups.as_ptr()) })?;
+    let opened = check(unsafe { libc::setegid(gid) })
+        .and_then(|_| check(unsafe { libc::seteuid(uid) }))
+        .and_then(|_| fs::File::open(path));
+
+    // Continuing without restoring the helper's own identity would be unsafe.
+    let restored = check(unsafe { libc::seteuid(saved_uid) })
+        .and_then(|_| check(unsafe { libc::setegid(saved_gid) }))
+        .and_then(|_| check(unsafe { libc::setgroups(saved_groups.len(), saved_groups.as_ptr()) }));
+
+    if let Err(why) = restored {
+        eprintln!("popsicle-helper: failed to restore privileges: {}", why);
+        process::abort();
+    }
+
+    opened
+}
+
+/// The primary group and name of the user `uid`, from the password database.
+fn user_entry(uid: libc::uid_t) -> io::Result<(libc::gid_t, CString)> {
+    let mut passwd: libc::passwd = unsafe { mem::zeroed() };
+    let mut buf: Vec<libc::c_char> = vec![0; 16 * 1024];
+    let mut result = ptr::null_mut();
+
+    let error =
+        unsafe { libc::getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result) };
+
+    if result.is_null() {
+        return Err(if error == 0 {
+            io::Error::new(io::ErrorKind::NotFound, format!("user {} does not exist", uid))
+        } else {
+            io::Error::from_raw_os_error(error)
+        });
+    }
+
+    Ok((passwd.pw_gid, unsafe { CStr::from_ptr(passwd.pw_name) }.to_owned()))
+}
+
+/// Every group that the named user is a member of.
+fn group_list(name: &CStr, gid: libc::gid_t) -> io::Result<Vec<libc::gid_t>> {
+    let mut capacity: libc::c_int = 32;
+
+    loop {
+        let mut groups = vec![0; capacity as usize];
+        let mut len = capacity;
+
+        if unsafe { libc::getgrouplist(name.as_ptr(), gid, groups.as_mut_ptr(), &mut len) } >= 0 {
+            groups.truncate(len as usize);
+            return Ok(groups);
+        }
+
+        if capacity >= 65536 {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "user is a member of too many groups",
+            ));
+        }
+
+        capacity = if len > capacity { len } else { capacity * 2 };
+    }
+}
+
+/// The supplementary groups of this process.
+fn current_groups() -> io::Result<Vec<libc::gid_t>> {
+    let len = check(unsafe { libc::getgroups(0, ptr::null_mut()) })?;
+    let mut groups = vec![0; len as usize];
+    let len = check(unsafe { libc::getgroups(len, groups.as_mut_ptr()) })?;
+    groups.truncate(len as usize);
+    Ok(groups)
+}
+
+fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Flash, String> {
+        parse(args.iter().map(OsString::from))
+    }
+
+    fn error(args: &[&str]) -> String {
+        parse_args(args).unwrap_err()
+    }
+
+    #[test]
+    fn parse_flash() {
+        let flash = parse_args(&["flash", "--check", "/tmp/pop.iso", "/dev/sdb", "/dev/sdc"]);
+        let flash = flash.unwrap();
+        assert_eq!(flash.image, Path::new("/tmp/pop.iso"));
+        assert_eq!(flash.devices, vec![PathBuf::from("/dev/sdb"), PathBuf::from("/dev/sdc")]);
+        assert!(flash.check);
+
+        assert!(!parse_args(&["flash", "/tmp/pop.iso", "/dev/sdb"]).unwrap().check);
+    }
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(error(&[]), "no command given");
+        assert_eq!(error(&["write", "/tmp/pop.iso", "/dev/sdb"]), "unknown command 'write'");
+        assert_eq!(error(&["flash"]), "no image given");
+        assert_eq!(error(&["flash", "/tmp/pop.iso"]), "no devices given");
+        assert_eq!(
+            error(&["flash", "pop.iso", "/dev/sdb"]),
+            "image path 'pop.iso' is not absolute"
+        );
+
+        // Options are refused anywhere among the paths, rather than being taken as a path.
+        let unknown = error(&["flash", "/tmp/pop.iso", "--force", "/dev/sdb"]);
+        assert_eq!(unknown, "unknown option '--force'");
+        assert_eq!(error(&["flash", "/tmp/pop.iso", "/dev/sdb", "-"]), "unknown option '-'");
+    }
+
+    #[test]
+    fn parse_max_size() {
+        let flash = parse_args(&["flash", "/tmp/pop.iso", "/dev/sdb"]).unwrap();
+        assert_eq!(flash.policy, SafetyPolicy::default());
+
+        let flash = parse_args(&["flash", "--max-size", "512", "/tmp/pop.iso", "/dev/sdb"]);
+        assert_eq!(flash.unwrap().policy.max_size, Some(512 << 30));
+        let flash = parse_args(&["flash", "--max-size", "0", "/tmp/pop.iso", "/dev/sdb"]);
+        assert_eq!(flash.unwrap().policy.max_size, None);
+
+        // Devices which are not removable are still refused.
+        let flash = parse_args(&["flash", "--max-size", "0", "/tmp/pop.iso", "/dev/sdb"]);
+        assert!(!flash.unwrap().policy.allow_unsafe);
+
+        assert_eq!(
+            error(&["flash", "/tmp/pop.iso", "/dev/sdb", "--max-size"]),
+            "no size given to '--max-size'"
+        );
+
+        for size in &["-1", "0x10", "", "18446744073709551615"] {
+            let why = error(&["flash", "--max-size", size, "/tmp/pop.iso", "/dev/sdb"]);
+            assert_eq!(why, format!("invalid maximum size '{}'", size));
+        }
+    }
+
+    #[test]
+    fn parse_devices() {
+        assert!(parse_args(&["flash", "/tmp/pop.iso", "/dev/disk/by-id/usb-Ultra-0:0"]).is_ok());
+
+        for device in &[
+            "/etc/shadow",
+            "/devices/sdb",
+            "dev/sdb",
+            "/dev/../etc/shadow",
+            "/dev/disk/../../etc/shadow",
+        ] {
+            let why = error(&["flash", "/tmp/pop.iso", device]);
+            assert_eq!(why, format!("'{}' is not a device in /dev", device));
+        }
+    }
+
+    #[test]
+    fn parse_device_limits() {
+        let mut args = vec!["flash".to_owned(), "/tmp/pop.iso".to_owned()];
+        args.extend((0..MAX_DEVICES).map(|id| format!("/dev/sd{}", id)));
+        assert_eq!(parse(args.iter().map(OsString::from)).unwrap().devices.len(), MAX_DEVICES);
+
+        args.push("/dev/sdz".into());
+        let why = parse(args.iter().map(OsString::from)).unwrap_err();
+        assert_eq!(why, format!("at most {} devices may be flashed at once", MAX_DEVICES));
+
+        // The same device may not be given twice, even when it is spelled differently.
+        let twice = error(&["flash", "/tmp/pop.iso", "/dev/sdb", "/dev/sdc", "/dev/sdb"]);
+        assert_eq!(twice, "'/dev/sdb' was given more than once");
+        let twice = error(&["flash", "/tmp/pop.iso", "/dev/sdb", "/dev//sdb/"]);
+        assert_eq!(twice, "'/dev//sdb/' was given more than once");
+    }
+}
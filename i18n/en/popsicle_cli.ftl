@@ -4,7 +4,7 @@ yn = y/N
 
 # Arguments
 arg-image = IMAGE
-arg-image-desc = Input image file
+arg-image-desc = Input image file, or an HTTP(S) URL to stream the image from
 
 arg-disks = DISKS
 arg-disks-desc = Output disk devices
@@ -12,7 +12,57 @@ arg-disks-desc = Output disk devices
 arg-all-desc = Flash all detected USB drives
 arg-check-desc = Check if written image matches source image
 arg-unmount-desc = Unmount mounted devices
+arg-lazy-desc = Detach busy mounts lazily when unmounting
+arg-force-desc = Force busy mounts to be unmounted
 arg-yes-desc = Continue without confirmation
+arg-allow-unsafe-desc = Allow flashing drives that are not removable
+arg-max-size-desc = Refuse drives larger than this many GiB, or 0 for no limit (default: 256)
+arg-format-desc = Format of the machine-readable output, when it is not written to a terminal
+arg-backend-desc = How devices are found, unmounted and opened: 'direct' requires root, while 'udisks2' asks UDisks2 and authenticates with polkit
+arg-checksum-desc = Verify the image against this {$algorithm} checksum, exiting with status 3 if it does not match
+arg-checksum-file-desc = Verify the image against the checksums which this file, such as SHA256SUMS, lists for it
+arg-keyring-desc = GPG keyring to verify the signatures of checksum files with, which must then be signed by a configured key (default: ~/.config/popsicle/trusted.gpg)
+arg-minisign-key-desc = minisign public key to verify the signatures of checksum files with, which must then be signed by a configured key (default: ~/.config/popsicle/minisign.pub)
+arg-no-checksum-discovery-desc = Do not verify a local image against checksum files found next to it, such as SHA256SUMS or IMAGE.sha256
+arg-control-desc = Read control messages, such as Cancel and Pause, from stdin when '-', or from connections to a Unix socket created at this path
+
+# Subcommands
+list-desc = List detected USB drives
+dbus-service-desc = Serve the com.system76.Popsicle1 D-Bus API, so that other applications may flash images
+hash-desc = Print checksums of an image, computing every algorithm in a single read
+arg-algorithm-desc = Algorithm to compute, which may be given more than once
+info-desc = Print what an image contains, such as its operating system and partition table, and whether it boots with BIOS or UEFI
+serve-desc = Serve an HTTP and WebSocket API for listing devices, uploading images, and running flash jobs
+serve-listening = listening on http://{$address}
+arg-listen-desc = Address to listen on, which should only be reachable by trusted clients, as the API has no authentication
+arg-images-desc = Directory of images which may be flashed, and where uploaded images are stored
+
+# Devices
+device-bus = Bus
+device-port = Port
+device-removable = removable
+device-serial = Serial
+
+# Image information
+info-container = Type
+info-os = Operating system
+info-volume = Volume
+info-publisher = Publisher
+info-application = Application
+info-partition-table = Partition table
+info-efi-system = EFI system partition
+info-boot = Boots with
+
+# Checksums
+checksum-file-found = verifying the image against checksums from '{$path}'
+checksum-file-signed = '{$path}' is signed by {$signer}
+
+# warnings
+warning-control-unknown-device = warning: cannot cancel '{$device}', which is not being flashed
+warning-checksum-file-unsigned = warning: '{$path}' is not signed, so it only guards against corrupted downloads
+warning-signature-unverified = warning: the checksums are not trusted, as their signature was not verified: {$why}
+warning-iso-not-hybrid = warning: '{$image_path}' is an ISO9660 image without a partition table, so it will only boot from a CD or DVD, and not from a drive it is written to; copy its files to a FAT32-formatted drive instead
+warning-image-size-unknown = warning: the uncompressed size of '{$image_path}' is not known, so drives are only checked against the size of the file
 
 # errors
 error-caused-by = caused by
@@ -20,8 +70,15 @@ error-image-not-set = {arg-image} not set
 error-image-open = unable to open image at '{$image_path}'
 error-image-metadata = unable to fetch image metadata at '{$image_path}'
 error-disks-fetch = failed to fetch list of USB disks
+error-backend = failed to set up the device backend
+error-dbus-connect = failed to connect to the D-Bus session bus
+error-dbus-service = the D-Bus service stopped
+error-http-listen = unable to serve the HTTP API on {$address}
 error-no-disks-specified = no disks specified
 error-fetching-mounts = failed to fetch list of mounts
 error-opening-disks = failed to open disks
 error-exiting = exiting without flashing
 error-reading-mounts = error reading mounts
+error-max-size-invalid = invalid maximum size '{$max_size}'
+error-control-socket = unable to create control socket at '{$path}'
+error-keyring = unable to find the keyring at '{$path}'
@@ -3,9 +3,15 @@ app-title = USB Flasher
 # Images View
 cannot-select-directories = File chooser can't select directories
 check-label = Check
+checksums-signed = Checksums from {$file} are signed by {$signer}
+checksums-unsigned = Checksums from {$file} are not signed
+checksums-untrusted = Checksums were not trusted: {$why}
 choose-image-button = Choose Image
 generating-checksum = Generating Checksum
 hash-label = Hash:
+image-boot = {$details}, boots with {$boot}
+image-not-bootable = {$details}, not bootable
+image-partition-table = {$details} with {$table} partition table
 image-view-description = Select the .iso or .img that you want to flash. You can also plug your USB drives in now.
 image-view-title = Choose an Image
 no-image-selected = No image selected
@@ -16,6 +22,8 @@ warning = Warning:
 device-too-small = Device too small
 devices-view-description = Flashing will erase all data on the selected drives.
 devices-view-title = Select Drives
+large-devices = Some of the selected drives are larger than {$limit}, which is larger than most USB drives. Make sure that nothing on them is needed before flashing them.
+large-devices-flash = Flash Anyway
 select-all = Select all
 
 # Flashing View
@@ -42,6 +50,7 @@ task-finished = Complete
 error = error: {$why}
 partial-flash = {$number} of {$total} devices successfully flashed
 successful-flash = {$total} devices successfully flashed
+iso-not-hybrid = This ISO only boots from a CD or DVD, and will not boot from a USB drive it is written to. Copy its files to a FAT32-formatted drive instead.
 win-isos-not-supported = Windows ISOs are not currently supported
 
 # Errors
@@ -0,0 +1,68 @@
+//! Selection of how devices are enumerated, unmounted, and opened.
+
+use crate::{DiskError, UDisks2};
+use async_std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// How devices are enumerated, unmounted, and opened.
+pub enum Backend {
+    /// Reads sysfs, and opens the nodes in `/dev` directly, which requires root.
+    Direct,
+    /// Asks the UDisks2 daemon, which authorizes an unprivileged user with polkit.
+    UDisks2(UDisks2),
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Backend::Direct
+    }
+}
+
+/// The name of a `Backend`, as given on the command line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BackendKind {
+    Direct,
+    UDisks2,
+}
+
+#[derive(Debug, Error)]
+#[error("unknown backend '{}': expected 'direct' or 'udisks2'", _0)]
+pub struct UnknownBackend(String);
+
+impl FromStr for BackendKind {
+    type Err = UnknownBackend;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "direct" => Ok(BackendKind::Direct),
+            "udisks2" | "udisks" => Ok(BackendKind::UDisks2),
+            _ => Err(UnknownBackend(name.into())),
+        }
+    }
+}
+
+impl Backend {
+    /// Creates the backend of the given kind, connecting to the system bus for UDisks2.
+    pub fn new(kind: BackendKind) -> Result<Self, DiskError> {
+        match kind {
+            BackendKind::Direct => Ok(Backend::Direct),
+            BackendKind::UDisks2 => {
+                UDisks2::system().map(Backend::UDisks2).map_err(DiskError::UDisksConnect)
+            }
+        }
+    }
+
+    /// Stores the paths of every USB disk which this backend can find into `disks`.
+    pub async fn usb_disks(&self, disks: &mut Vec<Box<Path>>) -> Result<(), DiskError> {
+        match self {
+            Backend::Direct => {
+                crate::usb_disk_devices(disks).await.map_err(DiskError::DeviceStream)
+            }
+            Backend::UDisks2(udisks) => {
+                let found = udisks.usb_disks().map_err(DiskError::UDisksEnumerate)?;
+                disks.extend(found.into_iter().map(|disk| PathBuf::from(disk.device).into()));
+                Ok(())
+            }
+        }
+    }
+}
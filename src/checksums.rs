@@ -0,0 +1,225 @@
+//! Reading the checksum files which are published alongside images, such as `SHA256SUMS`.
+//!
+//! Both the GNU format of `sha256sum`, and the BSD format which `sha256sum --tag` writes, are
+//! supported:
+//!
+//! ```text
+//! 9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08  pop-os.iso
+//! SHA256 (pop-os.iso) = 9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08
+//! ```
+
+use crate::{
+    hash::{Algorithm, Checksum},
+    signature,
+};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Error)]
+#[cfg_attr(rustfmt, rustfmt_skip)]
+pub enum ChecksumFileError {
+    #[error("unable to read checksum file '{}': {}", path.display(), why)]
+    Read { path: PathBuf, why: io::Error },
+    #[error("'{}' does not list a checksum for '{}'", path.display(), file)]
+    NotListed { path: PathBuf, file: String },
+}
+
+/// A checksum of a file, as listed by a checksum file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChecksumEntry {
+    pub checksum: Checksum,
+    pub file: String,
+}
+
+/// Parses every line of a checksum file which lists a valid checksum, and skips the rest.
+///
+/// The algorithm of a line in the GNU format is inferred from the length of its digest.
+pub fn parse(contents: &str) -> Vec<ChecksumEntry> {
+    parse_as(contents, None)
+}
+
+/// Parses a checksum file whose lines in the GNU format are of `algorithm`, if it is known.
+fn parse_as(contents: &str, algorithm: Option<Algorithm>) -> Vec<ChecksumEntry> {
+    contents
+        .lines()
+        .filter_map(|line| parse_bsd(line).or_else(|| parse_gnu(line, algorithm)))
+        .collect()
+}
+
+/// The checksums which the file at `path` lists for `file`, which is a file name rather than
+/// a path, as checksum files list images in the same directory.
+///
+/// A file which contains nothing but a digest, as some `.sha256` files do, is taken to be the
+/// checksum of `file`. Digests without a tag are of the algorithm which the file is named
+/// after, if it is named after one, as `BLAKE2bSUMS` is.
+pub fn checksums_for(path: &Path, file: &str) -> Result<Vec<Checksum>, ChecksumFileError> {
+    let contents = read(path)?;
+    let checksums = listed(&contents, file, true, named_algorithm(path));
+
+    if checksums.is_empty() {
+        return Err(ChecksumFileError::NotListed { path: path.to_path_buf(), file: file.into() });
+    }
+
+    Ok(checksums)
+}
+
+/// Detects the checksum in text which the user entered, which may be a bare digest, a line
+/// of a checksum file, or a digest prefixed with its algorithm, as in `sha256:9f86...`.
+///
+/// The algorithm of a bare digest is the one which is most likely for its length.
+pub fn detect(text: &str) -> Option<Checksum> {
+    let text = text.trim();
+    if let Some(entry) = parse_bsd(text).or_else(|| parse_gnu(text, None)) {
+        return Some(entry.checksum);
+    }
+
+    if let Some((name, digest)) = text.split_once(|c: char| c == ':' || c.is_whitespace()) {
+        if let Ok(algorithm) = name.parse() {
+            return Checksum::new(algorithm, digest).ok();
+        }
+    }
+
+    Checksum::new(Algorithm::from_hex_len(text.len())?, text).ok()
+}
+
+/// A checksum file which was found next to an image, and the checksums it lists for it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Discovered {
+    pub path: PathBuf,
+    pub checksums: Vec<Checksum>,
+}
+
+impl Discovered {
+    /// The checksum of the strongest algorithm.
+    pub fn strongest(&self) -> &Checksum {
+        self.checksums.iter().max_by_key(|checksum| checksum.algorithm()).expect("no checksums")
+    }
+}
+
+/// Looks for a checksum file next to `image` which lists it.
+///
+/// Files named after the image, such as `pop-os.iso.sha256` and `pop-os.iso.sha256sum`, are
+/// preferred over those shared by every image in the directory, such as `SHA256SUMS`, and
+/// Fedora's `*-CHECKSUM`. Files which cannot be read are skipped.
+pub fn discover(image: &Path) -> Option<Discovered> {
+    let file = image.file_name()?.to_str()?;
+    let stem = image.file_stem().and_then(|stem| stem.to_str());
+    let directory = match image.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let mut own = Vec::new();
+    let mut shared = Vec::new();
+    for algorithm in Algorithm::ALL.iter().rev() {
+        let extension = algorithm.name().to_ascii_lowercase();
+        own.push(format!("{}.{}", file, extension));
+        own.push(format!("{}.{}sum", file, extension));
+        if let Some(stem) = stem {
+            own.push(format!("{}.{}", stem, extension));
+        }
+
+        shared.push(format!("{}SUMS", algorithm.name()));
+        shared.push(format!("{}sum.txt", extension));
+        shared.push(format!("{}sums.txt", extension));
+    }
+
+    if let Ok(entries) = fs::read_dir(directory) {
+        let mut checksum_files: Vec<String> = entries
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .filter(|name| name.ends_with("CHECKSUM") || name.ends_with("CHECKSUMS"))
+            .collect();
+
+        checksum_files.sort();
+        shared.extend(checksum_files);
+    }
+
+    let candidates = own.into_iter().map(|name| (name, true));
+    let mut candidates = candidates.chain(shared.into_iter().map(|name| (name, false)));
+
+    candidates.find_map(|(name, bare)| {
+        let path = directory.join(name);
+        let checksums = listed(&read(&path).ok()?, file, bare, named_algorithm(&path));
+        if checksums.is_empty() {
+            None
+        } else {
+            Some(Discovered { path, checksums })
+        }
+    })
+}
+
+/// Checksum files are small, so anything larger is not read.
+const MAX_CHECKSUM_FILE_SIZE: u64 = 1024 * 1024;
+
+fn read(path: &Path) -> Result<String, ChecksumFileError> {
+    let error = |why| ChecksumFileError::Read { path: path.to_path_buf(), why };
+
+    if fs::metadata(path).map_err(error)?.len() > MAX_CHECKSUM_FILE_SIZE {
+        return Err(error(io::Error::new(io::ErrorKind::InvalidData, "file is too large")));
+    }
+
+    // Only the signed text of a clearsigned file is trusted.
+    fs::read_to_string(path).map(|contents| signature::signed_text(&contents)).map_err(error)
+}
+
+/// The algorithm which a checksum file is named after, as in `SHA3-256SUMS`, `sha256sum.txt`,
+/// `pop-os.iso.blake3` or `pop-os.iso.sha512sum`.
+fn named_algorithm(path: &Path) -> Option<Algorithm> {
+    let name = path.file_name()?.to_str()?;
+    let name = name.strip_suffix(".txt").unwrap_or(name);
+    let name =
+        ["SUMS", "sums", "sum"].iter().find_map(|suffix| name.strip_suffix(suffix)).unwrap_or(name);
+
+    name.rsplit('.').next()?.parse().ok()
+}
+
+/// The checksums which `contents` lists for `file`, or its bare digest if `bare` is set.
+///
+/// Digests without a tag are of `algorithm` if it is given, and are otherwise inferred from
+/// their length.
+fn listed(contents: &str, file: &str, bare: bool, algorithm: Option<Algorithm>) -> Vec<Checksum> {
+    let entries = parse_as(contents, algorithm);
+    if entries.is_empty() && bare {
+        return parse_bare(contents, algorithm).into_iter().collect();
+    }
+
+    entries
+        .into_iter()
+        .filter(|entry| entry.file.trim_start_matches("./") == file)
+        .map(|entry| entry.checksum)
+        .collect()
+}
+
+/// A file which contains nothing but a digest.
+fn parse_bare(contents: &str, algorithm: Option<Algorithm>) -> Option<Checksum> {
+    let mut lines = contents.lines().map(str::trim).filter(|line| !line.is_empty());
+    let digest = lines.next()?;
+    if lines.next().is_some() {
+        return None;
+    }
+
+    Checksum::new(algorithm.or_else(|| Algorithm::from_hex_len(digest.len()))?, digest).ok()
+}
+
+/// `SHA256 (pop-os.iso) = 9f86...`
+fn parse_bsd(line: &str) -> Option<ChecksumEntry> {
+    let (name, rest) = line.trim().split_once(" (")?;
+    let (file, digest) = rest.rsplit_once(") = ")?;
+    let checksum = Checksum::new(name.parse().ok()?, digest).ok()?;
+    Some(ChecksumEntry { checksum, file: file.into() })
+}
+
+/// `9f86...  pop-os.iso`, or `9f86... *pop-os.iso` when the file was hashed in binary mode.
+fn parse_gnu(line: &str, algorithm: Option<Algorithm>) -> Option<ChecksumEntry> {
+    let (digest, file) = line.trim().split_once(' ')?;
+    let file = file.strip_prefix(|c| c == ' ' || c == '*')?;
+    if file.is_empty() {
+        return None;
+    }
+
+    let algorithm = algorithm.or_else(|| Algorithm::from_hex_len(digest.len()))?;
+    let checksum = Checksum::new(algorithm, digest).ok()?;
+    Some(ChecksumEntry { checksum, file: file.into() })
+}
@@ -1,25 +1,156 @@
-use futures_codec::{BytesMut, Decoder};
+use futures_codec::{BytesMut, Decoder, Encoder};
 use memchr::memchr;
-use serde::{Deserialize, Serialize};
-use std::{io, path::PathBuf};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{io, path::PathBuf, str::FromStr};
 
 /// Errors that may occur when decoding the IPC stream.
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("failed to decode popsicle message: {{\n  {}\n}}", input)]
     Decode { input: Box<str>, source: ron::de::Error },
+    #[error("failed to decode popsicle message: {}", input)]
+    DecodeJson { input: Box<str>, source: serde_json::Error },
     #[error("reading from popsicle stream failed")]
     Read(#[from] io::Error),
+    #[error(
+        "popsicle stream uses protocol version {}, but only up to {} is supported",
+        _0,
+        PROTOCOL_VERSION
+    )]
+    UnsupportedVersion(u32),
+    #[error("popsicle message exceeds the limit of {} bytes", _0)]
+    LineTooLong(usize),
+}
+
+/// Errors that may occur when encoding the IPC stream.
+#[derive(Debug, Error)]
+pub enum EncodeError {
+    #[error("failed to encode popsicle message")]
+    Encode(#[from] ron::Error),
+    #[error("failed to encode popsicle message as JSON")]
+    EncodeJson(#[from] serde_json::Error),
+    #[error("writing to popsicle stream failed")]
+    Write(#[from] io::Error),
+}
+
+/// The version of the IPC protocol which is written by this library.
+///
+/// Streams which do not begin with a `Hello` message are version 1 streams, which only
+/// contain the `Device`, `Finished`, `Message`, `Set`, and `Size` messages.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// The longest line that decoders will buffer before discarding it: 64 KiB.
+pub const DEFAULT_MAX_LINE_LENGTH: usize = 64 * 1024;
+
+/// The serialization format of each line of the IPC stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Rusty Object Notation, which is the default.
+    Ron,
+    /// JSON Lines, where each message is a JSON value on its own line.
+    ///
+    /// Messages use serde's externally-tagged representation of enums, such as
+    /// `{"Set":["/dev/sdb",589824]}`, and unit variants are strings, such as `"Success"`.
+    Json,
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Format::Ron
+    }
+}
+
+/// An unrecognized format was given to `Format::from_str`.
+#[derive(Debug, Error)]
+#[error("unknown popsicle message format '{}': expected ron or jsonl", _0)]
+pub struct UnknownFormat(Box<str>);
+
+impl FromStr for Format {
+    type Err = UnknownFormat;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "ron" => Ok(Format::Ron),
+            "json" | "jsonl" => Ok(Format::Json),
+            _ => Err(UnknownFormat(format.into())),
+        }
+    }
 }
 
 /// Popsicle's IPC protocol
-#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub enum Message {
     Device(PathBuf),
     Finished(PathBuf),
     Message(PathBuf, String),
     Set(PathBuf, u64),
     Size(u64),
+
+    /// Sent once at the start of the stream, with the protocol version of the stream.
+    Hello {
+        version: u32,
+    },
+    /// A device has entered a new phase of flashing.
+    Phase(PathBuf, Phase),
+    /// The number of bytes per second being processed for a device.
+    Rate(PathBuf, u64),
+    /// A device failed during the given phase.
+    Error {
+        device: PathBuf,
+        phase: Phase,
+        message: String,
+    },
+    /// The final outcome of flashing a device.
+    Result(PathBuf, Outcome),
+    /// A digest of the image, or of what was written to a device.
+    Digest {
+        device: Option<PathBuf>,
+        algorithm: String,
+        digest: String,
+    },
+}
+
+/// The phases of flashing a device.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum Phase {
+    Writing,
+    Seeking,
+    Validating,
+}
+
+impl Phase {
+    /// The phase denoted by the message kinds given to `Progress::message`.
+    pub fn from_kind(kind: &str) -> Option<Self> {
+        match kind {
+            "W" => Some(Phase::Writing),
+            "S" => Some(Phase::Seeking),
+            "V" => Some(Phase::Validating),
+            _ => None,
+        }
+    }
+}
+
+/// The final outcome of flashing a device.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Outcome {
+    Success,
+    Failure(String),
+    Cancelled,
+}
+
+/// Messages written by a supervising process to control a running flash.
+///
+/// These travel in the opposite direction to `Message`, such as over popsicle's stdin or a
+/// control socket, and use the same line-based formats.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Control {
+    /// Stops flashing the device at the given path, leaving the others unaffected.
+    Cancel(PathBuf),
+    /// Stops flashing every device.
+    CancelAll,
+    /// Stops reading from the image and writing to devices until `Resume` is received.
+    Pause,
+    Resume,
 }
 
 /// A decoder for creating a stream of messages from a reader
@@ -29,26 +160,295 @@ pub enum Message {
 ///
 /// FramedRead::new(pipe_reader, PopsicleDecoder::default())
 /// ```
-#[derive(Default)]
-pub struct PopsicleDecoder;
+pub struct PopsicleDecoder {
+    format: Format,
+    version: Option<u32>,
+    lines: Lines,
+}
+
+impl Default for PopsicleDecoder {
+    fn default() -> Self {
+        Self::new(Format::default())
+    }
+}
+
+impl PopsicleDecoder {
+    /// A decoder for streams in the given format.
+    pub fn new(format: Format) -> Self {
+        Self { format, version: None, lines: Lines::default() }
+    }
+
+    /// Limits the length of each line, which is `DEFAULT_MAX_LINE_LENGTH` by default.
+    ///
+    /// Longer lines are discarded up to the next newline, and reported as `Error::LineTooLong`.
+    pub fn max_line_length(mut self, max: usize) -> Self {
+        self.lines.max = max;
+        self
+    }
+
+    /// Converts this into a decoder which reports malformed lines as items of the stream.
+    pub fn lenient(self) -> LenientDecoder {
+        LenientDecoder { decoder: self }
+    }
+
+    /// The protocol version of the stream, which is 1 until a `Hello` message is decoded.
+    pub fn version(&self) -> u32 {
+        self.version.unwrap_or(1)
+    }
+
+    fn decode_line(&mut self, line: &[u8]) -> Result<Message, Error> {
+        match deserialize(self.format, line)? {
+            Message::Hello { version } if version > PROTOCOL_VERSION => {
+                Err(Error::UnsupportedVersion(version))
+            }
+            value => {
+                if let Message::Hello { version } = value {
+                    self.version = Some(version);
+                }
+
+                Ok(value)
+            }
+        }
+    }
+}
 
 impl Decoder for PopsicleDecoder {
     type Item = Message;
     type Error = Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        match memchr(b'\n', src) {
-            Some(pos) => {
-                let buf = src.split_to(pos + 1);
-                match ron::de::from_bytes::<Self::Item>(&buf) {
-                    Ok(value) => Ok(Some(value)),
-                    Err(source) => Err(Error::Decode {
-                        input: String::from_utf8_lossy(&buf).into_owned().into(),
-                        source,
-                    }),
+        match self.lines.next(src)? {
+            Some(line) => self.decode_line(&line).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// An item of the stream decoded by a `LenientDecoder`.
+#[derive(Debug)]
+pub enum Decoded {
+    Message(Message),
+    /// A line which could not be decoded, and was skipped.
+    Malformed(Error),
+}
+
+/// A decoder which skips lines that cannot be decoded, rather than failing.
+///
+/// Where `PopsicleDecoder` returns an error, this yields `Decoded::Malformed` and continues
+/// with the next line. A final line which is missing its newline is also decoded at the end
+/// of the stream, as it may have been cut short by the producer exiting.
+///
+/// ```ignore
+/// FramedRead::new(pipe_reader, PopsicleDecoder::default().lenient())
+/// ```
+pub struct LenientDecoder {
+    decoder: PopsicleDecoder,
+}
+
+impl LenientDecoder {
+    /// The protocol version of the stream, which is 1 until a `Hello` message is decoded.
+    pub fn version(&self) -> u32 {
+        self.decoder.version()
+    }
+}
+
+impl Decoder for LenientDecoder {
+    type Item = Decoded;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        Ok(match self.decoder.decode(src) {
+            Ok(Some(message)) => Some(Decoded::Message(message)),
+            Ok(None) => None,
+            Err(why) => Some(Decoded::Malformed(why)),
+        })
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if let Some(item) = self.decode(src)? {
+            return Ok(Some(item));
+        }
+
+        if self.decoder.lines.discarding || src.is_empty() {
+            src.clear();
+            return Ok(None);
+        }
+
+        let line = src.split_to(src.len());
+        Ok(Some(match self.decoder.decode_line(&line) {
+            Ok(message) => Decoded::Message(message),
+            Err(why) => Decoded::Malformed(why),
+        }))
+    }
+}
+
+/// Splits newline-delimited lines from the stream, discarding lines which are too long.
+struct Lines {
+    max: usize,
+    /// Whether the remainder of an overlong line is being discarded.
+    discarding: bool,
+}
+
+impl Default for Lines {
+    fn default() -> Self {
+        Self { max: DEFAULT_MAX_LINE_LENGTH, discarding: false }
+    }
+}
+
+impl Lines {
+    fn next(&mut self, src: &mut BytesMut) -> Result<Option<BytesMut>, Error> {
+        if self.discarding {
+            match memchr(b'\n', src) {
+                Some(pos) => {
+                    let _ = src.split_to(pos + 1);
+                    self.discarding = false;
+                }
+                None => {
+                    src.clear();
+                    return Ok(None);
                 }
             }
+        }
+
+        match memchr(b'\n', src) {
+            Some(pos) if pos > self.max => {
+                let _ = src.split_to(pos + 1);
+                Err(Error::LineTooLong(self.max))
+            }
+            Some(pos) => Ok(Some(src.split_to(pos + 1))),
+            None if src.len() > self.max => {
+                src.clear();
+                self.discarding = true;
+                Err(Error::LineTooLong(self.max))
+            }
             None => Ok(None),
         }
     }
 }
+
+/// An encoder for writing a stream of messages to a writer
+///
+/// ```ignore
+/// use futures_codec::FramedWrite;
+///
+/// FramedWrite::new(pipe_writer, PopsicleEncoder::default())
+/// ```
+#[derive(Default)]
+pub struct PopsicleEncoder {
+    format: Format,
+}
+
+impl PopsicleEncoder {
+    /// An encoder for streams in the given format.
+    pub fn new(format: Format) -> Self {
+        Self { format }
+    }
+}
+
+impl Encoder for PopsicleEncoder {
+    type Item = Message;
+    type Error = EncodeError;
+
+    fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        serialize(self.format, &item, dst)
+    }
+}
+
+/// A decoder for reading a stream of control messages from a supervising process.
+#[derive(Default)]
+pub struct ControlDecoder {
+    format: Format,
+    lines: Lines,
+}
+
+impl ControlDecoder {
+    pub fn new(format: Format) -> Self {
+        Self { format, lines: Lines::default() }
+    }
+}
+
+impl Decoder for ControlDecoder {
+    type Item = Control;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.lines.next(src)? {
+            Some(line) => deserialize(self.format, &line).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// An encoder for writing a stream of control messages to popsicle.
+#[derive(Default)]
+pub struct ControlEncoder {
+    format: Format,
+}
+
+impl ControlEncoder {
+    pub fn new(format: Format) -> Self {
+        Self { format }
+    }
+}
+
+impl Encoder for ControlEncoder {
+    type Item = Control;
+    type Error = EncodeError;
+
+    fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        serialize(self.format, &item, dst)
+    }
+}
+
+fn deserialize<T: DeserializeOwned>(format: Format, buf: &[u8]) -> Result<T, Error> {
+    let input = || String::from_utf8_lossy(buf).into_owned().into();
+
+    match format {
+        Format::Ron => {
+            ron::de::from_bytes(buf).map_err(|source| Error::Decode { input: input(), source })
+        }
+        Format::Json => serde_json::from_slice(buf)
+            .map_err(|source| Error::DecodeJson { input: input(), source }),
+    }
+}
+
+fn serialize<T: Serialize>(
+    format: Format,
+    item: &T,
+    dst: &mut BytesMut,
+) -> Result<(), EncodeError> {
+    let encoded = match format {
+        Format::Ron => escape_nul(&ron::ser::to_string(item)?),
+        Format::Json => serde_json::to_string(item)?,
+    };
+
+    dst.reserve(encoded.len() + 1);
+    dst.extend_from_slice(encoded.as_bytes());
+    dst.extend_from_slice(b"\n");
+    Ok(())
+}
+
+/// RON escapes strings with `char::escape_debug`, which writes NUL as `\0`, but RON's
+/// parser only accepts it as a unicode escape.
+fn escape_nul(encoded: &str) -> String {
+    let mut escaped = String::with_capacity(encoded.len());
+    let mut chars = encoded.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            escaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('0') => escaped.push_str("\\u{0}"),
+            Some(next) => {
+                escaped.push(c);
+                escaped.push(next);
+            }
+            None => escaped.push(c),
+        }
+    }
+
+    escaped
+}
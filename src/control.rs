@@ -0,0 +1,208 @@
+//! Cancelling and pausing a running `Task` from outside of it.
+
+use crate::codec::Control;
+use async_std::{fs::File, task};
+use futures::io::{AsyncRead, AsyncSeek, AsyncWrite};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+/// How often a paused task checks whether it has been resumed.
+const PAUSE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A handle for cancelling and pausing a running `Task`, obtained with `Task::control`.
+///
+/// The handle may be cloned and sent to other threads, such as a thread which is reading
+/// `Control` messages from a pipe or socket.
+#[derive(Clone, Default)]
+pub struct TaskControl {
+    state: Arc<ControlState>,
+}
+
+#[derive(Default)]
+struct ControlState {
+    paused: AtomicBool,
+    cancelled: AtomicBool,
+    devices: Mutex<HashMap<usize, Device>>,
+}
+
+struct Device {
+    path: Option<PathBuf>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl TaskControl {
+    /// Applies a control message which was received from a supervising process.
+    ///
+    /// Returns `false` if the message names a device which is not part of the task.
+    pub fn apply(&self, control: &Control) -> bool {
+        match control {
+            Control::Cancel(path) => self.cancel_path(path),
+            Control::CancelAll => {
+                self.cancel_all();
+                true
+            }
+            Control::Pause => {
+                self.pause();
+                true
+            }
+            Control::Resume => {
+                self.resume();
+                true
+            }
+        }
+    }
+
+    /// Cancels the device which was subscribed with `Task::subscribe_path` as `path`.
+    ///
+    /// Symlinks to the device node, such as paths in `/dev/disk/by-id`, are also accepted.
+    /// Returns `false` if there is no such device.
+    pub fn cancel_path(&self, path: &Path) -> bool {
+        let canonical = fs::canonicalize(path).ok();
+        let devices = self.state.devices.lock().expect("task control lock poisoned");
+
+        let device = devices.values().find(|device| match device.path {
+            Some(ref subscribed) => {
+                subscribed == path || canonical.as_ref().map_or(false, |c| c == subscribed)
+            }
+            None => false,
+        });
+
+        match device {
+            Some(device) => {
+                device.cancelled.store(true, Ordering::SeqCst);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Cancels every device, which also resumes a paused task so that it may stop.
+    pub fn cancel_all(&self) {
+        self.state.cancelled.store(true, Ordering::SeqCst);
+        for device in self.state.devices.lock().expect("task control lock poisoned").values() {
+            device.cancelled.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Stops reading from the image and writing to devices until `resume` is called.
+    pub fn pause(&self) {
+        self.state.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.state.paused.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state.paused.load(Ordering::SeqCst) && !self.is_all_cancelled()
+    }
+
+    /// Whether `cancel_all` has been called.
+    pub fn is_all_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Whether the device with the given writer entity has been cancelled.
+    pub(crate) fn is_cancelled(&self, entity: usize) -> bool {
+        self.state
+            .devices
+            .lock()
+            .expect("task control lock poisoned")
+            .get(&entity)
+            .map_or(false, |device| device.cancelled.load(Ordering::SeqCst))
+    }
+
+    /// Registers a device, returning the file wrapped so that it fails once cancelled.
+    pub(crate) fn register(
+        &self,
+        path: Option<PathBuf>,
+        file: File,
+        insert: impl FnOnce(CancellableFile) -> usize,
+    ) -> usize {
+        let cancelled = Arc::new(AtomicBool::new(self.is_all_cancelled()));
+        let entity = insert(CancellableFile { file, cancelled: cancelled.clone() });
+
+        self.state
+            .devices
+            .lock()
+            .expect("task control lock poisoned")
+            .insert(entity, Device { path, cancelled });
+
+        entity
+    }
+
+    /// Waits until the task is no longer paused.
+    pub(crate) async fn wait_while_paused(&self) {
+        while self.is_paused() {
+            task::sleep(PAUSE_INTERVAL).await;
+        }
+    }
+}
+
+/// A device file which fails every operation once its device has been cancelled.
+pub struct CancellableFile {
+    file: File,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellableFile {
+    fn check(&self) -> io::Result<()> {
+        if self.cancelled.load(Ordering::SeqCst) {
+            Err(io::Error::new(io::ErrorKind::Other, "cancelled"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl AsyncRead for CancellableFile {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.check()?;
+        Pin::new(&mut self.file).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for CancellableFile {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.check()?;
+        Pin::new(&mut self.file).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.check()?;
+        Pin::new(&mut self.file).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.file).poll_close(cx)
+    }
+}
+
+impl AsyncSeek for CancellableFile {
+    fn poll_seek(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        pos: io::SeekFrom,
+    ) -> Poll<io::Result<u64>> {
+        self.check()?;
+        Pin::new(&mut self.file).poll_seek(cx, pos)
+    }
+}
@@ -0,0 +1,231 @@
+//! Descriptions of block devices, as reported by sysfs.
+
+use mnt::MountEntry;
+use std::{
+    fmt::{self, Display, Formatter},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+const SYSFS_BLOCK: &str = "/sys/class/block";
+
+/// The bus that a block device is connected through.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bus {
+    Usb,
+    Mmc,
+    Nvme,
+    Ata,
+    Scsi,
+    Virtio,
+    Other,
+}
+
+impl Display for Bus {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Bus::Usb => "usb",
+            Bus::Mmc => "mmc",
+            Bus::Nvme => "nvme",
+            Bus::Ata => "ata",
+            Bus::Scsi => "scsi",
+            Bus::Virtio => "virtio",
+            Bus::Other => "other",
+        })
+    }
+}
+
+/// A partition that resides on a `Device`.
+#[derive(Clone, Debug)]
+pub struct Partition {
+    /// Path to the partition's device node, such as `/dev/sdb1`.
+    pub path: Box<Path>,
+    /// The kernel name of the partition, such as `sdb1`.
+    pub name: Box<str>,
+    pub number: u32,
+    /// Size of the partition in bytes.
+    pub size: u64,
+    /// Where this partition is currently mounted.
+    pub mounts: Vec<Box<Path>>,
+}
+
+/// A whole-disk block device, and the information needed to identify it to a user.
+#[derive(Clone, Debug)]
+pub struct Device {
+    /// Path to the device node, such as `/dev/sdb`.
+    pub path: Box<Path>,
+    /// The kernel name of the device, such as `sdb`.
+    pub name: Box<str>,
+    pub vendor: Option<Box<str>>,
+    pub model: Option<Box<str>>,
+    pub serial: Option<Box<str>>,
+    /// Size of the device in bytes.
+    pub size: u64,
+    pub removable: bool,
+    pub bus: Bus,
+    /// Negotiated speed of a USB device, in Mbit/s.
+    pub usb_speed: Option<u32>,
+    /// The physical port that the device is plugged into, such as `2-1.4` for USB devices.
+    pub port: Option<Box<str>>,
+    pub partitions: Vec<Partition>,
+    /// Where the whole device is mounted, if it has been mounted without a partition table.
+    pub mounts: Vec<Box<Path>>,
+}
+
+impl Device {
+    /// Fetches information about the block device at `path` from sysfs.
+    ///
+    /// The `path` may be a symlink to the device node, such as a path in `/dev/disk/by-id`.
+    pub fn new<P: AsRef<Path>>(path: P, mounts: &[MountEntry]) -> io::Result<Self> {
+        Self::from_sysfs(Path::new(SYSFS_BLOCK), path.as_ref(), mounts)
+    }
+
+    /// Like `Device::new`, but reads device information from an alternative sysfs block directory.
+    pub fn from_sysfs(sysfs: &Path, path: &Path, mounts: &[MountEntry]) -> io::Result<Self> {
+        let canonical = fs::canonicalize(path)?;
+        let name = canonical
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid device name"))?;
+
+        let sys = fs::canonicalize(sysfs.join(name))?;
+
+        if sys.join("partition").exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is a partition, not a whole device", canonical.display()),
+            ));
+        }
+
+        let dev_dir = canonical.parent().unwrap_or_else(|| Path::new("/dev"));
+        let usb = usb_device_dir(&sys);
+
+        let mut partitions = Vec::new();
+        for entry in fs::read_dir(&sys)? {
+            let entry = entry?;
+            let part_sys = entry.path();
+            let number = match read_trimmed(&part_sys.join("partition")) {
+                Some(number) => number.parse::<u32>().unwrap_or(0),
+                None => continue,
+            };
+
+            let part_name = entry.file_name().to_string_lossy().into_owned();
+            let part_path = dev_dir.join(&part_name);
+            partitions.push(Partition {
+                mounts: mount_points(&part_path, mounts),
+                path: part_path.into(),
+                name: part_name.into(),
+                number,
+                size: read_size(&part_sys),
+            });
+        }
+
+        partitions.sort_by_key(|p| p.number);
+
+        let vendor = read_trimmed(&sys.join("device/vendor"))
+            .or_else(|| usb.as_ref().and_then(|usb| read_trimmed(&usb.join("manufacturer"))));
+
+        let model = read_trimmed(&sys.join("device/model"))
+            .or_else(|| read_trimmed(&sys.join("device/name")))
+            .or_else(|| usb.as_ref().and_then(|usb| read_trimmed(&usb.join("product"))));
+
+        let serial = read_trimmed(&sys.join("device/serial"))
+            .or_else(|| usb.as_ref().and_then(|usb| read_trimmed(&usb.join("serial"))));
+
+        Ok(Device {
+            mounts: mount_points(&canonical, mounts),
+            name: name.into(),
+            vendor,
+            model,
+            serial,
+            size: read_size(&sys),
+            removable: read_trimmed(&sys.join("removable")).map_or(false, |r| &*r == "1"),
+            bus: bus_of(&sys, usb.is_some()),
+            usb_speed: usb
+                .as_ref()
+                .and_then(|usb| read_trimmed(&usb.join("speed")))
+                .and_then(|speed| speed.parse::<f32>().ok())
+                .map(|speed| speed as u32),
+            port: usb
+                .as_ref()
+                .and_then(|usb| usb.file_name())
+                .map(|port| port.to_string_lossy().into_owned().into()),
+            partitions,
+            path: canonical.into(),
+        })
+    }
+
+    /// A human-readable label for the device, such as `SanDisk Ultra (/dev/sdb)`.
+    pub fn label(&self) -> String {
+        let name: Vec<&str> = self.vendor.iter().chain(self.model.iter()).map(|s| &**s).collect();
+
+        if name.is_empty() {
+            self.path.display().to_string()
+        } else {
+            format!("{} ({})", name.join(" "), self.path.display())
+        }
+    }
+
+    /// Every path where the device, or one of its partitions, is mounted.
+    pub fn all_mounts(&self) -> impl Iterator<Item = &Path> {
+        self.mounts
+            .iter()
+            .chain(self.partitions.iter().flat_map(|p| p.mounts.iter()))
+            .map(|path| &**path)
+    }
+}
+
+/// Walks up the sysfs device hierarchy to find the USB device which the block device belongs to.
+fn usb_device_dir(sys: &Path) -> Option<PathBuf> {
+    sys.ancestors()
+        .find(|dir| dir.join("idVendor").exists() && dir.join("speed").exists())
+        .map(Path::to_path_buf)
+}
+
+fn bus_of(sys: &Path, is_usb: bool) -> Bus {
+    if is_usb {
+        return Bus::Usb;
+    }
+
+    let path = sys.to_string_lossy();
+    if path.contains("/mmc") {
+        Bus::Mmc
+    } else if path.contains("/nvme") {
+        Bus::Nvme
+    } else if path.contains("/virtio") {
+        Bus::Virtio
+    } else if path.contains("/ata") {
+        Bus::Ata
+    } else if path.contains("/host") {
+        Bus::Scsi
+    } else {
+        Bus::Other
+    }
+}
+
+fn mount_points(device: &Path, mounts: &[MountEntry]) -> Vec<Box<Path>> {
+    mounts
+        .iter()
+        .filter(|mount| mount.spec.starts_with('/'))
+        .filter(|mount| {
+            fs::canonicalize(&mount.spec).map_or(false, |spec| spec.as_path() == device)
+        })
+        .map(|mount| Path::new(&mount.file).into())
+        .collect()
+}
+
+fn read_size(sys: &Path) -> u64 {
+    // The size file is always measured in 512-byte sectors.
+    read_trimmed(&sys.join("size")).and_then(|size| size.parse::<u64>().ok()).map_or(0, |s| s * 512)
+}
+
+fn read_trimmed(path: &Path) -> Option<Box<str>> {
+    let value = fs::read_to_string(path).ok()?;
+    let value = value.trim();
+
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.into())
+    }
+}
@@ -0,0 +1,322 @@
+//! Hashing images, with progress reporting and cancellation.
+//!
+//! Any number of digests may be computed from a single read of the image, so that checking
+//! several checksums of a large image does not read it several times.
+
+mod cache;
+
+pub use self::cache::{hash_file_cached, DigestCache, FileIdentity};
+
+use digest::DynDigest;
+use std::{
+    fmt::{self, Write},
+    fs::File,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// How much of the image is read at a time.
+const BUFFER_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Error)]
+#[cfg_attr(rustfmt, rustfmt_skip)]
+pub enum HashError {
+    #[error("unable to open '{}': {}", path.display(), why)]
+    Open { path: PathBuf, why: io::Error },
+    #[error("unable to read image: {}", _0)]
+    Read(io::Error),
+    #[error("hashing was cancelled")]
+    Cancelled,
+}
+
+#[derive(Debug, Error)]
+#[error("unknown hash algorithm '{}'", _0)]
+pub struct UnknownAlgorithm(pub String);
+
+#[derive(Debug, Error)]
+#[error("'{}' is not a {} checksum", checksum, algorithm)]
+pub struct InvalidChecksum {
+    pub algorithm: Algorithm,
+    pub checksum: String,
+}
+
+#[derive(Debug, Error)]
+#[error("{} checksum mismatch: expected {}, but the image is {}", algorithm, expected, actual)]
+pub struct ChecksumMismatch {
+    pub algorithm: Algorithm,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// A hash algorithm which images may be checked with.
+///
+/// Algorithms are ordered from the oldest to the newest, and digests of the same length are
+/// assumed to be of the earliest algorithm which has that length, as it is most widely used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Algorithm {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha384,
+    Sha512,
+    Sha3_256,
+    Sha3_512,
+    Blake2b,
+    Blake3,
+}
+
+impl Algorithm {
+    pub const ALL: [Algorithm; 9] = [
+        Algorithm::Md5,
+        Algorithm::Sha1,
+        Algorithm::Sha256,
+        Algorithm::Sha384,
+        Algorithm::Sha512,
+        Algorithm::Sha3_256,
+        Algorithm::Sha3_512,
+        Algorithm::Blake2b,
+        Algorithm::Blake3,
+    ];
+
+    /// The name of the algorithm, such as `SHA256`, as it is tagged in BSD checksum files.
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Md5 => "MD5",
+            Algorithm::Sha1 => "SHA1",
+            Algorithm::Sha256 => "SHA256",
+            Algorithm::Sha384 => "SHA384",
+            Algorithm::Sha512 => "SHA512",
+            Algorithm::Sha3_256 => "SHA3-256",
+            Algorithm::Sha3_512 => "SHA3-512",
+            Algorithm::Blake2b => "BLAKE2b",
+            Algorithm::Blake3 => "BLAKE3",
+        }
+    }
+
+    /// The algorithm which a hex digest most likely is, judging by its length.
+    pub fn from_hex_len(len: usize) -> Option<Algorithm> {
+        Algorithm::ALL.iter().copied().find(|algorithm| algorithm.digest_len() * 2 == len)
+    }
+
+    /// The length of a digest, in bytes.
+    pub fn digest_len(self) -> usize {
+        self.digest().output_size()
+    }
+
+    fn digest(self) -> Box<dyn DynDigest + Send> {
+        match self {
+            Algorithm::Md5 => Box::new(md5::Md5::default()),
+            Algorithm::Sha1 => Box::new(sha1::Sha1::default()),
+            Algorithm::Sha256 => Box::new(sha2::Sha256::default()),
+            Algorithm::Sha384 => Box::new(sha2::Sha384::default()),
+            Algorithm::Sha512 => Box::new(sha2::Sha512::default()),
+            Algorithm::Sha3_256 => Box::new(sha3::Sha3_256::default()),
+            Algorithm::Sha3_512 => Box::new(sha3::Sha3_512::default()),
+            Algorithm::Blake2b => Box::new(blake2::Blake2b512::default()),
+            Algorithm::Blake3 => Box::new(blake3::Hasher::default()),
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = UnknownAlgorithm;
+
+    /// Parses names such as `sha256`, `SHA-256`, `SHA256`, `sha3_256` and `blake2b-512`.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let normalize = |name: &str| name.replace(&['-', '_'][..], "").to_ascii_uppercase();
+        let normalized = match normalize(name).as_str() {
+            "BLAKE2B512" => "BLAKE2B".to_owned(),
+            normalized => normalized.to_owned(),
+        };
+
+        Algorithm::ALL
+            .iter()
+            .copied()
+            .find(|algorithm| normalize(algorithm.name()) == normalized)
+            .ok_or_else(|| UnknownAlgorithm(name.into()))
+    }
+}
+
+/// Computes the digests of several algorithms from the same data.
+pub struct Hasher {
+    digests: Vec<(Algorithm, Box<dyn DynDigest + Send>)>,
+}
+
+impl Hasher {
+    pub fn new(algorithms: &[Algorithm]) -> Self {
+        let mut algorithms = algorithms.to_vec();
+        algorithms.sort();
+        algorithms.dedup();
+
+        Self {
+            digests: algorithms
+                .into_iter()
+                .map(|algorithm| (algorithm, algorithm.digest()))
+                .collect(),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for (_, digest) in &mut self.digests {
+            digest.update(data);
+        }
+    }
+
+    pub fn finish(self) -> Digests {
+        Digests(
+            self.digests
+                .into_iter()
+                .map(|(algorithm, digest)| (algorithm, encode_hex(&digest.finalize())))
+                .collect(),
+        )
+    }
+}
+
+/// The digests computed by a `Hasher`, in lowercase hex.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Digests(Vec<(Algorithm, String)>);
+
+impl Digests {
+    pub fn get(&self, algorithm: Algorithm) -> Option<&str> {
+        self.0.iter().find(|(a, _)| *a == algorithm).map(|(_, digest)| digest.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Algorithm, &str)> {
+        self.0.iter().map(|(algorithm, digest)| (*algorithm, digest.as_str()))
+    }
+}
+
+/// A checksum which an image is expected to have.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Checksum {
+    algorithm: Algorithm,
+    digest: String,
+}
+
+impl Checksum {
+    /// Validates a checksum given in hex, which is stored in lowercase.
+    pub fn new(algorithm: Algorithm, digest: &str) -> Result<Self, InvalidChecksum> {
+        let digest = digest.trim();
+        let valid = decode_hex(digest).map_or(false, |bytes| bytes.len() == algorithm.digest_len());
+        if !valid {
+            return Err(InvalidChecksum { algorithm, checksum: digest.into() });
+        }
+
+        Ok(Self { algorithm, digest: digest.to_ascii_lowercase() })
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub fn digest(&self) -> &str {
+        &self.digest
+    }
+
+    /// Compares the checksum with the digest of the same algorithm in `digests`.
+    ///
+    /// # Panics
+    ///
+    /// If `digests` were not computed with this checksum's algorithm.
+    pub fn verify(&self, digests: &Digests) -> Result<(), ChecksumMismatch> {
+        let actual = digests.get(self.algorithm).expect("digest was not computed");
+        if actual == self.digest {
+            Ok(())
+        } else {
+            Err(ChecksumMismatch {
+                algorithm: self.algorithm,
+                expected: self.digest.clone(),
+                actual: actual.to_owned(),
+            })
+        }
+    }
+}
+
+/// A handle for cancelling a hash which is running on another thread.
+#[derive(Clone, Debug, Default)]
+pub struct HashCancel(Arc<AtomicBool>);
+
+impl HashCancel {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Hashes the file at `path` with each of the `algorithms`.
+///
+/// `progress` is called with the number of bytes hashed so far, after each read.
+pub fn hash_file(
+    path: &Path,
+    algorithms: &[Algorithm],
+    cancel: &HashCancel,
+    progress: impl FnMut(u64),
+) -> Result<Digests, HashError> {
+    let file = File::open(path).map_err(|why| HashError::Open { path: path.to_path_buf(), why })?;
+
+    hash_reader(file, algorithms, cancel, progress)
+}
+
+/// Hashes everything that is read from `reader` with each of the `algorithms`.
+///
+/// `progress` is called with the number of bytes hashed so far, after each read. Hashing stops
+/// with `HashError::Cancelled` once `cancel` is cancelled.
+pub fn hash_reader<R: Read>(
+    mut reader: R,
+    algorithms: &[Algorithm],
+    cancel: &HashCancel,
+    mut progress: impl FnMut(u64),
+) -> Result<Digests, HashError> {
+    let mut hasher = Hasher::new(algorithms);
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    let mut total = 0;
+
+    loop {
+        if cancel.is_cancelled() {
+            return Err(HashError::Cancelled);
+        }
+
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(ref why) if why.kind() == io::ErrorKind::Interrupted => continue,
+            Err(why) => return Err(HashError::Read(why)),
+        };
+
+        hasher.update(&buffer[..read]);
+        total += read as u64;
+        progress(total);
+    }
+
+    Ok(hasher.finish())
+}
+
+/// Decodes a hex digest, such as one which the user entered, or a checksum file listed.
+pub fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+
+    (0..hex.len()).step_by(2).map(|pos| u8::from_str_radix(&hex[pos..pos + 2], 16).ok()).collect()
+}
+
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::with_capacity(bytes.len() * 2), |mut hex, byte| {
+        let _ = write!(hex, "{:02x}", byte);
+        hex
+    })
+}
@@ -0,0 +1,185 @@
+//! Remembering the digests of files across runs, so that an image is only hashed again if it
+//! was changed.
+
+use super::{hash_file, Algorithm, Digests, HashCancel, HashError};
+use serde::{Deserialize, Serialize};
+use std::{
+    env, fs, io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// How many files have their digests remembered, before the least recently hashed are
+/// forgotten.
+const MAX_ENTRIES: usize = 256;
+
+/// What identifies a file as it is now, which changes whenever the file is replaced or written.
+///
+/// The modification time may be set to anything by the owner of the file, so the time at which
+/// its status last changed, which may not be set, identifies it as well.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct FileIdentity {
+    pub device: u64,
+    pub inode: u64,
+    pub size: u64,
+    pub modified: i64,
+    pub modified_nanos: i64,
+    pub changed: i64,
+    pub changed_nanos: i64,
+}
+
+impl FileIdentity {
+    pub fn of(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        Ok(Self {
+            device: metadata.dev(),
+            inode: metadata.ino(),
+            size: metadata.size(),
+            modified: metadata.mtime(),
+            modified_nanos: metadata.mtime_nsec(),
+            changed: metadata.ctime(),
+            changed_nanos: metadata.ctime_nsec(),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Entry {
+    file: FileIdentity,
+    /// Digests by the name of their algorithm, so that entries which were written by a version
+    /// which knew other algorithms are still read.
+    digests: Vec<(String, String)>,
+}
+
+/// Digests of files, which are stored in a cache file, and which are forgotten once the file
+/// that they were computed from is changed.
+#[derive(Debug, Default)]
+pub struct DigestCache {
+    path: Option<PathBuf>,
+    entries: Vec<Entry>,
+}
+
+impl DigestCache {
+    /// Reads the cache file at `path`, or starts an empty cache if it is missing or unreadable.
+    pub fn open(path: PathBuf) -> Self {
+        let entries = fs::read(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_slice(&contents).ok())
+            .unwrap_or_default();
+
+        Self { path: Some(path), entries }
+    }
+
+    /// The cache in the user's cache directory, `~/.cache/popsicle/digests.json`.
+    pub fn open_default() -> Self {
+        let directory = env::var_os("XDG_CACHE_HOME")
+            .filter(|directory| !directory.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")));
+
+        match directory {
+            Some(directory) => Self::open(directory.join("popsicle").join("digests.json")),
+            None => Self::default(),
+        }
+    }
+
+    /// The digest of `file`, if it was computed since the file was last changed.
+    pub fn get(&self, file: &FileIdentity, algorithm: Algorithm) -> Option<&str> {
+        let entry = self.entries.iter().find(|entry| entry.file == *file)?;
+        entry
+            .digests
+            .iter()
+            .find(|(name, _)| name.parse::<Algorithm>().ok() == Some(algorithm))
+            .map(|(_, digest)| digest.as_str())
+    }
+
+    /// Remembers the digests of `file`, and stores them in the cache file.
+    pub fn insert(&mut self, file: &FileIdentity, digests: &Digests) -> io::Result<()> {
+        // Digests of an earlier version of the file are no longer needed.
+        let same_file =
+            |entry: &Entry| entry.file.device == file.device && entry.file.inode == file.inode;
+
+        let mut entry = match self.entries.iter().position(same_file) {
+            Some(index) if self.entries[index].file == *file => self.entries.remove(index),
+            Some(index) => {
+                self.entries.remove(index);
+                Entry { file: file.clone(), digests: Vec::new() }
+            }
+            None => Entry { file: file.clone(), digests: Vec::new() },
+        };
+
+        for (algorithm, digest) in digests.iter() {
+            entry.digests.retain(|(name, _)| name.parse::<Algorithm>().ok() != Some(algorithm));
+            entry.digests.push((algorithm.name().to_owned(), digest.to_owned()));
+        }
+
+        self.entries.push(entry);
+        if self.entries.len() > MAX_ENTRIES {
+            let excess = self.entries.len() - MAX_ENTRIES;
+            self.entries.drain(..excess);
+        }
+
+        self.save()
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let path = match self.path.as_ref() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // The cache is replaced at once, so that it is never left half written.
+        let temporary = path.with_extension("json.part");
+        let contents = serde_json::to_vec(&self.entries)
+            .map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))?;
+        fs::write(&temporary, contents)?;
+        fs::rename(&temporary, path)
+    }
+}
+
+/// Hashes the file at `path` like `hash_file`, but takes digests which were already computed
+/// for the file, as it is now, from `cache`, and remembers those which are computed.
+///
+/// Digests are only remembered if the file did not change while it was hashed.
+pub fn hash_file_cached(
+    path: &Path,
+    algorithms: &[Algorithm],
+    cache: &Mutex<DigestCache>,
+    cancel: &HashCancel,
+    progress: impl FnMut(u64),
+) -> Result<Digests, HashError> {
+    let open_error = |why| HashError::Open { path: path.to_path_buf(), why };
+    let file = FileIdentity::of(path).map_err(open_error)?;
+
+    let mut cached = Vec::new();
+    let mut missing = Vec::new();
+    {
+        let cache = cache.lock().expect("digest cache was poisoned");
+        for &algorithm in algorithms {
+            match cache.get(&file, algorithm) {
+                Some(digest) => cached.push((algorithm, digest.to_owned())),
+                None => missing.push(algorithm),
+            }
+        }
+    }
+
+    if !missing.is_empty() {
+        let computed = hash_file(path, &missing, cancel, progress)?;
+
+        // The cache only saves time, so the digests are still returned if it cannot be written.
+        if FileIdentity::of(path).ok().as_ref() == Some(&file) {
+            let _ = cache.lock().expect("digest cache was poisoned").insert(&file, &computed);
+        }
+
+        cached.extend(computed.0);
+    }
+
+    cached.sort_by_key(|&(algorithm, _)| algorithm);
+    cached.dedup_by_key(|&mut (algorithm, _)| algorithm);
+    Ok(Digests(cached))
+}
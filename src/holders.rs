@@ -0,0 +1,251 @@
+//! Detection of everything that keeps a block device, or one of its partitions, in use.
+
+use mnt::MountEntry;
+use std::{
+    fmt::{self, Display, Formatter},
+    fs::{self, OpenOptions},
+    io,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+// Relative to the root directory which devices are found in.
+const SYSFS_BLOCK: &str = "sys/class/block";
+const SYSFS_BTRFS: &str = "sys/fs/btrfs";
+const PROC_SWAPS: &str = "proc/swaps";
+const UDEV_DATA: &str = "run/udev/data";
+
+/// What is holding a device node.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HolderKind {
+    /// A filesystem on the node is mounted at the given path.
+    Mount(Box<Path>),
+    /// The node is an active swap area.
+    Swap,
+    /// The node is a physical volume of an active LVM logical volume.
+    Lvm(Box<str>),
+    /// The node is backing an open dm-crypt / LUKS mapping.
+    Crypt(Box<str>),
+    /// The node is backing some other device-mapper target.
+    DeviceMapper(Box<str>),
+    /// The node is a member of an assembled md RAID array.
+    Raid(Box<str>),
+    /// The node is a member of a mounted btrfs filesystem, identified by its UUID.
+    Btrfs(Box<str>),
+    /// The node is a member of an imported ZFS pool.
+    Zfs(Box<str>),
+    /// The kernel refuses exclusive access to the node, for an unknown reason.
+    Busy,
+    /// The node is held by another kernel device.
+    Other(Box<str>),
+}
+
+/// A device node, and what is holding it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Holder {
+    /// The whole device or partition that is being held.
+    pub device: Box<Path>,
+    pub kind: HolderKind,
+}
+
+impl Display for Holder {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let device = self.device.display();
+        match self.kind {
+            HolderKind::Mount(ref target) => {
+                write!(f, "{} is mounted at {}", device, target.display())
+            }
+            HolderKind::Swap => write!(f, "{} is an active swap area", device),
+            HolderKind::Lvm(ref name) => {
+                write!(f, "{} is a physical volume of active LVM volume {}", device, name)
+            }
+            HolderKind::Crypt(ref name) => {
+                write!(f, "{} is backing the open encrypted mapping {}", device, name)
+            }
+            HolderKind::DeviceMapper(ref name) => {
+                write!(f, "{} is backing the device-mapper target {}", device, name)
+            }
+            HolderKind::Raid(ref name) => {
+                write!(f, "{} is a member of the RAID array {}", device, name)
+            }
+            HolderKind::Btrfs(ref uuid) => {
+                write!(f, "{} is a member of the mounted btrfs filesystem {}", device, uuid)
+            }
+            HolderKind::Zfs(ref pool) => {
+                write!(f, "{} is a member of the ZFS pool {}", device, pool)
+            }
+            HolderKind::Busy => write!(f, "{} is in use by the kernel", device),
+            HolderKind::Other(ref name) => write!(f, "{} is held by {}", device, name),
+        }
+    }
+}
+
+/// Finds every holder of the whole-disk device at `device`, and of each of its partitions.
+///
+/// Mounts are matched against the exact device nodes of the disk and its partitions, so
+/// that holders of `/dev/sdaa` are never attributed to `/dev/sda`.
+pub fn device_holders<P: AsRef<Path>>(device: P, mounts: &[MountEntry]) -> io::Result<Vec<Holder>> {
+    device_holders_in(Path::new("/"), device.as_ref(), mounts)
+}
+
+/// Like `device_holders`, but reads sysfs, `/proc` and the udev database beneath `root`.
+pub fn device_holders_in(
+    root: &Path,
+    device: &Path,
+    mounts: &[MountEntry],
+) -> io::Result<Vec<Holder>> {
+    let device = fs::canonicalize(device)?;
+    let name = device
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid device name"))?;
+
+    let dev_dir = device.parent().unwrap_or_else(|| Path::new("/dev"));
+    let sys = root.join(SYSFS_BLOCK).join(name);
+
+    // The whole device, followed by each of its partitions.
+    let mut nodes = vec![(device.clone(), sys.clone())];
+    for entry in fs::read_dir(&sys)? {
+        let entry = entry?;
+        if entry.path().join("partition").exists() {
+            nodes.push((dev_dir.join(entry.file_name()), entry.path()));
+        }
+    }
+
+    let swaps = active_swaps(root);
+    let mut holders = Vec::new();
+
+    for (node, node_sys) in &nodes {
+        let node_name = node_sys.file_name().map(|n| n.to_string_lossy().into_owned());
+        let node_name = node_name.as_deref().unwrap_or_default();
+
+        for mount in mounts {
+            if mount.spec.starts_with('/') && same_node(Path::new(&mount.spec), node) {
+                holders.push(Holder {
+                    device: node.clone().into(),
+                    kind: HolderKind::Mount(Path::new(&mount.file).into()),
+                });
+            }
+        }
+
+        if swaps.iter().any(|swap| same_node(swap, node)) {
+            holders.push(Holder { device: node.clone().into(), kind: HolderKind::Swap });
+        }
+
+        if let Ok(entries) = fs::read_dir(node_sys.join("holders")) {
+            for entry in entries.filter_map(Result::ok) {
+                let kind = kernel_holder(root, &entry.file_name().to_string_lossy());
+                holders.push(Holder { device: node.clone().into(), kind });
+            }
+        }
+
+        if let Some(uuid) = btrfs_filesystem(root, node_name) {
+            holders.push(Holder { device: node.clone().into(), kind: HolderKind::Btrfs(uuid) });
+        }
+    }
+
+    // ZFS and other in-kernel users do not register as holders, but they do prevent
+    // exclusive access to the device, which the kernel reports as `EBUSY`.
+    if holders.is_empty() {
+        for (node, node_sys) in &nodes {
+            if !is_busy(node) {
+                continue;
+            }
+
+            let kind = match udev_property(root, node_sys, "ID_FS_TYPE") {
+                Some(ref fs_type) if &**fs_type == "zfs_member" => HolderKind::Zfs(
+                    udev_property(root, node_sys, "ID_FS_LABEL")
+                        .unwrap_or_else(|| "unknown".into()),
+                ),
+                _ => HolderKind::Busy,
+            };
+
+            holders.push(Holder { device: node.clone().into(), kind });
+        }
+    }
+
+    Ok(holders)
+}
+
+/// Classifies a kernel device found in a `holders` directory, such as `dm-0` or `md127`.
+fn kernel_holder(root: &Path, holder: &str) -> HolderKind {
+    let sys = root.join(SYSFS_BLOCK).join(holder);
+
+    if holder.starts_with("dm-") {
+        let name = read_trimmed(&sys.join("dm/name")).unwrap_or_else(|| holder.into());
+        let uuid = read_trimmed(&sys.join("dm/uuid")).unwrap_or_default();
+
+        if uuid.starts_with("LVM-") {
+            HolderKind::Lvm(name)
+        } else if uuid.starts_with("CRYPT-") {
+            HolderKind::Crypt(name)
+        } else {
+            HolderKind::DeviceMapper(name)
+        }
+    } else if holder.starts_with("md") {
+        HolderKind::Raid(holder.into())
+    } else {
+        HolderKind::Other(holder.into())
+    }
+}
+
+/// The UUID of the mounted btrfs filesystem which the named node is a member of.
+fn btrfs_filesystem(root: &Path, node_name: &str) -> Option<Box<str>> {
+    fs::read_dir(root.join(SYSFS_BTRFS))
+        .ok()?
+        .filter_map(Result::ok)
+        .find(|fs| fs.path().join("devices").join(node_name).exists())
+        .map(|fs| fs.file_name().to_string_lossy().into_owned().into())
+}
+
+/// The device paths of every active swap area, from `/proc/swaps`.
+fn active_swaps(root: &Path) -> Vec<PathBuf> {
+    fs::read_to_string(root.join(PROC_SWAPS))
+        .map(|swaps| {
+            swaps
+                .lines()
+                .skip(1)
+                .filter_map(|line| line.split_whitespace().next())
+                .map(PathBuf::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Reads a property of the node from the udev database, such as `ID_FS_TYPE`.
+fn udev_property(root: &Path, node_sys: &Path, property: &str) -> Option<Box<str>> {
+    let dev = read_trimmed(&node_sys.join("dev"))?;
+    let data = fs::read_to_string(root.join(UDEV_DATA).join(["b", &dev].concat())).ok()?;
+    let prefix = ["E:", property, "="].concat();
+
+    data.lines().find_map(|line| {
+        if line.starts_with(&prefix) {
+            Some(line[prefix.len()..].into())
+        } else {
+            None
+        }
+    })
+}
+
+/// Whether the kernel refuses to grant exclusive access to the node.
+fn is_busy(node: &Path) -> bool {
+    match OpenOptions::new().read(true).custom_flags(libc::O_EXCL).open(node) {
+        Ok(_) => false,
+        Err(why) => why.raw_os_error() == Some(libc::EBUSY),
+    }
+}
+
+fn same_node(path: &Path, node: &Path) -> bool {
+    fs::canonicalize(path).map_or(false, |path| path == node)
+}
+
+fn read_trimmed(path: &Path) -> Option<Box<str>> {
+    let value = fs::read_to_string(path).ok()?;
+    let value = value.trim();
+
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.into())
+    }
+}
@@ -0,0 +1,561 @@
+//! An HTTP service through which popsicle may be driven remotely, such as by the controller of
+//! a headless flashing appliance.
+//!
+//! Requests and responses are JSON, at the following endpoints:
+//!
+//! - `GET /devices`: the devices which may be flashed, as `[{"path", "label", "size"}]`.
+//! - `GET /images`: the images in the image directory, as `[{"name", "size"}]`.
+//! - `PUT /images/NAME`: stores the body of the request in the image directory as `NAME`.
+//! - `POST /jobs`: flashes `{"image", "devices", "check", "unmount", "force"}`, where `image` is
+//!   the name of an image in the image directory, and responds with the `{"id"}` of the job.
+//! - `GET /jobs` and `GET /jobs/ID`: the status of every job, or of one job.
+//! - `POST /jobs/ID/cancel`: cancels every device of a job.
+//! - `POST /jobs/ID/control`: applies a `codec::Control` to a job, such as `{"Cancel": PATH}`
+//!   or `"Pause"`.
+//! - `GET /jobs/ID/events`: a WebSocket which sends each `codec::Message` of a job as a JSON
+//!   text frame, starting from the beginning of the job, and is closed once the job is done.
+//!
+//! There is no authentication, so the service should only listen on localhost, or on a
+//! network which is trusted to flash any device attached to the machine.
+//!
+//! So that web pages cannot drive the service from a browser on the machine, requests must name
+//! the address which the service listens on in their `Host`, and in their `Origin` if they have
+//! one, by its IP address, or as `localhost` if it is a local address. Bodies of `POST`
+//! requests must be `application/json`. Only the most recent jobs which have finished are
+//! remembered.
+
+use crate::{
+    codec::{Control, Message},
+    service::{Devices, Flash, FlashOptions},
+    DeviceProgress, DeviceStatus, ProgressModel, TaskControl,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use std::{
+    fmt::Display,
+    fs,
+    io::{self, Cursor, Read},
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex, MutexGuard,
+    },
+    thread,
+};
+use tiny_http::{Header, Method, Request, Response, StatusCode};
+use tungstenite::{handshake::derive_accept_key, protocol::Role, WebSocket};
+
+/// How many jobs which have finished are remembered, so that clients may still fetch their
+/// status.
+const MAX_FINISHED_JOBS: usize = 32;
+
+/// The HTTP service, which is listening for requests.
+pub struct Server {
+    http: tiny_http::Server,
+    state: Arc<State>,
+}
+
+impl Server {
+    /// Listens on `address`, such as `127.0.0.1:8076`, for requests to flash the images in the
+    /// `images` directory to `devices`.
+    pub fn bind<D: Devices>(address: &str, images: PathBuf, devices: D) -> anyhow::Result<Self> {
+        let http = tiny_http::Server::http(address)
+            .map_err(|why| anyhow!("unable to listen on {}: {}", address, why))?;
+
+        let address = http
+            .server_addr()
+            .to_ip()
+            .ok_or_else(|| anyhow!("{} is not an IP address", address))?;
+
+        let state = State {
+            address,
+            devices: Arc::new(devices),
+            images,
+            jobs: Mutex::default(),
+            next_job: AtomicUsize::new(0),
+        };
+
+        Ok(Self { http, state: Arc::new(state) })
+    }
+
+    /// The address that the service is listening on.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        Some(self.state.address)
+    }
+
+    /// Serves requests, each on a thread of its own, until the listener fails.
+    pub fn run(self) {
+        for request in self.http.incoming_requests() {
+            let state = self.state.clone();
+            thread::spawn(move || handle(&state, request));
+        }
+    }
+}
+
+struct State {
+    address: SocketAddr,
+    devices: Arc<dyn Devices>,
+    images: PathBuf,
+    jobs: Mutex<Vec<Arc<Job>>>,
+    next_job: AtomicUsize,
+}
+
+impl State {
+    fn job(&self, id: &str) -> Result<Arc<Job>, Failure> {
+        let jobs = self.jobs.lock().expect("jobs lock poisoned");
+        id.parse::<usize>()
+            .ok()
+            .and_then(|id| jobs.iter().find(|job| job.id == id))
+            .cloned()
+            .ok_or_else(|| Failure::not_found(format!("no such job: {}", id)))
+    }
+
+    /// Whether `host`, from the `Host` or `Origin` of a request, names the address which the
+    /// service is listening on.
+    ///
+    /// Names other than `localhost` are refused, even if they resolve to that address, as a web
+    /// page could have its own name resolved to it, and then send requests as its own origin.
+    fn is_own_host(&self, host: &str) -> bool {
+        let (name, port) = match host.rsplit_once(':') {
+            Some((name, port)) if !port.ends_with(']') => match port.parse::<u16>() {
+                Ok(port) => (name, port),
+                Err(_) => return false,
+            },
+            _ => (host, 80),
+        };
+
+        if port != self.address.port() {
+            return false;
+        }
+
+        let ip = self.address.ip();
+        if name.eq_ignore_ascii_case("localhost") {
+            return ip.is_loopback() || ip.is_unspecified();
+        }
+
+        match name.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+            Ok(named) => named == ip || ip.is_unspecified(),
+            Err(_) => false,
+        }
+    }
+
+    /// The path of the image called `name`, if it is a valid name.
+    fn image(&self, name: &str) -> Result<PathBuf, Failure> {
+        if name.is_empty() || name.starts_with('.') || name.contains(&['/', '\0'][..]) {
+            return Err(Failure::bad_request(format!("invalid image name: {}", name)));
+        }
+
+        Ok(self.images.join(name))
+    }
+}
+
+/// A flash which was started with `POST /jobs`.
+struct Job {
+    id: usize,
+    image: String,
+    control: TaskControl,
+    record: Mutex<Record>,
+}
+
+/// What a job has reported so far.
+#[derive(Default)]
+struct Record {
+    history: Vec<Message>,
+    model: ProgressModel,
+    result: Option<Result<(), String>>,
+    /// The WebSockets which are waiting for the next message.
+    subscribers: Vec<mpsc::Sender<Message>>,
+}
+
+impl Job {
+    fn record(&self) -> MutexGuard<'_, Record> {
+        self.record.lock().expect("job lock poisoned")
+    }
+
+    fn report(&self, message: Message) {
+        let mut record = self.record();
+        record.model.apply(&message);
+        record.subscribers.retain(|subscriber| subscriber.send(message.clone()).is_ok());
+        record.history.push(message);
+    }
+
+    fn is_finished(&self) -> bool {
+        self.record().result.is_some()
+    }
+
+    fn finish(&self, result: Result<(), String>) {
+        let mut record = self.record();
+        record.result = Some(result);
+        record.subscribers.clear();
+    }
+
+    /// Every message reported so far, and a receiver of those which follow, unless the job has
+    /// already finished.
+    fn subscribe(&self) -> (Vec<Message>, Option<mpsc::Receiver<Message>>) {
+        let mut record = self.record();
+        let history = record.history.clone();
+        if record.result.is_some() {
+            return (history, None);
+        }
+
+        let (tx, rx) = mpsc::channel();
+        record.subscribers.push(tx);
+        (history, Some(rx))
+    }
+
+    fn status(&self) -> Value {
+        let record = self.record();
+        let error = match record.result {
+            Some(Err(ref why)) => Some(why.as_str()),
+            _ => None,
+        };
+
+        json!({
+            "id": self.id,
+            "image": self.image,
+            "size": record.model.size(),
+            "finished": record.result.is_some(),
+            "error": error,
+            "devices": record.model.devices().iter().map(device_status).collect::<Vec<_>>(),
+        })
+    }
+}
+
+fn device_status(device: &DeviceProgress) -> Value {
+    let (status, message) = match device.status {
+        DeviceStatus::Active => ("active", None),
+        DeviceStatus::Succeeded => ("succeeded", None),
+        DeviceStatus::Failed(ref why) => ("failed", Some(why.as_str())),
+        DeviceStatus::Cancelled => ("cancelled", None),
+    };
+
+    json!({
+        "path": device.path,
+        "phase": device.phase,
+        "written": device.written,
+        "total": device.total,
+        "rate": device.rate(),
+        "status": status,
+        "message": message,
+    })
+}
+
+/// The body of `POST /jobs`.
+#[derive(Deserialize)]
+struct JobRequest {
+    image: String,
+    devices: Vec<PathBuf>,
+    #[serde(default)]
+    check: bool,
+    #[serde(default)]
+    unmount: bool,
+    #[serde(default)]
+    force: bool,
+}
+
+/// A request which could not be served, and the status which the client is given.
+struct Failure {
+    status: u16,
+    message: String,
+}
+
+impl Failure {
+    fn bad_request(message: impl Display) -> Self {
+        Self { status: 400, message: message.to_string() }
+    }
+
+    fn forbidden(message: impl Display) -> Self {
+        Self { status: 403, message: message.to_string() }
+    }
+
+    fn not_found(message: impl Display) -> Self {
+        Self { status: 404, message: message.to_string() }
+    }
+
+    fn internal(message: impl Display) -> Self {
+        Self { status: 500, message: message.to_string() }
+    }
+
+    fn response(&self) -> Response<Cursor<Vec<u8>>> {
+        json_response(self.status, &json!({ "error": self.message }))
+    }
+}
+
+type Reply = Result<Response<Cursor<Vec<u8>>>, Failure>;
+
+fn json_response(status: u16, body: &Value) -> Response<Cursor<Vec<u8>>> {
+    let content_type = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+        .expect("static header is valid");
+
+    Response::from_string(body.to_string())
+        .with_status_code(StatusCode(status))
+        .with_header(content_type)
+}
+
+fn handle(state: &State, mut request: Request) {
+    let path = request.url().split('?').next().unwrap_or_default();
+    let segments: Vec<String> =
+        path.split('/').filter(|segment| !segment.is_empty()).map(decode).collect();
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+
+    if let Err(why) = check_origin(state, &request) {
+        let _ = request.respond(why.response());
+        return;
+    }
+
+    if let (Method::Get, ["jobs", id, "events"]) = (request.method(), &segments[..]) {
+        events(state, id, request);
+        return;
+    }
+
+    let reply = route(state, &mut request, &segments).unwrap_or_else(|why| why.response());
+    let _ = request.respond(reply);
+}
+
+fn route(state: &State, request: &mut Request, segments: &[&str]) -> Reply {
+    match (request.method().clone(), segments) {
+        (Method::Get, ["devices"]) => list_devices(state),
+        (Method::Get, ["images"]) => list_images(state),
+        (Method::Put, ["images", name]) => upload(state, name, request.as_reader()),
+        (Method::Get, ["jobs"]) => {
+            let jobs = state.jobs.lock().expect("jobs lock poisoned").clone();
+            Ok(json_response(200, &jobs.iter().map(|job| job.status()).collect()))
+        }
+        (Method::Post, ["jobs"]) => start(state, json_body(request)?),
+        (Method::Get, ["jobs", id]) => Ok(json_response(200, &state.job(id)?.status())),
+        (Method::Post, ["jobs", id, "cancel"]) => {
+            let job = state.job(id)?;
+            job.control.cancel_all();
+            Ok(json_response(200, &job.status()))
+        }
+        (Method::Post, ["jobs", id, "control"]) => {
+            let job = state.job(id)?;
+            let control: Control = json_body(request)?;
+
+            if !job.control.apply(&control) {
+                return Err(Failure::bad_request("the job is not flashing that device"));
+            }
+
+            Ok(json_response(200, &job.status()))
+        }
+        _ => Err(Failure::not_found("no such endpoint")),
+    }
+}
+
+/// Refuses requests which a web page may have sent, either from an origin of its own, or from
+/// a name of its own which it had resolved to the address of the service.
+fn check_origin(state: &State, request: &Request) -> Result<(), Failure> {
+    let host = header(request, "Host").unwrap_or_default();
+    if !state.is_own_host(host) {
+        return Err(Failure::forbidden(format!("unexpected host: {}", host)));
+    }
+
+    if let Some(origin) = header(request, "Origin") {
+        let own = origin.strip_prefix("http://").map_or(false, |host| state.is_own_host(host));
+        if !own {
+            return Err(Failure::forbidden(format!("unexpected origin: {}", origin)));
+        }
+    }
+
+    Ok(())
+}
+
+fn header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str())
+}
+
+/// Parses the body of a request, which must be declared to be JSON, as browsers let web pages
+/// send bodies of some other types to any origin without asking it first.
+fn json_body<T: DeserializeOwned>(request: &mut Request) -> Result<T, Failure> {
+    let json = header(request, "Content-Type").map_or(false, |content_type| {
+        let media_type = content_type.split(';').next().unwrap_or_default();
+        media_type.trim().eq_ignore_ascii_case("application/json")
+    });
+
+    if !json {
+        return Err(Failure { status: 415, message: "expected a JSON body".into() });
+    }
+
+    serde_json::from_reader(request.as_reader()).map_err(Failure::bad_request)
+}
+
+fn list_devices(state: &State) -> Reply {
+    let devices = state.devices.list().map_err(|why| Failure::internal(format!("{:#}", why)))?;
+    let devices: Vec<Value> = devices
+        .into_iter()
+        .map(|device| json!({ "path": device.path, "label": device.label, "size": device.size }))
+        .collect();
+
+    Ok(json_response(200, &Value::Array(devices)))
+}
+
+fn list_images(state: &State) -> Reply {
+    let entries = fs::read_dir(&state.images).map_err(|why| {
+        Failure::internal(format!("unable to read '{}': {}", state.images.display(), why))
+    })?;
+
+    let mut images = Vec::new();
+    for entry in entries.filter_map(Result::ok) {
+        let name = match entry.file_name().into_string() {
+            Ok(name) if !name.starts_with('.') => name,
+            _ => continue,
+        };
+
+        match entry.metadata() {
+            Ok(metadata) if metadata.is_file() => images.push((name, metadata.len())),
+            _ => (),
+        }
+    }
+
+    images.sort();
+    let images: Vec<Value> =
+        images.into_iter().map(|(name, size)| json!({ "name": name, "size": size })).collect();
+
+    Ok(json_response(200, &Value::Array(images)))
+}
+
+/// Stores an uploaded image, which only appears under its name once it is complete.
+fn upload(state: &State, name: &str, body: &mut dyn Read) -> Reply {
+    let path = state.image(name)?;
+    let partial = state.images.join(format!(".{}.part", name));
+
+    let result = fs::File::create(&partial)
+        .and_then(|mut file| io::copy(body, &mut file))
+        .and_then(|size| fs::rename(&partial, &path).map(|_| size));
+
+    match result {
+        Ok(size) => Ok(json_response(201, &json!({ "name": name, "size": size }))),
+        Err(why) => {
+            let _ = fs::remove_file(&partial);
+            Err(Failure::internal(format!("unable to store '{}': {}", name, why)))
+        }
+    }
+}
+
+fn start(state: &State, request: JobRequest) -> Reply {
+    if request.devices.is_empty() {
+        return Err(Failure::bad_request("no devices given"));
+    }
+
+    let image = state.image(&request.image)?;
+    if !image.is_file() {
+        return Err(Failure::not_found(format!("no such image: {}", request.image)));
+    }
+
+    let control = TaskControl::default();
+    let job = {
+        let mut jobs = state.jobs.lock().expect("jobs lock poisoned");
+
+        // The oldest jobs which have finished are forgotten.
+        let finished = jobs.iter().filter(|job| job.is_finished()).count();
+        let mut excess = finished.saturating_sub(MAX_FINISHED_JOBS);
+        jobs.retain(|job| {
+            let forget = excess > 0 && job.is_finished();
+            if forget {
+                excess -= 1;
+            }
+
+            !forget
+        });
+
+        let job = Arc::new(Job {
+            id: state.next_job.fetch_add(1, Ordering::SeqCst),
+            image: request.image,
+            control: control.clone(),
+            record: Mutex::default(),
+        });
+
+        jobs.push(job.clone());
+        job
+    };
+
+    let flash = Flash {
+        image,
+        targets: request.devices,
+        options: FlashOptions {
+            check: request.check,
+            unmount: request.unmount,
+            force: request.force,
+        },
+        control,
+        devices: state.devices.clone(),
+    };
+
+    let id = job.id;
+    thread::spawn(move || {
+        let result = flash.run(|message| job.report(message));
+        job.finish(result);
+    });
+
+    Ok(json_response(201, &json!({ "id": id })))
+}
+
+/// Upgrades the request to a WebSocket, which follows the messages of a job until it is done.
+fn events(state: &State, id: &str, request: Request) {
+    let job = match state.job(id) {
+        Ok(job) => job,
+        Err(why) => {
+            let _ = request.respond(why.response());
+            return;
+        }
+    };
+
+    let key = header(&request, "Sec-WebSocket-Key").map(|key| derive_accept_key(key.as_bytes()));
+
+    let accept =
+        match key.and_then(|key| Header::from_bytes(&b"Sec-WebSocket-Accept"[..], key).ok()) {
+            Some(accept) => accept,
+            None => {
+                let _ = request.respond(Failure::bad_request("expected a WebSocket").response());
+                return;
+            }
+        };
+
+    let stream = request.upgrade("websocket", Response::empty(101).with_header(accept));
+    let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+
+    let (history, live) = job.subscribe();
+    let live = live.into_iter().flat_map(|receiver| receiver.into_iter());
+    for message in history.into_iter().chain(live) {
+        let text = serde_json::to_string(&message).expect("messages are serializable");
+        if socket.write_message(tungstenite::Message::Text(text)).is_err() {
+            return;
+        }
+    }
+
+    // Wait for the client to acknowledge the close.
+    let _ = socket.close(None);
+    while socket.read_message().is_ok() {}
+}
+
+/// Decodes the percent-encoded bytes of a path segment.
+fn decode(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let escaped = bytes
+            .get(pos + 1..pos + 3)
+            .filter(|_| bytes[pos] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                pos += 3;
+            }
+            None => {
+                decoded.push(bytes[pos]);
+                pos += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -0,0 +1,316 @@
+//! Determining how many bytes an image will occupy once it is written to a device.
+
+use crate::ImageError;
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+const XZ_MAGIC: &[u8] = &[0xFD, b'7', b'z', b'X', b'Z', 0x00];
+const XZ_FOOTER_MAGIC: &[u8] = b"YZ";
+const ZSTD_MAGIC: u32 = 0xFD2F_B528;
+const BZIP2_MAGIC: &[u8] = b"BZh";
+
+/// The compression format of an image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Xz,
+    Zstd,
+    Bzip2,
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Compression::Gzip => "gzip",
+            Compression::Xz => "xz",
+            Compression::Zstd => "zstd",
+            Compression::Bzip2 => "bzip2",
+        })
+    }
+}
+
+/// The size of an image file, and of the data that it contains.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImageSize {
+    /// The size of the image file itself.
+    pub file: u64,
+    pub compression: Option<Compression>,
+    /// The size of the image's data once decompressed.
+    ///
+    /// If `exact` is false, the size of the data is not known, and this is only an estimate,
+    /// which may be smaller than the data, or even than the file.
+    pub data: u64,
+    pub exact: bool,
+}
+
+impl ImageSize {
+    /// Measures the image at `path`, reading the uncompressed size from compressed images.
+    ///
+    /// Images which are not regular files, such as optical drives, are not probed for
+    /// compression, and only their size is measured.
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, ImageError> {
+        let mut file = File::open(path).map_err(|why| ImageError::Open { why })?;
+        let metadata = file.metadata().map_err(|why| ImageError::Metadata { why })?;
+
+        if !metadata.is_file() {
+            // Block devices report their size by seeking to their end, while pipes have none.
+            let len = file.seek(SeekFrom::End(0)).unwrap_or(0);
+            return Ok(ImageSize { file: len, compression: None, data: len, exact: len != 0 });
+        }
+
+        Self::from_reader(&mut file, metadata.len())
+    }
+
+    /// How many bytes a device must hold to have the image written to it.
+    ///
+    /// The file is written as it is, so that is the least that is required. The size of its
+    /// decompressed data is also required if it is exactly known, so that a device which could
+    /// not hold the image once decompressed is not chosen.
+    pub fn required(&self) -> u64 {
+        if self.exact {
+            self.file.max(self.data)
+        } else {
+            self.file
+        }
+    }
+
+    /// Measures an image of `len` bytes, which is read from `reader`.
+    pub fn from_reader<R: Read + Seek>(reader: &mut R, len: u64) -> Result<Self, ImageError> {
+        let mut magic = [0u8; 6];
+        let read = read_at(reader, 0, &mut magic).map_err(|why| ImageError::ReadError { why })?;
+        let magic = &magic[..read];
+
+        let uncompressed = ImageSize { file: len, compression: None, data: len, exact: true };
+
+        let measured = match compression_of(magic) {
+            Some(Compression::Gzip) => {
+                gzip_size(reader, len).map(|data| (Compression::Gzip, data, false))
+            }
+            Some(Compression::Xz) => xz_size(reader, len).map(|data| (Compression::Xz, data, true)),
+            Some(Compression::Zstd) => {
+                zstd_size(reader, len).map(|(data, exact)| (Compression::Zstd, data, exact))
+            }
+            // The bzip2 format does not record the size of the data that it contains.
+            Some(Compression::Bzip2) => Ok((Compression::Bzip2, 0, false)),
+            None => return Ok(uncompressed),
+        };
+
+        let (compression, data, exact) = measured.map_err(|why| ImageError::ReadError { why })?;
+
+        Ok(ImageSize { file: len, compression: Some(compression), data, exact })
+    }
+}
+
+/// The compression format of an image which begins with `magic`.
+pub(crate) fn compression_of(magic: &[u8]) -> Option<Compression> {
+    if magic.starts_with(GZIP_MAGIC) {
+        Some(Compression::Gzip)
+    } else if magic.starts_with(XZ_MAGIC) {
+        Some(Compression::Xz)
+    } else if magic.len() >= 4 && u32_le(magic) == ZSTD_MAGIC {
+        Some(Compression::Zstd)
+    } else if magic.starts_with(BZIP2_MAGIC) {
+        Some(Compression::Bzip2)
+    } else {
+        None
+    }
+}
+
+/// The gzip trailer only stores the size of the data modulo 2^32, which is a lower bound.
+fn gzip_size<R: Read + Seek>(reader: &mut R, len: u64) -> io::Result<u64> {
+    if len < 18 {
+        return Err(invalid("gzip image is truncated"));
+    }
+
+    let mut isize = [0u8; 4];
+    read_exact_at(reader, len - 4, &mut isize)?;
+    Ok(u64::from(u32_le(&isize)))
+}
+
+/// Sums the uncompressed size of every block in the index of every concatenated xz stream.
+fn xz_size<R: Read + Seek>(reader: &mut R, len: u64) -> io::Result<u64> {
+    let mut total = 0;
+    let mut end = len;
+
+    while end > 0 {
+        if end < 4 {
+            return Err(invalid("xz image is truncated"));
+        }
+
+        // Streams may be followed by stream padding, which is a multiple of four null bytes.
+        let mut word = [0u8; 4];
+        read_exact_at(reader, end - 4, &mut word)?;
+        if word == [0; 4] {
+            end -= 4;
+            continue;
+        }
+
+        if end < 24 {
+            return Err(invalid("xz image is truncated"));
+        }
+
+        let mut footer = [0u8; 12];
+        read_exact_at(reader, end - 12, &mut footer)?;
+        if &footer[10..] != XZ_FOOTER_MAGIC {
+            return Err(invalid("xz stream footer is missing"));
+        }
+
+        let index_size = (u64::from(u32_le(&footer[4..8])) + 1) * 4;
+        let index_start = (end - 12)
+            .checked_sub(index_size)
+            .ok_or_else(|| invalid("xz index is out of bounds"))?;
+
+        let mut index = vec![0u8; index_size as usize];
+        read_exact_at(reader, index_start, &mut index)?;
+
+        if index[0] != 0 {
+            return Err(invalid("xz index indicator is missing"));
+        }
+
+        let mut pos = 1;
+        let records = varint(&index, &mut pos)?;
+        let mut blocks = 0;
+        for _ in 0..records {
+            let unpadded = varint(&index, &mut pos)?;
+            let uncompressed = varint(&index, &mut pos)?;
+            total = xz_add(total, uncompressed)?;
+            blocks = xz_add(blocks, xz_add(unpadded, 3)? & !3)?;
+        }
+
+        end = index_start
+            .checked_sub(xz_add(blocks, 12)?)
+            .ok_or_else(|| invalid("xz stream is out of bounds"))?;
+    }
+
+    Ok(total)
+}
+
+/// Sums the content size of every zstd frame, walking the blocks of each frame to find the
+/// next. The size is not exact if any frame omits its content size.
+fn zstd_size<R: Read + Seek>(reader: &mut R, len: u64) -> io::Result<(u64, bool)> {
+    let mut total = 0;
+    let mut exact = true;
+    let mut pos = 0;
+
+    while pos < len {
+        let mut magic = [0u8; 4];
+        read_exact_at(reader, pos, &mut magic)?;
+        let magic = u32_le(&magic);
+
+        // Skippable frames carry metadata, and are followed by their length.
+        if magic & 0xFFFF_FFF0 == 0x184D_2A50 {
+            let mut size = [0u8; 4];
+            read_exact_at(reader, pos + 4, &mut size)?;
+            pos += 8 + u64::from(u32_le(&size));
+            continue;
+        }
+
+        if magic != ZSTD_MAGIC {
+            return Err(invalid("invalid zstd frame"));
+        }
+
+        let mut descriptor = [0u8; 1];
+        read_exact_at(reader, pos + 4, &mut descriptor)?;
+        let descriptor = descriptor[0];
+
+        let single_segment = descriptor & 0x20 != 0;
+        let has_checksum = descriptor & 0x04 != 0;
+        let dict_id_len = [0, 1, 2, 4][usize::from(descriptor & 0x03)];
+        let content_size_len = match descriptor >> 6 {
+            0 if single_segment => 1,
+            0 => 0,
+            1 => 2,
+            2 => 4,
+            _ => 8,
+        };
+
+        pos += 5 + if single_segment { 0 } else { 1 } + dict_id_len;
+
+        if content_size_len == 0 {
+            exact = false;
+        } else {
+            let mut size = [0u8; 8];
+            read_exact_at(reader, pos, &mut size[..content_size_len as usize])?;
+            let size = u64::from_le_bytes(size);
+            total += if content_size_len == 2 { size + 256 } else { size };
+        }
+
+        pos += content_size_len;
+
+        loop {
+            let mut header = [0u8; 4];
+            read_exact_at(reader, pos, &mut header[..3])?;
+            let header = u32_le(&header);
+            let last = header & 1 != 0;
+            let size = u64::from(header >> 3);
+
+            // RLE blocks store a single byte, regardless of the size they decode to.
+            pos += 3 + if (header >> 1) & 3 == 1 { 1 } else { size };
+
+            if last {
+                break;
+            }
+        }
+
+        if has_checksum {
+            pos += 4;
+        }
+    }
+
+    Ok((total, exact))
+}
+
+fn varint(buf: &[u8], pos: &mut usize) -> io::Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..63).step_by(7) {
+        let byte = *buf.get(*pos).ok_or_else(|| invalid("xz index is truncated"))?;
+        *pos += 1;
+        value |= u64::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(invalid("xz index contains an invalid integer"))
+}
+
+/// Adds sizes from an xz index, which may be corrupt or crafted to overflow.
+fn xz_add(a: u64, b: u64) -> io::Result<u64> {
+    a.checked_add(b).ok_or_else(|| invalid("xz index sizes are out of bounds"))
+}
+
+pub(crate) fn read_at<R: Read + Seek>(
+    reader: &mut R,
+    pos: u64,
+    buf: &mut [u8],
+) -> io::Result<usize> {
+    reader.seek(SeekFrom::Start(pos))?;
+
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+
+    Ok(read)
+}
+
+fn read_exact_at<R: Read + Seek>(reader: &mut R, pos: u64, buf: &mut [u8]) -> io::Result<()> {
+    reader.seek(SeekFrom::Start(pos))?;
+    reader.read_exact(buf)
+}
+
+pub(crate) fn u32_le(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,457 @@
+//! Inspecting what an image contains, and how it boots, before it is written.
+
+mod os;
+
+pub use self::os::OsInfo;
+
+use crate::{
+    image::{compression_of, read_at, u32_le},
+    Compression, ImageError,
+};
+use std::{
+    fmt,
+    fs::File,
+    io::{Read, Seek},
+    path::Path,
+};
+
+const SECTOR: u64 = 512;
+const ISO_SECTOR: u64 = 2048;
+
+/// ISO9660 volume descriptors begin after the 32 KiB system area, which hybrid images use
+/// for their partition table.
+const ISO_DESCRIPTORS: u64 = 16 * ISO_SECTOR;
+const ISO_MAGIC: &[u8] = b"CD001";
+const EL_TORITO: &[u8] = b"EL TORITO SPECIFICATION";
+
+const GPT_MAGIC: &[u8] = b"EFI PART";
+const GPT_PROTECTIVE: u8 = 0xEE;
+const MBR_EFI_SYSTEM: u8 = 0xEF;
+const EFI_SYSTEM_GUID: &str = "C12A7328-F81F-11D2-BA4B-00A0C93EC93B";
+const BIOS_BOOT_GUID: &str = "21686148-6449-6E6F-744E-656564454649";
+
+/// El Torito platform IDs.
+const PLATFORM_BIOS: u8 = 0x00;
+const PLATFORM_EFI: u8 = 0xEF;
+
+/// What kind of image a file is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Container {
+    /// An ISO9660 image, which is made to be booted from optical media.
+    Iso9660,
+    /// An ISO9660 image which also has a partition table, so that it may boot from a drive.
+    HybridIso,
+    /// An image of a whole disk, with its partition table.
+    DiskImage,
+    /// A compressed image, whose contents are not inspected.
+    Compressed(Compression),
+    /// Data without a partition table, such as a file system.
+    Unknown,
+}
+
+impl fmt::Display for Container {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Container::Iso9660 => f.write_str("ISO9660 image"),
+            Container::HybridIso => f.write_str("hybrid ISO9660 image"),
+            Container::DiskImage => f.write_str("disk image"),
+            Container::Compressed(compression) => write!(f, "{} compressed image", compression),
+            Container::Unknown => f.write_str("unknown"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PartitionTable {
+    Mbr,
+    Gpt,
+}
+
+impl fmt::Display for PartitionTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            PartitionTable::Mbr => "MBR",
+            PartitionTable::Gpt => "GPT",
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PartitionType {
+    /// The partition type byte of an MBR partition.
+    Mbr(u8),
+    /// The partition type GUID of a GPT partition.
+    Gpt(String),
+}
+
+impl PartitionType {
+    /// Whether this is an EFI System Partition, which UEFI firmware boots from.
+    pub fn is_efi_system(&self) -> bool {
+        match self {
+            PartitionType::Mbr(kind) => *kind == MBR_EFI_SYSTEM,
+            PartitionType::Gpt(guid) => guid == EFI_SYSTEM_GUID,
+        }
+    }
+}
+
+impl fmt::Display for PartitionType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PartitionType::Mbr(kind) => write!(f, "0x{:02x}", kind),
+            PartitionType::Gpt(guid) => f.write_str(guid),
+        }
+    }
+}
+
+/// A partition in the image's partition table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImagePartition {
+    pub number: u32,
+    pub kind: PartitionType,
+    /// Where the partition begins, in bytes.
+    pub start: u64,
+    /// The size of the partition, in bytes.
+    pub size: u64,
+    /// The name of a GPT partition.
+    pub name: Option<String>,
+}
+
+/// The identifiers from an ISO9660 image's primary volume descriptor.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IsoVolume {
+    pub system: String,
+    pub volume: String,
+    pub publisher: String,
+    pub application: String,
+}
+
+/// Which firmware an image may be booted by.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Boot {
+    pub bios: bool,
+    pub uefi: bool,
+}
+
+impl fmt::Display for Boot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match (self.bios, self.uefi) {
+            (true, true) => "BIOS and UEFI",
+            (true, false) => "BIOS",
+            (false, true) => "UEFI",
+            (false, false) => "not bootable",
+        })
+    }
+}
+
+/// What an image contains, and how it boots.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImageReport {
+    pub container: Container,
+    pub iso: Option<IsoVolume>,
+    /// The operating system which an ISO9660 image contains, if it describes itself.
+    pub os: Option<OsInfo>,
+    pub partition_table: Option<PartitionTable>,
+    pub partitions: Vec<ImagePartition>,
+    /// How the image boots, which is not known for compressed images.
+    ///
+    /// El Torito boot entries are included, although they only boot optical media, so an
+    /// ISO9660 image which is not hybrid may not boot from a drive.
+    pub boot: Option<Boot>,
+}
+
+impl ImageReport {
+    /// Inspects the image at `path`, which may also be a device, such as an optical drive.
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, ImageError> {
+        let mut file = File::open(path).map_err(|why| ImageError::Open { why })?;
+        Self::from_reader(&mut file)
+    }
+
+    /// Inspects an image which is read from `reader`.
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self, ImageError> {
+        let mut magic = [0u8; 6];
+        let read = read_at(reader, 0, &mut magic).map_err(|why| ImageError::ReadError { why })?;
+        if let Some(compression) = compression_of(&magic[..read]) {
+            return Ok(ImageReport {
+                container: Container::Compressed(compression),
+                iso: None,
+                os: None,
+                partition_table: None,
+                partitions: Vec::new(),
+                boot: None,
+            });
+        }
+
+        let mut boot = Boot::default();
+        let mut os = None;
+        let iso =
+            read_iso(reader, &mut boot, &mut os).map_err(|why| ImageError::ReadError { why })?;
+        let (partition_table, partitions) =
+            read_partitions(reader, &mut boot).map_err(|why| ImageError::ReadError { why })?;
+
+        let container = match (iso.is_some(), partition_table.is_some()) {
+            (true, true) => Container::HybridIso,
+            (true, false) => Container::Iso9660,
+            (false, true) => Container::DiskImage,
+            (false, false) => Container::Unknown,
+        };
+
+        Ok(ImageReport { container, iso, os, partition_table, partitions, boot: Some(boot) })
+    }
+
+    /// Whether the image is an ISO9660 image without an MBR or GPT, which only boots from
+    /// optical media, so that a drive which it is written to will not boot, even if its
+    /// El Torito catalog has boot entries.
+    ///
+    /// Such images are made bootable from a drive by copying their files to a formatted drive.
+    pub fn is_optical_only(&self) -> bool {
+        self.container == Container::Iso9660
+    }
+
+    /// Whether the image is a Windows installer, which must be copied to a formatted drive,
+    /// rather than written to it.
+    pub fn is_windows(&self) -> bool {
+        self.iso.as_ref().map_or(false, |iso| iso.publisher == "MICROSOFT CORPORATION")
+    }
+}
+
+/// Reads the volume descriptors of an ISO9660 image, the boot entries of its El Torito boot
+/// catalog, and the operating system which it describes itself as.
+fn read_iso<R: Read + Seek>(
+    reader: &mut R,
+    boot: &mut Boot,
+    os: &mut Option<OsInfo>,
+) -> std::io::Result<Option<IsoVolume>> {
+    let mut volume = None;
+    let mut descriptor = [0u8; ISO_SECTOR as usize];
+
+    // Images have a handful of descriptors, so a long run is not followed to its end.
+    for index in 0..64 {
+        let position = ISO_DESCRIPTORS + index * ISO_SECTOR;
+        if read_at(reader, position, &mut descriptor)? < descriptor.len()
+            || &descriptor[1..6] != ISO_MAGIC
+        {
+            break;
+        }
+
+        match descriptor[0] {
+            // A boot record.
+            0 if descriptor[7..7 + EL_TORITO.len()] == *EL_TORITO => {
+                read_boot_catalog(reader, u32_le(&descriptor[71..75]), boot)?;
+            }
+            // The primary volume descriptor.
+            1 => {
+                let primary = IsoVolume {
+                    system: iso_string(&descriptor[8..40]),
+                    volume: iso_string(&descriptor[40..72]),
+                    publisher: iso_string(&descriptor[318..446]),
+                    application: iso_string(&descriptor[574..702]),
+                };
+
+                *os = os::detect(reader, &descriptor[156..190], &primary.volume);
+                volume = Some(primary);
+            }
+            // The terminator.
+            255 => break,
+            _ => (),
+        }
+    }
+
+    Ok(volume)
+}
+
+fn read_boot_catalog<R: Read + Seek>(
+    reader: &mut R,
+    sector: u32,
+    boot: &mut Boot,
+) -> std::io::Result<()> {
+    let mut catalog = [0u8; ISO_SECTOR as usize];
+    let read = read_at(reader, u64::from(sector) * ISO_SECTOR, &mut catalog)?;
+
+    // The validation entry is followed by the default entry.
+    if read < catalog.len() || catalog[0] != 1 || catalog[30..32] != [0x55, 0xAA] {
+        return Ok(());
+    }
+
+    let mut bootable = |platform: u8| match platform {
+        PLATFORM_BIOS => boot.bios = true,
+        PLATFORM_EFI => boot.uefi = true,
+        _ => (),
+    };
+
+    if catalog[32] == 0x88 {
+        bootable(catalog[1]);
+    }
+
+    // Section headers are followed by their entries, and each entry by its extensions.
+    let mut offset = 64;
+    while offset + 32 <= catalog.len() {
+        let header = catalog[offset];
+        if header != 0x90 && header != 0x91 {
+            break;
+        }
+
+        let platform = catalog[offset + 1];
+        let entries = u16::from_le_bytes([catalog[offset + 2], catalog[offset + 3]]);
+        offset += 32;
+
+        for _ in 0..entries {
+            if offset + 32 > catalog.len() {
+                break;
+            }
+
+            if catalog[offset] == 0x88 {
+                bootable(platform);
+            }
+
+            offset += 32;
+            while offset + 32 <= catalog.len() && catalog[offset] == 0x44 {
+                offset += 32;
+            }
+        }
+
+        if header == 0x91 {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the MBR, and the GPT which it protects, if it has one.
+fn read_partitions<R: Read + Seek>(
+    reader: &mut R,
+    boot: &mut Boot,
+) -> std::io::Result<(Option<PartitionTable>, Vec<ImagePartition>)> {
+    let mut mbr = [0u8; SECTOR as usize];
+    if read_at(reader, 0, &mut mbr)? < mbr.len() || mbr[510..512] != [0x55, 0xAA] {
+        return Ok((None, Vec::new()));
+    }
+
+    let mut partitions = Vec::new();
+    for (index, entry) in mbr[446..510].chunks(16).enumerate() {
+        let kind = entry[4];
+        let sectors = u32_le(&entry[12..16]);
+        if kind == 0 || sectors == 0 {
+            continue;
+        }
+
+        partitions.push(ImagePartition {
+            number: index as u32 + 1,
+            kind: PartitionType::Mbr(kind),
+            start: u64::from(u32_le(&entry[8..12])) * SECTOR,
+            size: u64::from(sectors) * SECTOR,
+            name: None,
+        });
+    }
+
+    // An MBR signature alone, without partitions or boot code, is not a partition table.
+    let boot_code = mbr[..440].iter().any(|&byte| byte != 0);
+    if partitions.is_empty() && !boot_code {
+        return Ok((None, Vec::new()));
+    }
+
+    // BIOS firmware runs the MBR's boot code.
+    boot.bios |= boot_code;
+
+    let mut table = PartitionTable::Mbr;
+    if partitions.iter().any(|partition| partition.kind == PartitionType::Mbr(GPT_PROTECTIVE)) {
+        // The GPT is in the second logical block, which is usually 512 bytes.
+        for &block in &[SECTOR, 4096] {
+            if let Some(gpt) = read_gpt(reader, block)? {
+                table = PartitionTable::Gpt;
+                partitions = gpt;
+                break;
+            }
+        }
+    }
+
+    for partition in &partitions {
+        match partition.kind {
+            PartitionType::Gpt(ref guid) if guid == BIOS_BOOT_GUID => boot.bios = true,
+            ref kind if kind.is_efi_system() => boot.uefi = true,
+            _ => (),
+        }
+    }
+
+    Ok((Some(table), partitions))
+}
+
+fn read_gpt<R: Read + Seek>(
+    reader: &mut R,
+    block: u64,
+) -> std::io::Result<Option<Vec<ImagePartition>>> {
+    let mut header = [0u8; 92];
+    if read_at(reader, block, &mut header)? < header.len() || &header[..8] != GPT_MAGIC {
+        return Ok(None);
+    }
+
+    let entries_lba = u64_le(&header[72..80]);
+    let count = u32_le(&header[80..84]).min(256);
+    let entry_size = u32_le(&header[84..88]);
+    if !(128..=1024).contains(&entry_size) {
+        return Ok(None);
+    }
+
+    // Offsets which do not fit in 64 bits are not from a valid table.
+    let entries_offset = match entries_lba.checked_mul(block) {
+        Some(offset) => offset,
+        None => return Ok(None),
+    };
+
+    let mut entries = vec![0u8; (count * entry_size) as usize];
+    let read = read_at(reader, entries_offset, &mut entries)?;
+
+    let partitions: Option<Vec<ImagePartition>> = entries[..read]
+        .chunks_exact(entry_size as usize)
+        .enumerate()
+        .filter(|(_, entry)| entry[..16].iter().any(|&byte| byte != 0))
+        .map(|(index, entry)| {
+            let first = u64_le(&entry[32..40]);
+            let last = u64_le(&entry[40..48]);
+            let name: Vec<u16> = entry[56..128]
+                .chunks_exact(2)
+                .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                .take_while(|&unit| unit != 0)
+                .collect();
+
+            Some(ImagePartition {
+                number: index as u32 + 1,
+                kind: PartitionType::Gpt(guid(&entry[..16])),
+                start: first.checked_mul(block)?,
+                size: last.saturating_sub(first).checked_add(1)?.checked_mul(block)?,
+                name: Some(String::from_utf16_lossy(&name)).filter(|name| !name.is_empty()),
+            })
+        })
+        .collect();
+
+    Ok(partitions)
+}
+
+/// Formats a GUID, whose first three fields are stored in little endian.
+fn guid(bytes: &[u8]) -> String {
+    format!(
+        "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
+        u32_le(&bytes[..4]),
+        u16::from_le_bytes([bytes[4], bytes[5]]),
+        u16::from_le_bytes([bytes[6], bytes[7]]),
+        bytes[8],
+        bytes[9],
+        bytes[10],
+        bytes[11],
+        bytes[12],
+        bytes[13],
+        bytes[14],
+        bytes[15]
+    )
+}
+
+/// ISO9660 strings are padded with spaces.
+fn iso_string(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).trim_end_matches(&[' ', '\0'][..]).to_owned()
+}
+
+fn u64_le(bytes: &[u8]) -> u64 {
+    let mut le = [0u8; 8];
+    le.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(le)
+}
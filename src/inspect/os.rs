@@ -0,0 +1,284 @@
+//! Naming the operating system which an ISO9660 image installs, from the files which
+//! distributions place in it to describe themselves.
+
+use crate::image::{read_at, u32_le};
+use std::{
+    fmt,
+    io::{Read, Seek},
+};
+
+const ISO_SECTOR: u64 = 2048;
+
+/// Directories and descriptive files are small, so anything larger is not read.
+const MAX_FILE_SIZE: u32 = 1024 * 1024;
+
+const ARCHITECTURES: &[&str] = &[
+    "amd64", "x86_64", "i386", "i686", "arm64", "aarch64", "armhf", "ppc64el", "ppc64le", "s390x",
+    "riscv64",
+];
+
+/// The operating system which an image contains.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OsInfo {
+    pub name: String,
+    pub version: Option<String>,
+    pub arch: Option<String>,
+}
+
+impl fmt::Display for OsInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name)?;
+        if let Some(ref version) = self.version {
+            write!(f, " {}", version)?;
+        }
+
+        if let Some(ref arch) = self.arch {
+            write!(f, " ({})", arch)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl OsInfo {
+    /// Fills in what this lacks from another description of the same system.
+    fn merge(mut self, other: OsInfo) -> Self {
+        self.version = self.version.or(other.version);
+        self.arch = self.arch.or(other.arch);
+        self
+    }
+}
+
+/// Names the system from `.disk/info`, which Debian and Ubuntu derivatives write, `.treeinfo`,
+/// which Fedora and RHEL derivatives write, or an `etc/os-release` in the volume, falling back
+/// to the volume ID. Later sources fill in the version and architecture if earlier ones omit
+/// them.
+///
+/// `root` is the root directory record of the primary volume descriptor.
+pub(super) fn detect<R: Read + Seek>(reader: &mut R, root: &[u8], volume: &str) -> Option<OsInfo> {
+    let root = Record::parse(root)?;
+
+    let mut file = |path: &[&str]| {
+        let contents = read_file(reader, &root, path)?;
+        Some(String::from_utf8_lossy(&contents).into_owned())
+    };
+
+    let found = vec![
+        file(&[".disk", "info"]).and_then(|info| describe(info.lines().next()?, false)),
+        file(&[".treeinfo"]).and_then(|treeinfo| parse_treeinfo(&treeinfo)),
+        file(&["etc", "os-release"]).and_then(|os_release| parse_os_release(&os_release)),
+        describe(volume, true).filter(|os| os.version.is_some()),
+    ];
+
+    found.into_iter().flatten().reduce(OsInfo::merge)
+}
+
+/// Describes a system from text such as `Pop!_OS 22.04 LTS "Jammy Jellyfish" - Release amd64`,
+/// where the name is followed by the version, or a volume ID such as `Fedora-WS-Live-38-1-6`,
+/// whose words may also be separated by dashes.
+fn describe(text: &str, volume_id: bool) -> Option<OsInfo> {
+    let words: Vec<&str> = text
+        .split(|c: char| c.is_whitespace() || (volume_id && c == '-'))
+        .map(|word| word.trim_matches(|c: char| !c.is_alphanumeric() && c != '!' && c != '_'))
+        .filter(|word| !word.is_empty())
+        .collect();
+
+    let arch = words
+        .iter()
+        .find(|word| ARCHITECTURES.iter().any(|arch| word.eq_ignore_ascii_case(arch)))
+        .map(|arch| arch.to_ascii_lowercase());
+
+    let version = words.iter().position(|word| word.starts_with(|c: char| c.is_ascii_digit()));
+    let name = match version {
+        Some(0) | None => text.split(&['"', '('][..]).next()?.split(" - ").next()?,
+        Some(index) => {
+            return Some(OsInfo {
+                name: words[..index].join(" "),
+                version: Some(words[index].to_owned()),
+                arch,
+            })
+        }
+    };
+
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
+    }
+
+    Some(OsInfo { name: name.to_owned(), version: None, arch })
+}
+
+/// `.treeinfo` is an INI file, whose `[release]` or `[general]` section names the system, and
+/// whose `[tree]` or `[general]` section names the architecture.
+fn parse_treeinfo(contents: &str) -> Option<OsInfo> {
+    let mut section = "";
+    let mut values = Vec::new();
+    for line in contents.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            section = name;
+        } else if let Some((key, value)) = line.split_once('=') {
+            values.push((section, key.trim(), value.trim()));
+        }
+    }
+
+    let value = |candidates: &[(&str, &str)]| {
+        candidates.iter().find_map(|&(section, key)| {
+            values
+                .iter()
+                .find(|&&(s, k, v)| s == section && k == key && !v.is_empty())
+                .map(|&(_, _, value)| value.to_owned())
+        })
+    };
+
+    Some(OsInfo {
+        name: value(&[("release", "name"), ("general", "family"), ("general", "name")])?,
+        version: value(&[("release", "version"), ("general", "version")]),
+        arch: value(&[("tree", "arch"), ("general", "arch")]),
+    })
+}
+
+fn parse_os_release(contents: &str) -> Option<OsInfo> {
+    let value = |key: &str| {
+        contents.lines().find_map(|line| {
+            let value = line.trim().strip_prefix(key)?.strip_prefix('=')?;
+            let value = value.trim_matches(&['"', '\''][..]);
+            if value.is_empty() {
+                None
+            } else {
+                Some(value.to_owned())
+            }
+        })
+    };
+
+    Some(OsInfo {
+        name: value("NAME")?,
+        version: value("VERSION_ID").or_else(|| value("VERSION")),
+        arch: None,
+    })
+}
+
+/// A directory record, which locates a file or directory.
+struct Record {
+    extent: u32,
+    size: u32,
+    directory: bool,
+}
+
+impl Record {
+    fn parse(record: &[u8]) -> Option<Self> {
+        if record.len() < 34 {
+            return None;
+        }
+
+        Some(Record {
+            extent: u32_le(&record[2..6]),
+            size: u32_le(&record[10..14]),
+            directory: record[25] & 0x02 != 0,
+        })
+    }
+}
+
+/// Reads the file at `path`, below the `root` directory.
+fn read_file<R: Read + Seek>(reader: &mut R, root: &Record, path: &[&str]) -> Option<Vec<u8>> {
+    let mut contents = read_extent(reader, root)?;
+    let mut directory = true;
+
+    for component in path {
+        if !directory {
+            return None;
+        }
+
+        let record = find(&contents, component)?;
+        contents = read_extent(reader, &record)?;
+        directory = record.directory;
+    }
+
+    if directory {
+        None
+    } else {
+        Some(contents)
+    }
+}
+
+fn read_extent<R: Read + Seek>(reader: &mut R, record: &Record) -> Option<Vec<u8>> {
+    if record.size > MAX_FILE_SIZE {
+        return None;
+    }
+
+    let mut contents = vec![0u8; record.size as usize];
+    let read = read_at(reader, u64::from(record.extent) * ISO_SECTOR, &mut contents).ok()?;
+    contents.truncate(read);
+    Some(contents)
+}
+
+/// Finds `name` in the records of a directory.
+fn find(directory: &[u8], name: &str) -> Option<Record> {
+    let mut offset = 0;
+    while offset < directory.len() {
+        let length = usize::from(directory[offset]);
+
+        // Records do not cross sectors, which are padded with zeroes after their last record.
+        if length == 0 {
+            offset = (offset / ISO_SECTOR as usize + 1) * ISO_SECTOR as usize;
+            continue;
+        }
+
+        let record = directory.get(offset..offset + length)?;
+        offset += length;
+
+        let name_length = usize::from(*record.get(32)?);
+        let iso_name = record.get(33..33 + name_length)?;
+        let rock_ridge = rock_ridge_name(record, 33 + name_length + (1 - name_length % 2));
+
+        let matches = match rock_ridge {
+            Some(rock_ridge) => rock_ridge == name.as_bytes(),
+            None => iso_name_matches(iso_name, name),
+        };
+
+        if matches {
+            return Record::parse(record);
+        }
+    }
+
+    None
+}
+
+/// The name of a record from its Rock Ridge `NM` entries, which preserve the original name of
+/// the file.
+fn rock_ridge_name(record: &[u8], mut offset: usize) -> Option<Vec<u8>> {
+    let mut name = Vec::new();
+    while offset + 4 <= record.len() {
+        let length = usize::from(record[offset + 2]);
+        if length < 4 || offset + length > record.len() {
+            break;
+        }
+
+        if &record[offset..offset + 2] == b"NM" && length > 5 {
+            name.extend_from_slice(&record[offset + 5..offset + length]);
+        }
+
+        offset += length;
+    }
+
+    if name.is_empty() {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+/// Plain ISO9660 names are uppercase, are followed by a version, as in `INFO.;1`, and replace
+/// characters which they may not contain, such as leading dots, with underscores.
+fn iso_name_matches(iso_name: &[u8], name: &str) -> bool {
+    let iso_name = String::from_utf8_lossy(iso_name);
+    let iso_name = iso_name.split(';').next().unwrap_or("").trim_end_matches('.');
+
+    let normalize = |name: &str| -> String {
+        name.chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+            .collect()
+    };
+
+    let iso_name = normalize(iso_name);
+    iso_name == normalize(name) || iso_name == normalize(name.trim_start_matches('.'))
+}
@@ -7,14 +7,43 @@ extern crate thiserror;
 
 pub extern crate mnt;
 
+pub mod checksums;
 pub mod codec;
+pub mod hash;
+pub mod http;
+pub mod inspect;
+pub mod service;
+pub mod signature;
 
+mod backend;
+mod control;
+mod device;
+mod holders;
+mod image;
+mod model;
+mod remote;
+mod report;
+mod safety;
 mod task;
+mod udisks;
+mod unmount;
 
-pub use self::task::{Progress, Task};
+pub use self::backend::{Backend, BackendKind, UnknownBackend};
+pub use self::control::{CancellableFile, TaskControl};
+pub use self::device::{Bus, Device, Partition};
+pub use self::holders::{device_holders, device_holders_in, Holder, HolderKind};
+pub use self::image::{Compression, ImageSize};
+pub use self::model::{
+    DeviceProgress, DeviceStatus, ProgressChange, ProgressModel, ReportedDigest,
+};
+pub use self::remote::{is_url, RemoteError, RemoteImage};
+pub use self::report::MessageProgress;
+pub use self::safety::{check_device, check_device_in, SafetyPolicy, DEFAULT_MAX_SIZE};
+pub use self::task::{ImageSource, Progress, Task};
+pub use self::udisks::{UDisks2, UDisksBlock, UDisksError};
+pub use self::unmount::{swapoff, teardown, unmount, unmount_order, BusyProcess, UnmountFlags};
 
 use anyhow::Context;
-use as_result::MapResult;
 use async_std::{
     fs::{self, File, OpenOptions},
     os::unix::fs::OpenOptionsExt,
@@ -22,11 +51,7 @@ use async_std::{
 };
 use futures::{executor, prelude::*};
 use mnt::MountEntry;
-use std::{
-    io,
-    os::unix::{ffi::OsStrExt, fs::FileTypeExt},
-    process::Command,
-};
+use std::{io, os::unix::fs::FileTypeExt};
 use usb_disk_probe::stream::UsbDiskProbe;
 
 #[derive(Debug, Error)]
@@ -49,6 +74,12 @@ pub enum ImageError {
 pub enum DiskError {
     #[error("failed to fetch devices from USB device stream: {}", _0)]
     DeviceStream(anyhow::Error),
+    #[error("unable to connect to UDisks2: {}", _0)]
+    UDisksConnect(UDisksError),
+    #[error("failed to fetch devices from UDisks2: {}", _0)]
+    UDisksEnumerate(UDisksError),
+    #[error("error using disk '{}' through UDisks2: {}", arg.display(), why)]
+    UDisks { arg: Box<Path>, why: UDisksError },
     #[error("unable to open directory at '{}': {}", dir, why)]
     Directory { dir: &'static str, why: io::Error },
     #[error("writing to the device was killed")]
@@ -57,12 +88,30 @@ pub enum DiskError {
     UTF8 { dir: Box<Path> },
     #[error("unable to find disk '{}': {}", disk.display(), why)]
     NoDisk { disk: Box<Path>, why: io::Error },
-    #[error("failed to unmount {}: {}", path.display(), why)]
-    UnmountCommand { path: Box<Path>, why: io::Error },
+    #[error("failed to unmount {}: {}{}", target.display(), why, in_use_by(processes))]
+    Unmount { target: Box<Path>, why: io::Error, processes: Vec<BusyProcess> },
+    #[error("failed to disable swap on {}: {}", device.display(), why)]
+    Swapoff { device: Box<Path>, why: io::Error },
     #[error("error using disk '{}': {} already mounted at {}", arg.display(), source_.display(), dest.display())]
     AlreadyMounted { arg: Box<Path>, source_: Box<Path>, dest: Box<Path> },
+    #[error("unable to determine what is holding disk '{}': {}", arg.display(), why)]
+    Holders { arg: Box<Path>, why: io::Error },
+    #[error("error using disk '{}': {}", arg.display(), holder)]
+    Held { arg: Box<Path>, holder: Holder },
     #[error("'{}' is not a block device", arg.display())]
     NotABlock { arg: Box<Path> },
+    #[error("refusing to write to '{}': it hosts the running system's {} filesystem", arg.display(), mount.display())]
+    SystemDisk { arg: Box<Path>, mount: Box<Path> },
+    #[error("refusing to write to '{}': it hosts the running system's swap", arg.display())]
+    SystemSwap { arg: Box<Path> },
+    #[error("refusing to write to '{}': it is not a removable device", arg.display())]
+    NotRemovable { arg: Box<Path> },
+    #[error("refusing to write to '{}': its size of {} bytes exceeds the limit of {} bytes", arg.display(), size, limit)]
+    TooLarge { arg: Box<Path>, size: u64, limit: u64 },
+    #[error("'{}' is too small for the image: {} bytes short of the {} bytes required", arg.display(), required - size, required)]
+    TooSmall { arg: Box<Path>, size: u64, required: u64 },
+    #[error("unable to read device information of '{}': {}", arg.display(), why)]
+    DeviceInfo { arg: Box<Path>, why: io::Error },
     #[error("unable to get metadata of disk '{}': {}", arg.display(), why)]
     Metadata { arg: Box<Path>, why: io::Error },
     #[error("unable to open disk '{}': {}", disk.display(), why)]
@@ -107,10 +156,21 @@ pub fn get_disk_args(disks: &mut Vec<Box<Path>>) -> Result<(), DiskError> {
     )
 }
 
+/// Canonicalizes and opens each disk in `disk_args` for writing.
+///
+/// Disks which the running system depends upon, which `policy` does not permit, or which
+/// are too small to hold `image`, are refused. Disks that are in use are refused, unless
+/// `unmount` is set, in which case their mounts and swap areas are torn down first.
+///
+/// Devices are torn down and opened with `backend`, so that the UDisks2 backend may be used
+/// without root.
 pub async fn disks_from_args<D: Iterator<Item = Box<Path>>>(
     disk_args: D,
     mounts: &[MountEntry],
-    unmount: bool,
+    unmount: Option<UnmountFlags>,
+    policy: &SafetyPolicy,
+    image: &ImageSize,
+    backend: &Backend,
 ) -> Result<Vec<(Box<Path>, File)>, DiskError> {
     let mut disks = Vec::new();
 
@@ -119,32 +179,6 @@ pub async fn disks_from_args<D: Iterator<Item = Box<Path>>>(
             .await
             .map_err(|why| DiskError::NoDisk { disk: disk_arg.clone(), why })?;
 
-        for mount in mounts {
-            if mount.spec.as_bytes().starts_with(canonical_path.as_os_str().as_bytes()) {
-                if unmount {
-                    eprintln!(
-                        "unmounting '{}': {:?} is mounted at {:?}",
-                        disk_arg.display(),
-                        mount.spec,
-                        mount.file
-                    );
-
-                    Command::new("umount").arg(&mount.spec).status().map_result().map_err(
-                        |why| DiskError::UnmountCommand {
-                            path: PathBuf::from(mount.spec.clone()).into_boxed_path(),
-                            why,
-                        },
-                    )?;
-                } else {
-                    return Err(DiskError::AlreadyMounted {
-                        arg: disk_arg.clone(),
-                        source_: PathBuf::from(mount.spec.clone()).into_boxed_path(),
-                        dest: PathBuf::from(mount.file.clone()).into_boxed_path(),
-                    });
-                }
-            }
-        }
-
         let metadata = canonical_path
             .metadata()
             .await
@@ -154,16 +188,93 @@ pub async fn disks_from_args<D: Iterator<Item = Box<Path>>>(
             return Err(DiskError::NotABlock { arg: disk_arg.clone() });
         }
 
-        let disk = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .custom_flags(libc::O_SYNC)
-            .open(&canonical_path)
-            .await
-            .map_err(|why| DiskError::Open { disk: disk_arg.clone(), why })?;
+        let device = Device::new(&canonical_path, mounts)
+            .map_err(|why| DiskError::DeviceInfo { arg: disk_arg.clone(), why })?;
+
+        check_device(&disk_arg, &device, mounts, policy)?;
+        check_capacity(&disk_arg, &device, image)?;
+
+        let holders = device_holders(&canonical_path, mounts)
+            .map_err(|why| DiskError::Holders { arg: disk_arg.clone(), why })?;
+
+        let udisks = match backend {
+            Backend::Direct => None,
+            Backend::UDisks2(udisks) => Some((
+                udisks,
+                udisks
+                    .block(canonical_path.as_ref())
+                    .map_err(|why| DiskError::UDisks { arg: disk_arg.clone(), why })?,
+            )),
+        };
+
+        if let Some(holder) = holders.first() {
+            match (unmount, udisks.as_ref()) {
+                (Some(flags), None) => teardown(&disk_arg, &holders, mounts, flags)?,
+                (Some(flags), Some((udisks, block))) => {
+                    if let Some(holder) = holders.iter().find(|holder| {
+                        !matches!(holder.kind, HolderKind::Mount(_) | HolderKind::Swap)
+                    }) {
+                        return Err(DiskError::Held {
+                            arg: disk_arg.clone(),
+                            holder: holder.clone(),
+                        });
+                    }
+
+                    udisks
+                        .teardown(block, flags.force || flags.lazy)
+                        .map_err(|why| DiskError::UDisks { arg: disk_arg.clone(), why })?;
+                }
+                (None, _) => {
+                    return Err(match holder.kind {
+                        HolderKind::Mount(ref target) => DiskError::AlreadyMounted {
+                            arg: disk_arg.clone(),
+                            source_: PathBuf::from(holder.device.to_path_buf()).into_boxed_path(),
+                            dest: PathBuf::from(target.to_path_buf()).into_boxed_path(),
+                        },
+                        _ => DiskError::Held { arg: disk_arg.clone(), holder: holder.clone() },
+                    });
+                }
+            }
+        }
+
+        let disk = match udisks {
+            Some((udisks, block)) => udisks
+                .open(&block)
+                .map(File::from)
+                .map_err(|why| DiskError::UDisks { arg: disk_arg.clone(), why })?,
+            None => OpenOptions::new()
+                .read(true)
+                .write(true)
+                .custom_flags(libc::O_SYNC)
+                .open(&canonical_path)
+                .await
+                .map_err(|why| DiskError::Open { disk: disk_arg.clone(), why })?,
+        };
 
         disks.push((canonical_path.into_boxed_path(), disk));
     }
 
     Ok(disks)
 }
+
+/// Refuses `device` if it is too small to hold `image`, as measured by `ImageSize::required`.
+///
+/// If the size of a compressed image's data is not exactly known, only the size of its file
+/// is checked.
+pub fn check_capacity(arg: &Path, device: &Device, image: &ImageSize) -> Result<(), DiskError> {
+    let required = image.required();
+    if device.size < required {
+        return Err(DiskError::TooSmall { arg: arg.into(), size: device.size, required });
+    }
+
+    Ok(())
+}
+
+fn in_use_by(processes: &[BusyProcess]) -> String {
+    if processes.is_empty() {
+        return String::new();
+    }
+
+    let processes: Vec<String> = processes.iter().map(ToString::to_string).collect();
+    [" (in use by ", &processes.join(", "), ")"].concat()
+}
@@ -0,0 +1,302 @@
+//! A queryable model of flashing progress, built from the messages of the IPC stream.
+
+use crate::codec::{Message, Outcome, Phase};
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// The status of a device in the `ProgressModel`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeviceStatus {
+    Active,
+    Succeeded,
+    Failed(String),
+    Cancelled,
+}
+
+/// The progress of a single device, as last reported by the IPC stream.
+#[derive(Clone, Debug)]
+pub struct DeviceProgress {
+    pub path: PathBuf,
+    pub phase: Phase,
+    /// Bytes processed in the current phase.
+    pub written: u64,
+    /// Bytes to process in each phase, which is the size of the image.
+    pub total: u64,
+    pub status: DeviceStatus,
+    /// The most recent rate reported by the stream, in bytes per second.
+    reported_rate: Option<u64>,
+    phase_started: Instant,
+    last_update: Instant,
+}
+
+impl DeviceProgress {
+    fn new(path: PathBuf, total: u64, now: Instant) -> Self {
+        Self {
+            path,
+            phase: Phase::Writing,
+            written: 0,
+            total,
+            status: DeviceStatus::Active,
+            reported_rate: None,
+            phase_started: now,
+            last_update: now,
+        }
+    }
+
+    /// Progress through the current phase, from `0.0` to `100.0`.
+    pub fn percentage(&self) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            (self.written.min(self.total) as f64 / self.total as f64) * 100.0
+        }
+    }
+
+    /// Bytes per second being processed in the current phase.
+    ///
+    /// This is the rate reported by version 2 streams, or otherwise the average rate since the
+    /// phase began.
+    pub fn rate(&self) -> Option<u64> {
+        if self.status != DeviceStatus::Active {
+            return None;
+        }
+
+        self.reported_rate.or_else(|| {
+            let elapsed = self.last_update.duration_since(self.phase_started).as_secs_f64();
+            if elapsed > 0.0 && self.written > 0 {
+                Some((self.written as f64 / elapsed) as u64)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// The estimated time remaining until the current phase is complete.
+    pub fn eta(&self) -> Option<Duration> {
+        match self.rate() {
+            Some(rate) if rate > 0 => {
+                let remaining = self.total.saturating_sub(self.written);
+                Some(Duration::from_secs_f64(remaining as f64 / rate as f64))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.status != DeviceStatus::Active
+    }
+
+    fn enter(&mut self, phase: Phase, now: Instant) {
+        self.phase = phase;
+        self.written = 0;
+        self.reported_rate = None;
+        self.phase_started = now;
+        self.last_update = now;
+    }
+}
+
+/// A digest reported by the IPC stream.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReportedDigest {
+    /// The device that the digest was computed from, or `None` for the image.
+    pub device: Option<PathBuf>,
+    pub algorithm: String,
+    pub digest: String,
+}
+
+/// What a message changed in the `ProgressModel`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProgressChange {
+    /// The size of the image is now known.
+    Size(u64),
+    /// A device was added.
+    Added(PathBuf),
+    /// The bytes processed, or rate, of a device changed.
+    Progress(PathBuf),
+    /// A device entered a new phase.
+    Phase(PathBuf),
+    /// A device finished, failed, or was cancelled.
+    Status(PathBuf),
+    /// A digest was reported.
+    Digest,
+}
+
+/// Consumes the `Message` stream of the IPC protocol, and keeps the state of every device.
+///
+/// Both version 1 and version 2 streams are understood, so front ends may bind to this model
+/// rather than interpreting raw messages themselves.
+///
+/// ```ignore
+/// let mut model = ProgressModel::default();
+/// while let Some(message) = stream.next().await {
+///     if let Some(ProgressChange::Progress(path)) = model.apply(&message?) {
+///         let device = model.device(&path).unwrap();
+///         println!("{}: {:.1}%", path.display(), device.percentage());
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ProgressModel {
+    version: Option<u32>,
+    size: Option<u64>,
+    devices: Vec<DeviceProgress>,
+    digests: Vec<ReportedDigest>,
+}
+
+impl ProgressModel {
+    /// Updates the model with the next message of the stream.
+    pub fn apply(&mut self, message: &Message) -> Option<ProgressChange> {
+        self.apply_at(message, Instant::now())
+    }
+
+    /// Updates the model with a message that was received at `now`.
+    pub fn apply_at(&mut self, message: &Message, now: Instant) -> Option<ProgressChange> {
+        match message {
+            Message::Hello { version } => {
+                self.version = Some(*version);
+                None
+            }
+            Message::Size(size) => {
+                self.size = Some(*size);
+                for device in &mut self.devices {
+                    device.total = *size;
+                }
+
+                Some(ProgressChange::Size(*size))
+            }
+            Message::Device(path) => {
+                if self.device(path).is_some() {
+                    return None;
+                }
+
+                self.device_mut(path, now);
+                Some(ProgressChange::Added(path.clone()))
+            }
+            Message::Set(path, written) => {
+                let device = self.device_mut(path, now);
+                device.written = *written;
+                device.last_update = now;
+                Some(ProgressChange::Progress(path.clone()))
+            }
+            Message::Rate(path, rate) => {
+                self.device_mut(path, now).reported_rate = Some(*rate);
+                Some(ProgressChange::Progress(path.clone()))
+            }
+            Message::Phase(path, phase) => {
+                let device = self.device_mut(path, now);
+                if device.phase == *phase && device.written == 0 {
+                    return None;
+                }
+
+                device.enter(*phase, now);
+                Some(ProgressChange::Phase(path.clone()))
+            }
+            Message::Message(path, message) => {
+                let (kind, message) = match message.find(' ') {
+                    Some(pos) => (&message[..pos], &message[pos + 1..]),
+                    None => (message.as_str(), ""),
+                };
+
+                let device = self.device_mut(path, now);
+                match kind {
+                    "E" => fail(device, DeviceStatus::Failed(message.into())),
+                    "C" => fail(device, DeviceStatus::Cancelled),
+                    _ => match Phase::from_kind(kind) {
+                        Some(phase) if phase != device.phase => {
+                            device.enter(phase, now);
+                            Some(ProgressChange::Phase(path.clone()))
+                        }
+                        _ => None,
+                    },
+                }
+            }
+            Message::Error { device: path, message, .. } => {
+                fail(self.device_mut(path, now), DeviceStatus::Failed(message.clone()))
+            }
+            Message::Result(path, outcome) => {
+                let status = match outcome {
+                    Outcome::Success => DeviceStatus::Succeeded,
+                    Outcome::Failure(why) => DeviceStatus::Failed(why.clone()),
+                    Outcome::Cancelled => DeviceStatus::Cancelled,
+                };
+
+                let device = self.device_mut(path, now);
+                if device.status == status {
+                    return None;
+                }
+
+                device.status = status;
+                Some(ProgressChange::Status(path.clone()))
+            }
+            Message::Finished(path) => {
+                let device = self.device_mut(path, now);
+                if device.is_finished() {
+                    return None;
+                }
+
+                device.status = DeviceStatus::Succeeded;
+                Some(ProgressChange::Status(path.clone()))
+            }
+            Message::Digest { device, algorithm, digest } => {
+                self.digests.push(ReportedDigest {
+                    device: device.clone(),
+                    algorithm: algorithm.clone(),
+                    digest: digest.clone(),
+                });
+
+                Some(ProgressChange::Digest)
+            }
+        }
+    }
+
+    /// The protocol version of the stream, which is 1 until a `Hello` message is received.
+    pub fn version(&self) -> u32 {
+        self.version.unwrap_or(1)
+    }
+
+    /// The size of the image being flashed, once it is known.
+    pub fn size(&self) -> Option<u64> {
+        self.size
+    }
+
+    /// Every device, in the order that they were announced.
+    pub fn devices(&self) -> &[DeviceProgress] {
+        &self.devices
+    }
+
+    pub fn device(&self, path: &Path) -> Option<&DeviceProgress> {
+        self.devices.iter().find(|device| device.path == path)
+    }
+
+    pub fn digests(&self) -> &[ReportedDigest] {
+        &self.digests
+    }
+
+    /// Whether every device has finished, failed, or been cancelled.
+    pub fn is_finished(&self) -> bool {
+        !self.devices.is_empty() && self.devices.iter().all(DeviceProgress::is_finished)
+    }
+
+    /// The device at `path`, which is added if the stream did not announce it.
+    fn device_mut(&mut self, path: &Path, now: Instant) -> &mut DeviceProgress {
+        match self.devices.iter().position(|device| device.path == path) {
+            Some(pos) => &mut self.devices[pos],
+            None => {
+                let total = self.size.unwrap_or(0);
+                self.devices.push(DeviceProgress::new(path.to_path_buf(), total, now));
+                self.devices.last_mut().expect("device was just added")
+            }
+        }
+    }
+}
+
+fn fail(device: &mut DeviceProgress, status: DeviceStatus) -> Option<ProgressChange> {
+    if device.status == status {
+        return None;
+    }
+
+    device.status = status;
+    Some(ProgressChange::Status(device.path.clone()))
+}
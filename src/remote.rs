@@ -0,0 +1,249 @@
+//! Streaming images from HTTP and HTTPS URLs, so that they are written to devices while they
+//! are downloaded, rather than being saved to disk first.
+
+use crate::hash::{Checksum, Hasher};
+use std::{
+    io::{self, Read, Seek, SeekFrom},
+    thread,
+    time::Duration,
+};
+
+/// How many times a download is resumed, without receiving anything in between, before the
+/// image is considered unreadable.
+const MAX_RETRIES: u32 = 5;
+
+/// How long to wait before the first attempt to resume, which grows with each retry.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// How long a stalled connection is waited on before the download is resumed.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Error)]
+#[cfg_attr(rustfmt, rustfmt_skip)]
+pub enum RemoteError {
+    #[error("unable to download '{}': {}", url, why)]
+    Request { url: String, why: Box<ureq::Error> },
+}
+
+/// Whether `image` is the URL of a remote image, rather than the path of a file.
+pub fn is_url(image: &str) -> bool {
+    image.starts_with("http://") || image.starts_with("https://")
+}
+
+/// An image which is streamed from an HTTP or HTTPS URL.
+///
+/// Reads which fail, or connections which close early, are resumed from where they stopped
+/// with a range request. Seeking is also performed with range requests, so that
+/// `ImageSize::from_reader` may inspect the image, and a `Task` may validate devices against
+/// it, at the cost of downloading the image again.
+///
+/// This is a blocking reader, which may be given to a `Task` with `futures::io::AllowStdIo`.
+pub struct RemoteImage {
+    url: String,
+    agent: ureq::Agent,
+    body: Option<Box<dyn Read + Send + Sync>>,
+    position: u64,
+    size: Option<u64>,
+    verification: Option<Verification>,
+}
+
+/// The checksums which the image is verified against, once it has been read through.
+struct Verification {
+    checksums: Vec<Checksum>,
+    hasher: Hasher,
+    /// How much of the image, from the start, has been hashed.
+    hashed: u64,
+}
+
+impl Verification {
+    fn restart(&mut self) {
+        let algorithms: Vec<_> = self.checksums.iter().map(Checksum::algorithm).collect();
+        self.hasher = Hasher::new(&algorithms);
+        self.hashed = 0;
+    }
+}
+
+impl RemoteImage {
+    /// Requests the image at `url`, which begins the download.
+    pub fn open(url: &str) -> Result<Self, RemoteError> {
+        let agent = ureq::AgentBuilder::new().timeout_read(READ_TIMEOUT).build();
+        let response = agent
+            .get(url)
+            .call()
+            .map_err(|why| RemoteError::Request { url: url.into(), why: Box::new(why) })?;
+
+        let size = response.header("Content-Length").and_then(|size| size.parse().ok());
+
+        Ok(Self {
+            url: url.into(),
+            agent,
+            body: Some(Box::new(response.into_reader())),
+            position: 0,
+            size,
+            verification: None,
+        })
+    }
+
+    /// Verifies the image against a checksum, which may be given more than once to verify
+    /// several checksums.
+    ///
+    /// The image is hashed as it is read from the start, and the read which reaches the end
+    /// fails with `InvalidData` if a checksum does not match. The error wraps the
+    /// `ChecksumMismatch`, which `io::Error::get_ref` returns.
+    pub fn with_checksum(mut self, checksum: Checksum) -> Self {
+        let mut verification = self.verification.take().unwrap_or_else(|| Verification {
+            checksums: Vec::new(),
+            hasher: Hasher::new(&[]),
+            hashed: 0,
+        });
+
+        verification.checksums.push(checksum);
+        verification.restart();
+        self.verification = Some(verification);
+        self
+    }
+
+    /// The size of the image, if the server reported it.
+    pub fn size(&self) -> Option<u64> {
+        self.size
+    }
+
+    /// The body of the response which continues from the current position, which is
+    /// requested if the previous response was dropped.
+    fn body(&mut self) -> io::Result<&mut Box<dyn Read + Send + Sync>> {
+        if self.body.is_none() {
+            let mut request = self.agent.get(&self.url);
+            if self.position > 0 {
+                request = request.set("Range", &format!("bytes={}-", self.position));
+            }
+
+            let response = request.call().map_err(|why| {
+                io::Error::new(io::ErrorKind::Other, format!("unable to download: {}", why))
+            })?;
+
+            if self.position > 0 && response.status() != 206 {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "the server does not support resuming downloads",
+                ));
+            }
+
+            if self.size.is_none() && self.position == 0 {
+                self.size = response.header("Content-Length").and_then(|size| size.parse().ok());
+            }
+
+            self.body = Some(Box::new(response.into_reader()));
+        }
+
+        Ok(self.body.as_mut().expect("body was requested"))
+    }
+
+    fn remaining(&self) -> Option<u64> {
+        self.size.map(|size| size.saturating_sub(self.position))
+    }
+
+    /// Hashes data which was read at `start`, and verifies the checksum at the end.
+    fn hash(&mut self, start: u64, data: &[u8]) -> io::Result<()> {
+        let verification = match self.verification.as_mut() {
+            Some(verification) => verification,
+            None => return Ok(()),
+        };
+
+        // Only a read through from the start is hashed, so that the image may be inspected
+        // before it is written.
+        if start == 0 {
+            verification.restart();
+        }
+
+        if start != verification.hashed {
+            return Ok(());
+        }
+
+        if !data.is_empty() {
+            verification.hasher.update(data);
+            verification.hashed += data.len() as u64;
+            return Ok(());
+        }
+
+        let verification = self.verification.take().expect("verification is set");
+        let digests = verification.hasher.finish();
+        for checksum in &verification.checksums {
+            checksum
+                .verify(&digests)
+                .map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Read for RemoteImage {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let start = self.position;
+        let mut retries = 0;
+        let read = loop {
+            if self.remaining() == Some(0) || buf.is_empty() {
+                break 0;
+            }
+
+            let why = match self.body().and_then(|body| body.read(buf)) {
+                Ok(0) if self.remaining().is_some() => io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "the connection closed before the image was received",
+                ),
+                Ok(read) => break read,
+                Err(why) => why,
+            };
+
+            self.body = None;
+            if retries == MAX_RETRIES || why.kind() == io::ErrorKind::Unsupported {
+                return Err(why);
+            }
+
+            retries += 1;
+            thread::sleep(RETRY_DELAY * retries);
+        };
+
+        self.position += read as u64;
+        if !buf.is_empty() {
+            self.hash(start, &buf[..read])?;
+        }
+
+        Ok(read)
+    }
+}
+
+impl Seek for RemoteImage {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(position) => (position, 0),
+            SeekFrom::Current(offset) => (self.position, offset),
+            SeekFrom::End(offset) => match self.size {
+                Some(size) => (size, offset),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "the server did not report the size of the image",
+                    ))
+                }
+            },
+        };
+
+        let position = if offset >= 0 {
+            base.checked_add(offset as u64)
+        } else {
+            base.checked_sub(offset.unsigned_abs())
+        };
+
+        let position = position.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek to an invalid position")
+        })?;
+
+        if position != self.position {
+            self.body = None;
+            self.position = position;
+        }
+
+        Ok(position)
+    }
+}
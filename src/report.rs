@@ -0,0 +1,115 @@
+//! Reporting the progress of a `Task` as messages of the IPC protocol.
+
+use crate::{
+    codec::{Message, Outcome, Phase, PROTOCOL_VERSION},
+    hash::Digests,
+    Progress,
+};
+use futures::channel::mpsc::UnboundedSender;
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+/// A `Progress` which sends the progress of a device as IPC protocol messages.
+///
+/// A stream is written by sending the `preamble`, and the `digests` of the image if it was
+/// verified, followed by every message received from the progress of each device, to a
+/// `PopsicleEncoder`.
+pub struct MessageProgress {
+    path: PathBuf,
+    tx: UnboundedSender<Message>,
+    phase: Phase,
+    error: Option<String>,
+    cancelled: bool,
+    last_rate: Instant,
+    last_written: u64,
+}
+
+impl MessageProgress {
+    pub fn new(path: PathBuf, tx: UnboundedSender<Message>) -> Self {
+        Self {
+            path,
+            tx,
+            phase: Phase::Writing,
+            error: None,
+            cancelled: false,
+            last_rate: Instant::now(),
+            last_written: 0,
+        }
+    }
+
+    /// The messages which begin a stream that flashes an image of `size` bytes to `devices`.
+    pub fn preamble(size: u64, devices: &[PathBuf]) -> Vec<Message> {
+        let mut messages = vec![Message::Hello { version: PROTOCOL_VERSION }, Message::Size(size)];
+        messages.extend(devices.iter().cloned().map(Message::Device));
+        messages.extend(devices.iter().map(|path| Message::Phase(path.clone(), Phase::Writing)));
+        messages
+    }
+
+    /// The messages which report the digests of the image, computed before it was flashed.
+    pub fn digests(digests: &Digests) -> Vec<Message> {
+        digests
+            .iter()
+            .map(|(algorithm, digest)| Message::Digest {
+                device: None,
+                algorithm: algorithm.name().into(),
+                digest: digest.into(),
+            })
+            .collect()
+    }
+
+    fn send(&self, message: Message) {
+        let _ = self.tx.unbounded_send(message);
+    }
+}
+
+impl Progress for MessageProgress {
+    type Device = ();
+
+    fn message(&mut self, _device: &(), kind: &str, message: &str) {
+        self.send(Message::Message(
+            self.path.clone(),
+            if message.is_empty() { kind.into() } else { [kind, " ", message].concat() },
+        ));
+
+        if kind == "C" {
+            self.cancelled = true;
+        } else if kind == "E" {
+            self.error = Some(message.into());
+            self.send(Message::Error {
+                device: self.path.clone(),
+                phase: self.phase,
+                message: message.into(),
+            });
+        } else if let Some(phase) = Phase::from_kind(kind) {
+            self.phase = phase;
+            self.last_rate = Instant::now();
+            self.last_written = 0;
+            self.send(Message::Phase(self.path.clone(), phase));
+        }
+    }
+
+    fn finish(&mut self) {
+        let outcome = match self.error.take() {
+            _ if self.cancelled => Outcome::Cancelled,
+            Some(why) => Outcome::Failure(why),
+            None => Outcome::Success,
+        };
+
+        self.send(Message::Result(self.path.clone(), outcome));
+        self.send(Message::Finished(self.path.clone()));
+    }
+
+    fn set(&mut self, written: u64) {
+        self.send(Message::Set(self.path.clone(), written));
+
+        let elapsed = self.last_rate.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            let rate = written.saturating_sub(self.last_written) as f64 / elapsed.as_secs_f64();
+            self.last_rate = Instant::now();
+            self.last_written = written;
+            self.send(Message::Rate(self.path.clone(), rate as u64));
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! Guards against writing to the disks that the running system depends upon.
+
+use crate::{Bus, Device, DiskError};
+use async_std::path::Path as AsyncPath;
+use mnt::MountEntry;
+use std::{
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+// Relative to the root directory which devices are found in.
+const SYSFS_BLOCK: &str = "sys/class/block";
+const SYSFS_DEV_BLOCK: &str = "sys/dev/block";
+const PROC_SWAPS: &str = "proc/swaps";
+
+/// Mount points whose backing disks must never be written to.
+const SYSTEM_MOUNTS: &[&str] = &["/", "/boot", "/boot/efi"];
+
+/// Devices larger than this are refused by default: 256 GiB.
+pub const DEFAULT_MAX_SIZE: u64 = 256 * 1024 * 1024 * 1024;
+
+/// Which devices may be written to, beyond those that host the running system.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SafetyPolicy {
+    /// Permit writing to devices which are neither removable nor hot-pluggable.
+    pub allow_unsafe: bool,
+    /// The size above which devices are refused, or `None` for no limit.
+    ///
+    /// This applies even when `allow_unsafe` is set.
+    pub max_size: Option<u64>,
+}
+
+impl Default for SafetyPolicy {
+    fn default() -> Self {
+        Self { allow_unsafe: false, max_size: Some(DEFAULT_MAX_SIZE) }
+    }
+}
+
+/// Refuses `device` if the running system depends upon it, or if `policy` does not permit it.
+///
+/// Disks hosting the root filesystem, `/boot`, `/boot/efi`, or the running system's swap are
+/// always refused, including when they only back a device-mapper or RAID device that hosts
+/// them. Active swap on a removable or hot-pluggable device is not the system's, and is left
+/// to `teardown` to disable.
+pub fn check_device(
+    arg: &AsyncPath,
+    device: &Device,
+    mounts: &[MountEntry],
+    policy: &SafetyPolicy,
+) -> Result<(), DiskError> {
+    check_device_in(Path::new("/"), arg, device, mounts, policy)
+}
+
+/// Like `check_device`, but reads sysfs and `/proc/swaps` beneath `root`.
+pub fn check_device_in(
+    root: &Path,
+    arg: &AsyncPath,
+    device: &Device,
+    mounts: &[MountEntry],
+    policy: &SafetyPolicy,
+) -> Result<(), DiskError> {
+    for &target in SYSTEM_MOUNTS {
+        let hosted = mounts
+            .iter()
+            .filter(|mount| Path::new(&mount.file) == Path::new(target))
+            .filter_map(|mount| node_sysfs(root, Path::new(&mount.spec)))
+            .any(|sys| backing_disks(&sys).iter().any(|disk| **disk == *device.name));
+
+        if hosted {
+            return Err(DiskError::SystemDisk {
+                arg: arg.into(),
+                mount: AsyncPath::new(target).into(),
+            });
+        }
+    }
+
+    let hotpluggable = is_hotpluggable(root, device);
+
+    if !hotpluggable {
+        for swap in active_swaps(root) {
+            if backing_disks(&swap).iter().any(|disk| **disk == *device.name) {
+                return Err(DiskError::SystemSwap { arg: arg.into() });
+            }
+        }
+    }
+
+    if !policy.allow_unsafe && !hotpluggable {
+        return Err(DiskError::NotRemovable { arg: arg.into() });
+    }
+
+    if let Some(limit) = policy.max_size {
+        if device.size > limit {
+            return Err(DiskError::TooLarge { arg: arg.into(), size: device.size, limit });
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the device is removable media, or is attached through a hot-pluggable bus.
+///
+/// Of MMC devices, only SD cards are hot-pluggable: eMMC is soldered to the board, and is
+/// often the disk which the system boots from.
+fn is_hotpluggable(root: &Path, device: &Device) -> bool {
+    device.removable
+        || device.bus == Bus::Usb
+        || (device.bus == Bus::Mmc && mmc_type(root, &device.name).as_deref() == Some("SD"))
+}
+
+/// The type of the card behind an MMC block device, such as `SD` or `MMC`.
+fn mmc_type(root: &Path, name: &str) -> Option<String> {
+    let path = root.join(SYSFS_BLOCK).join(name).join("device/type");
+    fs::read_to_string(path).ok().map(|kind| kind.trim().to_owned())
+}
+
+/// The names of the whole disks which ultimately back the block device at `sys`.
+///
+/// Partitions resolve to their parent disk, and device-mapper or RAID devices resolve to
+/// the disks backing each of their slaves.
+fn backing_disks(sys: &Path) -> Vec<Box<str>> {
+    let sys = match fs::canonicalize(sys) {
+        Ok(sys) => sys,
+        Err(_) => return Vec::new(),
+    };
+
+    if sys.join("partition").exists() {
+        return sys.parent().map_or_else(Vec::new, backing_disks);
+    }
+
+    let slaves: Vec<PathBuf> = fs::read_dir(sys.join("slaves"))
+        .map(|slaves| slaves.filter_map(Result::ok).map(|slave| slave.path()).collect())
+        .unwrap_or_default();
+
+    if slaves.is_empty() {
+        sys.file_name()
+            .map(|name| vec![name.to_string_lossy().into_owned().into()])
+            .unwrap_or_default()
+    } else {
+        slaves.iter().flat_map(|slave| backing_disks(slave)).collect()
+    }
+}
+
+/// The sysfs directory of the block device node at `node`, if it is one.
+fn node_sysfs(root: &Path, node: &Path) -> Option<PathBuf> {
+    if !node.is_absolute() {
+        return None;
+    }
+
+    let node = fs::canonicalize(node).ok()?;
+    let sys = root.join(SYSFS_BLOCK).join(node.file_name()?);
+    if sys.exists() {
+        Some(sys)
+    } else {
+        None
+    }
+}
+
+/// The sysfs directories of the block devices backing each active swap area.
+///
+/// Swap files resolve to the block device of the filesystem containing them.
+fn active_swaps(root: &Path) -> Vec<PathBuf> {
+    let swaps = match fs::read_to_string(root.join(PROC_SWAPS)) {
+        Ok(swaps) => swaps,
+        Err(_) => return Vec::new(),
+    };
+
+    swaps
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let path = Path::new(fields.next()?);
+            match fields.next()? {
+                "file" => {
+                    let dev = fs::metadata(path).ok()?.dev();
+                    Some(root.join(SYSFS_DEV_BLOCK).join(device_number(dev)))
+                }
+                _ => node_sysfs(root, path),
+            }
+        })
+        .collect()
+}
+
+/// Formats a device ID as the `major:minor` pair used by sysfs.
+fn device_number(dev: u64) -> String {
+    let major = ((dev >> 8) & 0xfff) | ((dev >> 32) & !0xfff);
+    let minor = (dev & 0xff) | ((dev >> 12) & !0xff);
+    format!("{}:{}", major, minor)
+}
@@ -0,0 +1,460 @@
+//! A D-Bus service through which other applications may list devices, and flash images.
+//!
+//! The service claims `com.system76.Popsicle1`, and exports a manager object at
+//! `/com/system76/Popsicle1` with the following methods:
+//!
+//! - `ListDevices() -> a(sst)`: the path, label, and size of each device which may be flashed.
+//! - `Flash(s image, as devices, a{sv} options) -> o`: starts flashing, and returns the job.
+//!   The options are the booleans `check`, `unmount`, and `force`.
+//! - `Cancel(o job)`: cancels every device of a job.
+//!
+//! Each job is an object at `/com/system76/Popsicle1/Job/N`, with the `Cancel()`,
+//! `CancelDevice(s device)`, `Pause()` and `Resume()` methods. Its progress is emitted as the
+//! `Size(t)`, `Phase(s device, s phase)`, `Progress(s device, t written)`,
+//! `Rate(s device, t bytes_per_second)`, `Error(s device, s phase, s message)` and
+//! `Result(s device, s outcome, s message)` signals, which mirror the messages of the IPC
+//! protocol, followed by `Finished(b success, s error)` once the job is done, after which the
+//! job's object is removed.
+//!
+//! The service does not authorize its callers, and opens images with its own privileges, so it
+//! is only served on the session bus of the user who runs it.
+
+use crate::{
+    codec::{Message, Outcome, Phase},
+    disks_from_args, mnt, Backend, Device, ImageSize, MessageProgress, SafetyPolicy, Task,
+    TaskControl, UnmountFlags,
+};
+use anyhow::Context as _;
+use async_std::{fs::File, path::PathBuf as AsyncPathBuf};
+use dbus::{
+    arg::{PropMap, RefArg},
+    blocking::Connection,
+    channel::{MatchingReceiver, Sender},
+    message::MatchRule,
+    Path as ObjectPath,
+};
+use dbus_crossroads::{Context, Crossroads, IfaceToken, MethodErr};
+use futures::{channel::mpsc as async_mpsc, executor, join, prelude::*};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// The well-known name of the service.
+pub const NAME: &str = "com.system76.Popsicle1";
+/// The path of the manager object.
+pub const PATH: &str = "/com/system76/Popsicle1";
+pub const MANAGER_INTERFACE: &str = "com.system76.Popsicle1.Manager";
+pub const JOB_INTERFACE: &str = "com.system76.Popsicle1.Job";
+
+/// How long the service waits for requests before emitting the signals of running jobs.
+const SIGNAL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A device which may be flashed, as listed by `ListDevices`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ListedDevice {
+    pub path: PathBuf,
+    pub label: String,
+    pub size: u64,
+}
+
+/// Options given to `Flash`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FlashOptions {
+    /// Validate each device after it has been written.
+    pub check: bool,
+    /// Unmount devices which are mounted, rather than refusing them.
+    pub unmount: bool,
+    /// Force unmounting, even if the filesystems are busy.
+    pub force: bool,
+}
+
+impl FlashOptions {
+    fn from_props(options: &PropMap) -> Self {
+        let flag = |key: &str| {
+            options.get(key).and_then(|value| value.0.as_u64()).map_or(false, |value| value != 0)
+        };
+
+        Self { check: flag("check"), unmount: flag("unmount"), force: flag("force") }
+    }
+}
+
+/// Finds and opens the devices which the service flashes.
+pub trait Devices: Send + Sync + 'static {
+    /// Every device which may be flashed.
+    fn list(&self) -> anyhow::Result<Vec<ListedDevice>>;
+
+    /// Opens each device in `paths` for writing an image of the given size.
+    fn open(
+        &self,
+        paths: &[PathBuf],
+        image: &ImageSize,
+        options: FlashOptions,
+    ) -> anyhow::Result<Vec<(PathBuf, File)>>;
+}
+
+/// Finds USB devices with a `Backend`, and opens them after the usual safety checks.
+pub struct SystemDevices {
+    backend: Mutex<Backend>,
+    policy: SafetyPolicy,
+}
+
+impl SystemDevices {
+    pub fn new(backend: Backend, policy: SafetyPolicy) -> Self {
+        Self { backend: Mutex::new(backend), policy }
+    }
+}
+
+impl Devices for SystemDevices {
+    fn list(&self) -> anyhow::Result<Vec<ListedDevice>> {
+        let backend = self.backend.lock().expect("backend lock poisoned");
+        let mut paths = Vec::new();
+        executor::block_on(backend.usb_disks(&mut paths))?;
+
+        let mounts = mnt::get_submounts(Path::new("/")).context("error reading mounts")?;
+        let mut devices = Vec::new();
+        for path in paths {
+            let device = Device::new(&*path, &mounts).with_context(|| {
+                format!("unable to read device information of '{}'", path.display())
+            })?;
+
+            devices.push(ListedDevice {
+                path: device.path.to_path_buf(),
+                label: device.label(),
+                size: device.size,
+            });
+        }
+
+        Ok(devices)
+    }
+
+    fn open(
+        &self,
+        paths: &[PathBuf],
+        image: &ImageSize,
+        options: FlashOptions,
+    ) -> anyhow::Result<Vec<(PathBuf, File)>> {
+        let backend = self.backend.lock().expect("backend lock poisoned");
+        let mounts = mnt::get_submounts(Path::new("/")).context("error reading mounts")?;
+        let unmount = if options.unmount {
+            Some(UnmountFlags { lazy: false, force: options.force })
+        } else {
+            None
+        };
+
+        let args = paths.iter().map(|path| AsyncPathBuf::from(path.clone()).into_boxed_path());
+        let disks = executor::block_on(disks_from_args(
+            args,
+            &mounts,
+            unmount,
+            &self.policy,
+            image,
+            &backend,
+        ))
+        .context("failed to open disks")?;
+
+        Ok(disks.into_iter().map(|(path, file)| (path.to_path_buf().into(), file)).collect())
+    }
+}
+
+/// Something that a running job reports, which is emitted as a signal of the job's object.
+enum Event {
+    Message(ObjectPath<'static>, Message),
+    Finished(ObjectPath<'static>, Result<(), String>),
+}
+
+struct Manager {
+    devices: Arc<dyn Devices>,
+    events: mpsc::Sender<Event>,
+    jobs: HashMap<ObjectPath<'static>, TaskControl>,
+    next_job: u32,
+}
+
+/// Claims `NAME` on `connection`, and serves requests until the connection fails.
+pub fn serve<D: Devices>(connection: Connection, devices: D) -> Result<(), dbus::Error> {
+    connection.request_name(NAME, false, true, true)?;
+
+    let (events, received) = mpsc::channel();
+    let mut cr = Crossroads::new();
+    let job = register_job(&mut cr);
+    let manager = register_manager(&mut cr, job);
+
+    cr.insert(
+        PATH,
+        &[manager],
+        Manager { devices: Arc::new(devices), events, jobs: HashMap::new(), next_job: 0 },
+    );
+
+    let cr = Arc::new(Mutex::new(cr));
+    let handler = cr.clone();
+    connection.start_receive(
+        MatchRule::new_method_call(),
+        Box::new(move |message, connection| {
+            let mut cr = handler.lock().expect("crossroads lock poisoned");
+            let _ = cr.handle_message(message, connection);
+            true
+        }),
+    );
+
+    loop {
+        connection.process(SIGNAL_INTERVAL)?;
+        for event in received.try_iter() {
+            if let Event::Finished(ref path, _) = event {
+                remove_job(&mut cr.lock().expect("crossroads lock poisoned"), path);
+            }
+
+            if let Some(signal) = signal(event) {
+                let _ = connection.send(signal);
+            }
+        }
+    }
+}
+
+/// Unregisters a job which has finished, so that it may no longer be controlled.
+fn remove_job(cr: &mut Crossroads, path: &ObjectPath<'static>) {
+    cr.remove::<TaskControl>(path);
+    if let Some(manager) = cr.data_mut::<Manager>(&ObjectPath::from(PATH)) {
+        manager.jobs.remove(path);
+    }
+}
+
+fn register_manager(cr: &mut Crossroads, job: IfaceToken<TaskControl>) -> IfaceToken<Manager> {
+    cr.register(MANAGER_INTERFACE, move |b| {
+        b.method("ListDevices", (), ("devices",), |_: &mut Context, manager: &mut Manager, ()| {
+            let devices = manager.devices.list().map_err(|why| MethodErr::failed(&why))?;
+            let devices: Vec<(String, String, u64)> = devices
+                .into_iter()
+                .map(|device| (device.path.display().to_string(), device.label, device.size))
+                .collect();
+
+            Ok((devices,))
+        });
+
+        b.method_with_cr(
+            "Flash",
+            ("image", "devices", "options"),
+            ("job",),
+            move |ctx: &mut Context,
+                  cr: &mut Crossroads,
+                  (image, devices, options): (String, Vec<String>, PropMap)| {
+                let image = PathBuf::from(image);
+                if !image.is_absolute() {
+                    return Err(MethodErr::invalid_arg("image path must be absolute"));
+                }
+
+                if devices.is_empty() {
+                    return Err(MethodErr::invalid_arg("no devices given"));
+                }
+
+                let manager: &mut Manager =
+                    cr.data_mut(ctx.path()).ok_or_else(|| MethodErr::no_path(ctx.path()))?;
+
+                let path = ObjectPath::from(format!("{}/Job/{}", PATH, manager.next_job));
+                manager.next_job += 1;
+
+                let control = TaskControl::default();
+                manager.jobs.insert(path.clone(), control.clone());
+
+                let flash = Flash {
+                    image,
+                    targets: devices.into_iter().map(PathBuf::from).collect(),
+                    options: FlashOptions::from_props(&options),
+                    control: control.clone(),
+                    devices: manager.devices.clone(),
+                };
+
+                let events = manager.events.clone();
+                let job_path = path.clone();
+                thread::spawn(move || {
+                    let result = flash.run(|message| {
+                        let _ = events.send(Event::Message(job_path.clone(), message));
+                    });
+
+                    let _ = events.send(Event::Finished(job_path, result));
+                });
+
+                cr.insert(path.clone(), &[job], control);
+                Ok((path,))
+            },
+        );
+
+        b.method(
+            "Cancel",
+            ("job",),
+            (),
+            |_: &mut Context, manager: &mut Manager, (job,): (ObjectPath<'static>,)| match manager
+                .jobs
+                .get(&job)
+            {
+                Some(control) => {
+                    control.cancel_all();
+                    Ok(())
+                }
+                None => Err(MethodErr::invalid_arg(&format!("no such job: {}", job))),
+            },
+        );
+    })
+}
+
+fn register_job(cr: &mut Crossroads) -> IfaceToken<TaskControl> {
+    cr.register(JOB_INTERFACE, |b| {
+        b.signal::<(u64,), _>("Size", ("size",));
+        b.signal::<(String, String), _>("Phase", ("device", "phase"));
+        b.signal::<(String, u64), _>("Progress", ("device", "written"));
+        b.signal::<(String, u64), _>("Rate", ("device", "bytes_per_second"));
+        b.signal::<(String, String, String), _>("Error", ("device", "phase", "message"));
+        b.signal::<(String, String, String), _>("Result", ("device", "outcome", "message"));
+        b.signal::<(bool, String), _>("Finished", ("success", "error"));
+
+        b.method("Cancel", (), (), |_: &mut Context, control: &mut TaskControl, ()| {
+            control.cancel_all();
+            Ok(())
+        });
+
+        b.method(
+            "CancelDevice",
+            ("device",),
+            (),
+            |_: &mut Context, control: &mut TaskControl, (device,): (String,)| {
+                if control.cancel_path(Path::new(&device)) {
+                    Ok(())
+                } else {
+                    Err(MethodErr::invalid_arg(&format!("no such device: {}", device)))
+                }
+            },
+        );
+
+        b.method("Pause", (), (), |_: &mut Context, control: &mut TaskControl, ()| {
+            control.pause();
+            Ok(())
+        });
+
+        b.method("Resume", (), (), |_: &mut Context, control: &mut TaskControl, ()| {
+            control.resume();
+            Ok(())
+        });
+    })
+}
+
+/// A flash of an image to devices, which the services run on threads of their own.
+pub(crate) struct Flash {
+    pub image: PathBuf,
+    pub targets: Vec<PathBuf>,
+    pub options: FlashOptions,
+    pub control: TaskControl,
+    pub devices: Arc<dyn Devices>,
+}
+
+impl Flash {
+    /// Flashes every target, passing each message of the IPC protocol to `report`.
+    ///
+    /// If the flash fails, the error is returned with each of its causes.
+    pub fn run(self, mut report: impl FnMut(Message)) -> Result<(), String> {
+        self.flash(&mut report).map_err(|why| {
+            let mut message = why.to_string();
+            for source in why.chain().skip(1) {
+                message.push_str(": ");
+                message.push_str(&source.to_string());
+            }
+
+            message
+        })
+    }
+
+    fn flash(self, report: &mut dyn FnMut(Message)) -> anyhow::Result<()> {
+        let image = fs::File::open(&self.image)
+            .with_context(|| format!("unable to open image at '{}'", self.image.display()))?;
+
+        let image_size = image.metadata().context("unable to fetch image metadata")?.len();
+        let data_size = ImageSize::new(&self.image).context("unable to read image")?;
+
+        let disks = self.devices.open(&self.targets, &data_size, self.options)?;
+
+        let (tx, mut rx) = async_mpsc::unbounded();
+        let mut paths = Vec::new();
+        let mut task =
+            Task::new(File::from(image), self.options.check).with_control(self.control);
+
+        for (path, disk) in disks {
+            paths.push(path.clone());
+            task.subscribe_path(path.clone(), disk, (), MessageProgress::new(path, tx.clone()));
+        }
+
+        drop(tx);
+
+        let forward = async move {
+            for message in MessageProgress::preamble(image_size, &paths) {
+                report(message);
+            }
+
+            while let Some(message) = rx.next().await {
+                report(message);
+            }
+        };
+
+        let process = async move {
+            let buf = &mut [0u8; 64 * 1024];
+            task.process(buf).await
+        };
+
+        let (_, result) = executor::block_on(async move { join!(forward, process) });
+        result
+    }
+}
+
+/// The signal which reports an event of a job, if the event is one which clients see.
+fn signal(event: Event) -> Option<dbus::Message> {
+    let (path, message) = match event {
+        Event::Message(path, message) => (path, message),
+        Event::Finished(path, result) => {
+            let (success, error) = match result {
+                Ok(()) => (true, String::new()),
+                Err(why) => (false, why),
+            };
+
+            let signal = dbus::Message::signal(&path, &JOB_INTERFACE.into(), &"Finished".into());
+            return Some(signal.append2(success, error));
+        }
+    };
+
+    let signal =
+        |member: &'static str| dbus::Message::signal(&path, &JOB_INTERFACE.into(), &member.into());
+
+    Some(match message {
+        Message::Size(size) => signal("Size").append1(size),
+        Message::Phase(device, phase) => {
+            signal("Phase").append2(display(&device), phase_name(phase))
+        }
+        Message::Set(device, written) => signal("Progress").append2(display(&device), written),
+        Message::Rate(device, rate) => signal("Rate").append2(display(&device), rate),
+        Message::Error { device, phase, message } => {
+            signal("Error").append3(display(&device), phase_name(phase), message)
+        }
+        Message::Result(device, outcome) => {
+            let (outcome, message) = match outcome {
+                Outcome::Success => ("success", String::new()),
+                Outcome::Failure(why) => ("failure", why),
+                Outcome::Cancelled => ("cancelled", String::new()),
+            };
+
+            signal("Result").append3(display(&device), outcome, message)
+        }
+        _ => return None,
+    })
+}
+
+fn display(path: &Path) -> String {
+    path.display().to_string()
+}
+
+fn phase_name(phase: Phase) -> &'static str {
+    match phase {
+        Phase::Writing => "writing",
+        Phase::Seeking => "seeking",
+        Phase::Validating => "validating",
+    }
+}
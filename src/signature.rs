@@ -0,0 +1,316 @@
+//! Verifying the signatures of checksum files, so that a checksum is only trusted if it was
+//! published by someone whose key the user has chosen to trust.
+//!
+//! GPG signatures are verified with `gpgv`, and minisign signatures with `minisign`, against
+//! local keys only, so that verification works offline, and never contacts a key server.
+
+use std::{
+    env, fmt, fs, io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+const CLEARSIGN_HEADER: &str = "-----BEGIN PGP SIGNED MESSAGE-----";
+const CLEARSIGN_SIGNATURE: &str = "-----BEGIN PGP SIGNATURE-----";
+
+#[derive(Debug, Error)]
+#[cfg_attr(rustfmt, rustfmt_skip)]
+pub enum SignatureError {
+    #[error("no trusted {} key is configured to verify '{}'", kind, path.display())]
+    NoKey { kind: SignatureKind, path: PathBuf },
+    #[error("unable to run {}: {}", program, why)]
+    Spawn { program: &'static str, why: io::Error },
+    #[error("the {} signature '{}' is not valid: {}", kind, path.display(), output)]
+    Invalid { kind: SignatureKind, path: PathBuf, output: String },
+    #[error("'{}' is not signed, but a trusted key is configured", path.display())]
+    Unsigned { path: PathBuf },
+    #[error("'{}' is not signed by a trusted key: {}", path.display(), why)]
+    Untrusted { path: PathBuf, why: Box<SignatureError> },
+}
+
+impl SignatureError {
+    /// Whether the checksum file must not be trusted, because its signature was checked and
+    /// found to be bad, or because a key is configured which it is not signed by, rather than
+    /// because no key or program was available to check it with.
+    pub fn is_rejected(&self) -> bool {
+        matches!(
+            self,
+            SignatureError::Invalid { .. }
+                | SignatureError::Unsigned { .. }
+                | SignatureError::Untrusted { .. }
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignatureKind {
+    Gpg,
+    Minisign,
+}
+
+impl fmt::Display for SignatureKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            SignatureKind::Gpg => "GPG",
+            SignatureKind::Minisign => "minisign",
+        })
+    }
+}
+
+/// A signature of a checksum file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Signature {
+    pub kind: SignatureKind,
+    /// The detached signature, or the checksum file itself, if it is clearsigned.
+    pub path: PathBuf,
+}
+
+/// Who signed a checksum file, as named by their key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Signer {
+    pub kind: SignatureKind,
+    pub name: String,
+}
+
+impl fmt::Display for Signer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.kind)
+    }
+}
+
+/// The keys which signatures are verified against.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Keys {
+    /// A binary GPG keyring, such as one exported with `gpg --export KEY > trusted.gpg`.
+    pub keyring: Option<PathBuf>,
+    /// A minisign public key file.
+    pub minisign_key: Option<PathBuf>,
+}
+
+impl Keys {
+    /// The keys in the user's configuration directory, `~/.config/popsicle/trusted.gpg` and
+    /// `~/.config/popsicle/minisign.pub`, which are only used if they exist.
+    pub fn from_config() -> Self {
+        let directory = env::var_os("XDG_CONFIG_HOME")
+            .filter(|directory| !directory.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .map(|config| config.join("popsicle"));
+
+        // Keys are canonicalized, as gpgv would look for a relative keyring in `~/.gnupg`.
+        let existing = |name: &str| {
+            directory.as_ref().and_then(|directory| fs::canonicalize(directory.join(name)).ok())
+        };
+
+        Keys { keyring: existing("trusted.gpg"), minisign_key: existing("minisign.pub") }
+    }
+
+    /// Whether any key is configured, in which case checksum files must be signed by one.
+    pub fn is_configured(&self) -> bool {
+        self.keyring.is_some() || self.minisign_key.is_some()
+    }
+
+    /// The key which verifies signatures of the given kind.
+    fn key(&self, kind: SignatureKind) -> Option<&PathBuf> {
+        match kind {
+            SignatureKind::Gpg => self.keyring.as_ref(),
+            SignatureKind::Minisign => self.minisign_key.as_ref(),
+        }
+    }
+}
+
+/// The programs which signatures are verified with.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Verifiers {
+    pub gpgv: PathBuf,
+    pub minisign: PathBuf,
+}
+
+impl Default for Verifiers {
+    /// `gpgv` and `minisign`, as found in the `PATH`.
+    fn default() -> Self {
+        Verifiers { gpgv: "gpgv".into(), minisign: "minisign".into() }
+    }
+}
+
+/// Looks for the signature of a checksum file, which may be detached, as `SHA256SUMS.gpg`,
+/// `SHA256SUMS.sig`, `SHA256SUMS.asc`, or `SHA256SUMS.minisig`, or the file may be clearsigned.
+///
+/// If there are several, the first which one of `keys` can verify is preferred.
+pub fn find(checksum_file: &Path, keys: &Keys) -> Option<Signature> {
+    let detached = |extension: &str, kind| {
+        let mut path = checksum_file.as_os_str().to_owned();
+        path.push(extension);
+        let path = PathBuf::from(path);
+        if path.exists() {
+            Some(Signature { kind, path })
+        } else {
+            None
+        }
+    };
+
+    let clearsigned = || {
+        let contents = fs::read_to_string(checksum_file).ok()?;
+        if is_clearsigned(&contents) {
+            Some(Signature { kind: SignatureKind::Gpg, path: checksum_file.to_path_buf() })
+        } else {
+            None
+        }
+    };
+
+    let signatures: Vec<Signature> = vec![
+        detached(".minisig", SignatureKind::Minisign),
+        detached(".gpg", SignatureKind::Gpg),
+        detached(".sig", SignatureKind::Gpg),
+        detached(".asc", SignatureKind::Gpg),
+        clearsigned(),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+
+    let verifiable = signatures.iter().position(|signature| keys.key(signature.kind).is_some());
+    signatures.into_iter().nth(verifiable.unwrap_or(0))
+}
+
+/// Verifies the signature of a checksum file, returning who signed it, or `None` if it is not
+/// signed.
+///
+/// If any key is configured, the file must be signed by it, as a signature which may be
+/// removed guards against nothing: unsigned files are refused as `Unsigned`, and signatures
+/// which cannot be verified with the keys as `Untrusted`.
+pub fn check(checksum_file: &Path, keys: &Keys) -> Result<Option<Signer>, SignatureError> {
+    check_with(checksum_file, keys, &Verifiers::default())
+}
+
+/// Like `check`, but verifies signatures with the given programs.
+pub fn check_with(
+    checksum_file: &Path,
+    keys: &Keys,
+    verifiers: &Verifiers,
+) -> Result<Option<Signer>, SignatureError> {
+    let required = keys.is_configured();
+    match find(checksum_file, keys) {
+        Some(signature) => match verify(checksum_file, &signature, keys, verifiers) {
+            Err(why) if required && !why.is_rejected() => Err(SignatureError::Untrusted {
+                path: checksum_file.to_path_buf(),
+                why: Box::new(why),
+            }),
+            result => result.map(Some),
+        },
+        None if required => Err(SignatureError::Unsigned { path: checksum_file.to_path_buf() }),
+        None => Ok(None),
+    }
+}
+
+/// Verifies a signature of a checksum file with the given programs, returning who signed it.
+pub fn verify(
+    checksum_file: &Path,
+    signature: &Signature,
+    keys: &Keys,
+    verifiers: &Verifiers,
+) -> Result<Signer, SignatureError> {
+    let no_key = || SignatureError::NoKey { kind: signature.kind, path: signature.path.clone() };
+
+    match signature.kind {
+        SignatureKind::Gpg => {
+            let keyring = keys.key(signature.kind).ok_or_else(no_key)?;
+            let mut command = Command::new(&verifiers.gpgv);
+            command.arg("--status-fd").arg("1").arg("--keyring").arg(keyring);
+            command.arg("--").arg(&signature.path);
+            if signature.path != checksum_file {
+                command.arg(checksum_file);
+            }
+
+            let (success, output) = run("gpgv", command)?;
+
+            // A good signature is reported as `[GNUPG:] GOODSIG <KEY ID> <USER ID>`.
+            let name = output
+                .lines()
+                .filter_map(|line| line.strip_prefix("[GNUPG:] GOODSIG "))
+                .find_map(|good| good.split_once(' ').map(|(_, user)| user.to_owned()));
+
+            match name {
+                Some(name) if success => Ok(Signer { kind: signature.kind, name }),
+                // Signatures by keys which are not in the keyring are not trusted, but neither
+                // are they known to be bad.
+                _ if output.lines().any(|line| line.starts_with("[GNUPG:] NO_PUBKEY ")) => {
+                    Err(no_key())
+                }
+                _ => Err(invalid(signature, &output)),
+            }
+        }
+        SignatureKind::Minisign => {
+            let key = keys.key(signature.kind).ok_or_else(no_key)?;
+            let mut command = Command::new(&verifiers.minisign);
+            command.arg("-V").arg("-q").arg("-p").arg(key);
+            command.arg("-m").arg(checksum_file).arg("-x").arg(&signature.path);
+
+            let (success, output) = run("minisign", command)?;
+            if !success {
+                return Err(invalid(signature, &output));
+            }
+
+            // Public keys are named by their comment, such as `minisign public key 5C0A7EE0`.
+            let name = fs::read_to_string(key)
+                .ok()
+                .and_then(|key| {
+                    let comment = key.lines().next()?.strip_prefix("untrusted comment: ")?;
+                    Some(comment.trim().to_owned())
+                })
+                .unwrap_or_else(|| key.display().to_string());
+
+            Ok(Signer { kind: signature.kind, name })
+        }
+    }
+}
+
+pub fn is_clearsigned(contents: &str) -> bool {
+    contents.trim_start().starts_with(CLEARSIGN_HEADER)
+}
+
+/// The text which a clearsigned file signs, or all of `contents` if it is not clearsigned, so
+/// that nothing outside of the signature is trusted.
+pub fn signed_text(contents: &str) -> String {
+    if !is_clearsigned(contents) {
+        return contents.to_owned();
+    }
+
+    // The armor header is followed by `Hash:` headers, and an empty line.
+    let mut lines = contents.trim_start().lines().skip(1).skip_while(|line| !line.is_empty());
+    lines.next();
+
+    let mut text = String::new();
+    for line in lines.take_while(|line| *line != CLEARSIGN_SIGNATURE) {
+        // Lines which begin with a dash are escaped with `- `.
+        text.push_str(line.strip_prefix("- ").unwrap_or(line));
+        text.push('\n');
+    }
+
+    text
+}
+
+/// Runs a verifier, returning whether it succeeded, and what it wrote.
+fn run(program: &'static str, mut command: Command) -> Result<(bool, String), SignatureError> {
+    let output = command
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|why| SignatureError::Spawn { program, why })?;
+
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    Ok((output.status.success(), text))
+}
+
+fn invalid(signature: &Signature, output: &str) -> SignatureError {
+    // Only the verifier's own messages explain the failure, rather than its status lines.
+    let output = output
+        .lines()
+        .rev()
+        .find(|line| !line.starts_with("[GNUPG:]") && !line.trim().is_empty())
+        .unwrap_or("verification failed")
+        .trim()
+        .to_owned();
+
+    SignatureError::Invalid { kind: signature.kind, path: signature.path.clone(), output }
+}
@@ -1,21 +1,33 @@
+use crate::{CancellableFile, TaskControl};
 use anyhow::Context;
 use async_std::{fs::File, prelude::*};
+use futures::io::{AsyncRead, AsyncSeek};
 use srmw::*;
-use std::{collections::HashMap, io::SeekFrom, time::Instant};
+use std::{collections::HashMap, io::SeekFrom, path::PathBuf, time::Instant};
+
+/// Where a `Task` reads the image from, which it rewinds to validate the devices.
+///
+/// This is implemented for every seekable reader, so that a task may be given a `File`, a
+/// `RemoteImage` wrapped in `AllowStdIo`, or a boxed `dyn ImageSource` to choose between them.
+pub trait ImageSource: AsyncRead + AsyncSeek + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncSeek + Unpin + Send + ?Sized> ImageSource for T {}
 
 pub trait Progress {
     type Device;
+    /// The `kind` of a message is `E` for an error, `S` and `V` when the device begins seeking
+    /// and validating, and `C` when the device has been cancelled.
     fn message(&mut self, device: &Self::Device, kind: &str, message: &str);
     fn finish(&mut self);
     fn set(&mut self, value: u64);
 }
 
 #[derive(new)]
-pub struct Task<P: Progress> {
-    image: File,
+pub struct Task<P: Progress, I: ImageSource = File> {
+    image: I,
 
     #[new(default)]
-    pub writer: MultiWriter<File>,
+    pub writer: MultiWriter<CancellableFile>,
 
     #[new(default)]
     pub state: HashMap<usize, (P::Device, P)>,
@@ -23,10 +35,13 @@ pub struct Task<P: Progress> {
     #[new(value = "125")]
     pub millis_between: u64,
 
+    #[new(default)]
+    control: TaskControl,
+
     check: bool,
 }
 
-impl<P: Progress> Task<P> {
+impl<P: Progress, I: ImageSource> Task<P, I> {
     /// Performs the asynchronous USB device flashing.
     pub async fn process(mut self, buf: &mut [u8]) -> anyhow::Result<()> {
         self.copy(buf).await.context("failed to copy ISO")?;
@@ -44,7 +59,42 @@ impl<P: Progress> Task<P> {
     }
 
     pub fn subscribe(&mut self, file: File, device: P::Device, progress: P) -> &mut Self {
-        let entity = self.writer.insert(file);
+        self.subscribe_as(None, file, device, progress)
+    }
+
+    /// Like `subscribe`, but the device may also be cancelled by its path, with `Control::Cancel`.
+    pub fn subscribe_path(
+        &mut self,
+        path: PathBuf,
+        file: File,
+        device: P::Device,
+        progress: P,
+    ) -> &mut Self {
+        self.subscribe_as(Some(path), file, device, progress)
+    }
+
+    /// Controls the task with an existing handle, so that it may be cancelled or paused before
+    /// the task is created. This must be called before any devices are subscribed.
+    pub fn with_control(mut self, control: TaskControl) -> Self {
+        debug_assert!(self.state.is_empty(), "devices were subscribed before with_control");
+        self.control = control;
+        self
+    }
+
+    /// A handle for cancelling devices, and pausing the task, while it is being processed.
+    pub fn control(&self) -> TaskControl {
+        self.control.clone()
+    }
+
+    fn subscribe_as(
+        &mut self,
+        path: Option<PathBuf>,
+        file: File,
+        device: P::Device,
+        progress: P,
+    ) -> &mut Self {
+        let writer = &mut self.writer;
+        let entity = self.control.register(path, file, |file| writer.insert(file));
         self.state.insert(entity, (device, progress));
         self
     }
@@ -66,9 +116,7 @@ impl<P: Progress> Task<P> {
                     }
                 }
                 CopyEvent::Failure(entity, why) => {
-                    let (device, mut pb) = self.state.remove(&entity).expect("missing entity");
-                    pb.message(&device, "E", &format!("{}", why));
-                    pb.finish();
+                    fail(&mut self.state, &self.control, entity, format!("{}", why));
                 }
                 CopyEvent::SourceFailure(why) => {
                     for (device, pb) in self.state.values_mut() {
@@ -78,8 +126,10 @@ impl<P: Progress> Task<P> {
 
                     return Err(why).context("error reading from source");
                 }
-                CopyEvent::NoWriters => return Err(anyhow!("no writers left")),
+                CopyEvent::NoWriters => return Err(no_writers(&self.control)),
             }
+
+            self.control.wait_while_paused().await;
         }
 
         Ok(())
@@ -95,9 +145,8 @@ impl<P: Progress> Task<P> {
 
         let mut stream = self.writer.seek(SeekFrom::Start(0));
         while let Some((entity, why)) = stream.next().await {
-            let (path, mut pb) = self.state.remove(&entity).expect("missing entity");
-            pb.message(&path, "E", &format!("errored seeking to start: {}", why));
-            pb.finish();
+            let message = format!("errored seeking to start: {}", why);
+            fail(&mut self.state, &self.control, entity, message);
         }
 
         Ok(())
@@ -122,9 +171,7 @@ impl<P: Progress> Task<P> {
                     }
                 }
                 ValidationEvent::Failure(entity, why) => {
-                    let (path, mut pb) = self.state.remove(&entity).expect("missing entity");
-                    pb.message(&path, "E", &format!("{}", why));
-                    pb.finish();
+                    fail(&mut self.state, &self.control, entity, format!("{}", why));
                 }
                 ValidationEvent::SourceFailure(why) => {
                     for (path, pb) in self.state.values_mut() {
@@ -134,10 +181,37 @@ impl<P: Progress> Task<P> {
 
                     return Err(why).context("error reading from source");
                 }
-                ValidationEvent::NoWriters => return Err(anyhow!("no writers left")),
+                ValidationEvent::NoWriters => return Err(no_writers(&self.control)),
             }
+
+            self.control.wait_while_paused().await;
         }
 
         Ok(())
     }
 }
+
+/// Reports that a device failed, or that it stopped because it was cancelled.
+fn fail<P: Progress>(
+    state: &mut HashMap<usize, (P::Device, P)>,
+    control: &TaskControl,
+    entity: usize,
+    message: String,
+) {
+    let (device, mut pb) = state.remove(&entity).expect("missing entity");
+    if control.is_cancelled(entity) {
+        pb.message(&device, "C", "");
+    } else {
+        pb.message(&device, "E", &message);
+    }
+
+    pb.finish();
+}
+
+fn no_writers(control: &TaskControl) -> anyhow::Error {
+    if control.is_all_cancelled() {
+        anyhow!("every device was cancelled")
+    } else {
+        anyhow!("no writers left")
+    }
+}
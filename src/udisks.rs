@@ -0,0 +1,249 @@
+//! Enumerating, unmounting, and opening devices through UDisks2.
+//!
+//! UDisks2 authorizes each request with polkit, so these operations do not require the
+//! caller to be root.
+
+use dbus::{
+    arg::{PropMap, RefArg, Variant},
+    blocking::{stdintf::org_freedesktop_dbus::ObjectManager, Connection, Proxy},
+    channel::Channel,
+    Path as ObjectPath,
+};
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fs::File,
+    os::unix::{ffi::OsStrExt, io::FromRawFd},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+const SERVICE: &str = "org.freedesktop.UDisks2";
+const MANAGER: &str = "/org/freedesktop/UDisks2";
+
+const BLOCK: &str = "org.freedesktop.UDisks2.Block";
+const DRIVE: &str = "org.freedesktop.UDisks2.Drive";
+const FILESYSTEM: &str = "org.freedesktop.UDisks2.Filesystem";
+const PARTITION: &str = "org.freedesktop.UDisks2.Partition";
+const SWAPSPACE: &str = "org.freedesktop.UDisks2.Swapspace";
+
+const NOT_MOUNTED: &str = "org.freedesktop.UDisks2.Error.NotMounted";
+
+/// Long enough for the user to answer a polkit authentication prompt.
+const TIMEOUT: Duration = Duration::from_secs(120);
+
+type Objects = HashMap<ObjectPath<'static>, HashMap<String, PropMap>>;
+type Options = HashMap<&'static str, Variant<Box<dyn RefArg>>>;
+
+#[derive(Debug, Error)]
+#[cfg_attr(rustfmt, rustfmt_skip)]
+pub enum UDisksError {
+    #[error("unable to connect to the system bus: {}", _0)]
+    Connect(dbus::Error),
+    #[error("unable to fetch devices from UDisks2: {}", _0)]
+    Objects(dbus::Error),
+    #[error("UDisks2 does not know of a block device at '{}'", _0.display())]
+    NoBlock(PathBuf),
+    #[error("UDisks2 failed to unmount '{}': {}", device.display(), why)]
+    Unmount { device: PathBuf, why: dbus::Error },
+    #[error("UDisks2 failed to disable swap on '{}': {}", device.display(), why)]
+    Swapoff { device: PathBuf, why: dbus::Error },
+    #[error("UDisks2 failed to open '{}': {}", device.display(), why)]
+    Open { device: PathBuf, why: dbus::Error },
+}
+
+/// A block device which is known to UDisks2.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UDisksBlock {
+    /// The D-Bus object of the block device.
+    pub object: ObjectPath<'static>,
+    /// The device node, such as `/dev/sdb`.
+    pub device: PathBuf,
+    pub size: u64,
+    /// The drive that the block device belongs to.
+    pub drive: Option<ObjectPath<'static>>,
+    /// The partition table containing this block device, if it is a partition.
+    pub table: Option<ObjectPath<'static>>,
+    /// Where the filesystem on this block device is mounted.
+    pub mount_points: Vec<PathBuf>,
+    /// Whether this block device is an active swap area.
+    pub swap_active: bool,
+    /// Whether UDisks2 suggests that this device should not be shown to the user.
+    pub hint_ignore: bool,
+}
+
+/// A connection to the UDisks2 daemon.
+pub struct UDisks2 {
+    connection: Connection,
+}
+
+impl UDisks2 {
+    /// Connects to UDisks2 on the system bus.
+    pub fn system() -> Result<Self, UDisksError> {
+        Connection::new_system().map(|connection| Self { connection }).map_err(UDisksError::Connect)
+    }
+
+    /// Connects to a UDisks2 service on the bus at `address`, such as a private test bus.
+    pub fn connect(address: &str) -> Result<Self, UDisksError> {
+        let mut channel = Channel::open_private(address).map_err(UDisksError::Connect)?;
+        channel.register().map_err(UDisksError::Connect)?;
+        Ok(Self { connection: Connection::from(channel) })
+    }
+
+    /// Every block device which UDisks2 knows of.
+    pub fn blocks(&self) -> Result<Vec<UDisksBlock>, UDisksError> {
+        let objects = self.objects()?;
+        let mut blocks: Vec<UDisksBlock> =
+            objects.iter().filter_map(|(object, ifaces)| block(object, ifaces)).collect();
+
+        blocks.sort_by(|a, b| a.device.cmp(&b.device));
+        Ok(blocks)
+    }
+
+    /// The whole-disk block devices of drives which are connected by USB.
+    pub fn usb_disks(&self) -> Result<Vec<UDisksBlock>, UDisksError> {
+        let objects = self.objects()?;
+        let mut disks: Vec<UDisksBlock> = objects
+            .iter()
+            .filter_map(|(object, ifaces)| block(object, ifaces))
+            .filter(|block| block.table.is_none() && !block.hint_ignore)
+            .filter(|block| {
+                let drive = block.drive.as_ref().and_then(|drive| objects.get(drive));
+                let bus = drive
+                    .and_then(|drive| drive.get(DRIVE))
+                    .and_then(|drive| drive.get("ConnectionBus").and_then(|bus| bus.0.as_str()));
+
+                bus == Some("usb")
+            })
+            .collect();
+
+        disks.sort_by(|a, b| a.device.cmp(&b.device));
+        Ok(disks)
+    }
+
+    /// The block device whose device node is `device`.
+    pub fn block(&self, device: &Path) -> Result<UDisksBlock, UDisksError> {
+        self.blocks()?
+            .into_iter()
+            .find(|block| block.device == device)
+            .ok_or_else(|| UDisksError::NoBlock(device.to_path_buf()))
+    }
+
+    /// Unmounts every filesystem, and disables every swap area, on `disk` and its partitions.
+    pub fn teardown(&self, disk: &UDisksBlock, force: bool) -> Result<(), UDisksError> {
+        let blocks = self.blocks()?;
+        let held = blocks.iter().filter(|block| {
+            block.object == disk.object || block.table == Some(disk.object.clone())
+        });
+
+        for block in held {
+            if block.swap_active {
+                eprintln!("disabling swap on '{}'", block.device.display());
+                let result: Result<(), dbus::Error> =
+                    self.proxy(&block.object).method_call(SWAPSPACE, "Stop", (Options::new(),));
+
+                result.map_err(|why| UDisksError::Swapoff { device: block.device.clone(), why })?;
+            }
+
+            if !block.mount_points.is_empty() {
+                eprintln!("unmounting '{}'", block.device.display());
+                let mut options = Options::new();
+                options.insert("force", Variant(Box::new(force)));
+
+                let result: Result<(), dbus::Error> =
+                    self.proxy(&block.object).method_call(FILESYSTEM, "Unmount", (options,));
+
+                match result {
+                    Err(ref why) if why.name() == Some(NOT_MOUNTED) => (),
+                    result => result.map_err(|why| UDisksError::Unmount {
+                        device: block.device.clone(),
+                        why,
+                    })?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Opens `block` for synchronous reading and writing.
+    pub fn open(&self, block: &UDisksBlock) -> Result<File, UDisksError> {
+        let mut options = Options::new();
+        options.insert("flags", Variant(Box::new(libc::O_SYNC)));
+
+        let (fd,): (dbus::arg::OwnedFd,) = self
+            .proxy(&block.object)
+            .method_call(BLOCK, "OpenDevice", ("rw", options))
+            .map_err(|why| UDisksError::Open { device: block.device.clone(), why })?;
+
+        Ok(unsafe { File::from_raw_fd(fd.into_fd()) })
+    }
+
+    fn objects(&self) -> Result<Objects, UDisksError> {
+        self.proxy(&ObjectPath::from(MANAGER)).get_managed_objects().map_err(UDisksError::Objects)
+    }
+
+    fn proxy<'a>(&'a self, object: &'a ObjectPath) -> Proxy<'a, &'a Connection> {
+        self.connection.with_proxy(SERVICE, object.clone(), TIMEOUT)
+    }
+}
+
+/// Reads the block device from the interfaces of `object`, if it is one.
+fn block(object: &ObjectPath<'static>, ifaces: &HashMap<String, PropMap>) -> Option<UDisksBlock> {
+    let props = ifaces.get(BLOCK)?;
+    let device = props.get("Device").and_then(|device| path(&*device.0))?;
+
+    let object_prop = |props: &PropMap, key: &str| {
+        props
+            .get(key)
+            .and_then(|value| value.0.as_str())
+            .filter(|path| *path != "/")
+            .and_then(|path| ObjectPath::new(path.to_owned()).ok())
+    };
+
+    let mount_points = ifaces
+        .get(FILESYSTEM)
+        .and_then(|fs| fs.get("MountPoints"))
+        .and_then(|points| points.0.as_iter())
+        .map(|points| points.filter_map(path).collect())
+        .unwrap_or_default();
+
+    let swap_active = ifaces
+        .get(SWAPSPACE)
+        .and_then(|swap| swap.get("Active"))
+        .and_then(|active| active.0.as_u64())
+        .map_or(false, |active| active != 0);
+
+    Some(UDisksBlock {
+        object: object.clone(),
+        device,
+        size: props.get("Size").and_then(|size| size.0.as_u64()).unwrap_or(0),
+        drive: object_prop(props, "Drive"),
+        table: ifaces.get(PARTITION).and_then(|partition| object_prop(partition, "Table")),
+        mount_points,
+        swap_active,
+        hint_ignore: props
+            .get("HintIgnore")
+            .and_then(|hint| hint.0.as_u64())
+            .map_or(false, |hint| hint != 0),
+    })
+}
+
+/// Reads a NUL-terminated byte array property as a path.
+fn path(value: &dyn RefArg) -> Option<PathBuf> {
+    let bytes = value
+        .as_iter()?
+        .map(|byte| byte.as_u64().map(|byte| byte as u8))
+        .collect::<Option<Vec<u8>>>()?;
+
+    let bytes = match bytes.iter().position(|&byte| byte == 0) {
+        Some(end) => &bytes[..end],
+        None => &bytes[..],
+    };
+
+    if bytes.is_empty() {
+        None
+    } else {
+        Some(PathBuf::from(OsStr::from_bytes(bytes)))
+    }
+}
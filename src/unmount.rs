@@ -0,0 +1,181 @@
+//! Native teardown of the mounts and swap areas that are holding a device.
+
+use crate::{DiskError, Holder, HolderKind};
+use async_std::path::{Path as AsyncPath, PathBuf as AsyncPathBuf};
+use mnt::MountEntry;
+use std::{
+    ffi::CString,
+    fmt::{self, Display, Formatter},
+    fs, io,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+/// Options for detaching mounts from a device.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct UnmountFlags {
+    /// Detach the mount immediately, and clean it up once it is no longer busy.
+    pub lazy: bool,
+    /// Force the unmount, even if the filesystem is busy.
+    pub force: bool,
+}
+
+/// A process which is keeping a mount point busy.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BusyProcess {
+    pub pid: u32,
+    pub name: Box<str>,
+}
+
+impl Display for BusyProcess {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.pid)
+    }
+}
+
+/// Detaches every mount and swap area in `holders`, which were found on the device `arg`.
+///
+/// Mounts stacked on top of, or beneath, a mount of the device are detached first, with the
+/// most recent and deepest mounts being detached before those they are mounted upon. Swap
+/// areas are disabled once every mount has been detached.
+///
+/// Devices with holders which cannot be torn down, such as LVM volumes, are refused before
+/// anything is detached. Otherwise, if a mount cannot be detached, or a swap area disabled,
+/// whatever was detached before it remains detached.
+pub fn teardown(
+    arg: &AsyncPath,
+    holders: &[Holder],
+    mounts: &[MountEntry],
+    flags: UnmountFlags,
+) -> Result<(), DiskError> {
+    if let Some(holder) = holders
+        .iter()
+        .find(|holder| !matches!(holder.kind, HolderKind::Mount(_) | HolderKind::Swap))
+    {
+        return Err(DiskError::Held { arg: arg.into(), holder: holder.clone() });
+    }
+
+    let roots: Vec<&Path> = holders
+        .iter()
+        .filter_map(|holder| match holder.kind {
+            HolderKind::Mount(ref target) => Some(&**target),
+            _ => None,
+        })
+        .collect();
+
+    for target in unmount_order(&roots, mounts) {
+        eprintln!("unmounting '{}': {}", arg.display(), target.display());
+        unmount(&target, flags).map_err(|why| DiskError::Unmount {
+            processes: if why.raw_os_error() == Some(libc::EBUSY) {
+                busy_processes(&target)
+            } else {
+                Vec::new()
+            },
+            target: AsyncPathBuf::from(target).into_boxed_path(),
+            why,
+        })?;
+    }
+
+    for holder in holders.iter().filter(|holder| holder.kind == HolderKind::Swap) {
+        eprintln!("disabling swap on '{}': {}", arg.display(), holder.device.display());
+        swapoff(&holder.device).map_err(|why| DiskError::Swapoff {
+            device: AsyncPathBuf::from(holder.device.to_path_buf()).into_boxed_path(),
+            why,
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Unmounts the filesystem mounted at `target` with `umount2`.
+pub fn unmount(target: &Path, flags: UnmountFlags) -> io::Result<()> {
+    let target = CString::new(target.as_os_str().as_bytes())?;
+
+    let mut raw_flags = 0;
+    if flags.lazy {
+        raw_flags |= libc::MNT_DETACH;
+    }
+
+    if flags.force {
+        raw_flags |= libc::MNT_FORCE;
+    }
+
+    if unsafe { libc::umount2(target.as_ptr(), raw_flags) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Disables the swap area on `device`.
+pub fn swapoff(device: &Path) -> io::Result<()> {
+    let device = CString::new(device.as_os_str().as_bytes())?;
+
+    if unsafe { libc::swapoff(device.as_ptr()) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// The order in which mount points must be detached to release every mount in `roots`.
+///
+/// Every mount at or beneath a root is included. Deeper mount points are detached before
+/// shallower ones, and mounts sharing a mount point are detached in the reverse of the order
+/// in which they were mounted.
+pub fn unmount_order(roots: &[&Path], mounts: &[MountEntry]) -> Vec<PathBuf> {
+    let mut targets: Vec<PathBuf> = mounts
+        .iter()
+        .rev()
+        .map(|mount| PathBuf::from(&mount.file))
+        .filter(|target| roots.iter().any(|root| target.starts_with(root)))
+        .collect();
+
+    targets.sort_by_key(|target| std::cmp::Reverse(target.components().count()));
+    targets
+}
+
+/// Finds every process with an open file, working directory, root, or mapping beneath `target`.
+fn busy_processes(target: &Path) -> Vec<BusyProcess> {
+    let mut processes = Vec::new();
+
+    let entries = match fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(_) => return processes,
+    };
+
+    for entry in entries.filter_map(Result::ok) {
+        let pid = match entry.file_name().to_str().and_then(|pid| pid.parse::<u32>().ok()) {
+            Some(pid) => pid,
+            None => continue,
+        };
+
+        let proc = entry.path();
+        let links = ["cwd", "root", "exe"].iter().map(|link| proc.join(link)).chain(
+            fs::read_dir(proc.join("fd"))
+                .into_iter()
+                .flat_map(|fds| fds.filter_map(Result::ok).map(|fd| fd.path())),
+        );
+
+        let mut busy =
+            links.filter_map(|link| fs::read_link(link).ok()).any(|path| path.starts_with(target));
+
+        if !busy {
+            busy = fs::read_to_string(proc.join("maps")).map_or(false, |maps| {
+                maps.lines()
+                    .filter_map(|line| line.split_whitespace().nth(5))
+                    .any(|path| Path::new(path).starts_with(target))
+            });
+        }
+
+        if busy {
+            let name = fs::read_to_string(proc.join("comm"))
+                .map(|comm| comm.trim().into())
+                .unwrap_or_else(|_| "unknown".into());
+
+            processes.push(BusyProcess { pid, name });
+        }
+    }
+
+    processes
+}
@@ -0,0 +1,108 @@
+use popsicle::{
+    checksums,
+    hash::{Algorithm, Checksum, Hasher},
+};
+use std::{env, fs, process};
+
+const SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+const MD5: &str = "900150983cd24fb0d6963f7d28e17f72";
+
+#[test]
+fn checksums_parse() {
+    let contents = format!(
+        "# comment\n{}  pop-os.iso\n{} *./ubuntu.iso\nMD5 (pop-os.iso) = {}\nnot a checksum\n",
+        SHA256,
+        SHA256.to_uppercase(),
+        MD5
+    );
+
+    let entries = checksums::parse(&contents);
+    let listed: Vec<(Algorithm, &str)> =
+        entries.iter().map(|entry| (entry.checksum.algorithm(), entry.file.as_str())).collect();
+    assert_eq!(
+        listed,
+        vec![
+            (Algorithm::Sha256, "pop-os.iso"),
+            (Algorithm::Sha256, "./ubuntu.iso"),
+            (Algorithm::Md5, "pop-os.iso")
+        ]
+    );
+    assert_eq!(entries[1].checksum.digest(), SHA256);
+
+    let mut hasher = Hasher::new(&[Algorithm::Md5, Algorithm::Sha256]);
+    hasher.update(b"abc");
+    let digests = hasher.finish();
+    for entry in &entries {
+        entry.checksum.verify(&digests).unwrap();
+    }
+
+    let other = Checksum::new(Algorithm::Md5, &"0".repeat(32)).unwrap();
+    assert!(other.verify(&digests).unwrap_err().to_string().contains("MD5 checksum mismatch"));
+}
+
+#[test]
+fn checksums_discover() {
+    let directory = env::temp_dir().join(format!("popsicle-checksums-{}", process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let image = directory.join("pop-os.iso");
+    fs::write(&image, b"abc").unwrap();
+
+    assert_eq!(checksums::discover(&image), None);
+
+    // Shared checksum files must list the image.
+    fs::write(directory.join("SHA256SUMS"), format!("{}  other.iso\n", SHA256)).unwrap();
+    assert_eq!(checksums::discover(&image), None);
+
+    fs::write(directory.join("Fedora-CHECKSUM"), format!("MD5 (pop-os.iso) = {}\n", MD5)).unwrap();
+    let discovered = checksums::discover(&image).unwrap();
+    assert_eq!(discovered.path, directory.join("Fedora-CHECKSUM"));
+    assert_eq!(discovered.strongest().algorithm(), Algorithm::Md5);
+
+    // Digests without a tag are of the algorithm which the file is named after, rather than
+    // the one which is most likely for their length.
+    let blake2b = "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d1\
+                   7d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923";
+    fs::write(directory.join("BLAKE2bSUMS"), format!("{}  pop-os.iso\n", blake2b)).unwrap();
+    let discovered = checksums::discover(&image).unwrap();
+    assert_eq!(discovered.path, directory.join("BLAKE2bSUMS"));
+    assert_eq!(discovered.strongest().algorithm(), Algorithm::Blake2b);
+    assert_eq!(discovered.strongest().digest(), blake2b);
+
+    let sha3 = directory.join("pop-os.iso.sha3-256");
+    fs::write(&sha3, format!("{}\n", SHA256)).unwrap();
+    let listed = checksums::checksums_for(&sha3, "pop-os.iso").unwrap();
+    assert_eq!(listed, vec![Checksum::new(Algorithm::Sha3_256, SHA256).unwrap()]);
+    fs::remove_file(&sha3).unwrap();
+
+    // Files named after the image are preferred, and may contain only its digest.
+    fs::write(directory.join("pop-os.iso.sha256sum"), format!("{}\n", SHA256)).unwrap();
+    let discovered = checksums::discover(&image).unwrap();
+    assert_eq!(discovered.path, directory.join("pop-os.iso.sha256sum"));
+    assert_eq!(discovered.strongest().digest(), SHA256);
+
+    let _ = fs::remove_dir_all(&directory);
+}
+
+#[test]
+fn checksums_detect() {
+    let detected = |text: &str| {
+        let checksum = checksums::detect(text).unwrap();
+        (checksum.algorithm(), checksum.digest().to_owned())
+    };
+
+    assert_eq!(
+        detected(&format!("  {}\n", SHA256.to_uppercase())),
+        (Algorithm::Sha256, SHA256.into())
+    );
+    assert_eq!(detected(MD5), (Algorithm::Md5, MD5.into()));
+    assert_eq!(detected(&format!("sha3-256:{}", SHA256)), (Algorithm::Sha3_256, SHA256.into()));
+    assert_eq!(detected(&format!("BLAKE3 {}", SHA256)), (Algorithm::Blake3, SHA256.into()));
+    assert_eq!(detected(&format!("{}  pop-os.iso", MD5)), (Algorithm::Md5, MD5.into()));
+    assert_eq!(
+        detected(&format!("SHA3-256 (pop-os.iso) = {}", SHA256)),
+        (Algorithm::Sha3_256, SHA256.into())
+    );
+
+    assert!(checksums::detect("not a checksum").is_none());
+    assert!(checksums::detect(&format!("md5:{}", SHA256)).is_none());
+}
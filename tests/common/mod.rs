@@ -0,0 +1,160 @@
+//! Fixtures which are shared by the integration tests.
+
+#![allow(dead_code)]
+
+use async_std::fs::{File, OpenOptions};
+use dbus::{blocking::Connection, channel::Channel};
+use futures::executor;
+use popsicle::{
+    mnt::MountEntry,
+    service::{Devices, FlashOptions, ListedDevice},
+    ImageSize, Progress,
+};
+use std::{
+    env, fs,
+    io::{BufRead, BufReader},
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+    process::{self, Child, Command, Stdio},
+};
+
+/// A private bus, which is shut down when dropped.
+pub struct Bus {
+    daemon: Child,
+    pub address: String,
+}
+
+impl Bus {
+    /// Starts a private bus, which requires `dbus-daemon` to be installed.
+    pub fn start() -> Bus {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("failed to start dbus-daemon");
+
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take().unwrap()).read_line(&mut address).unwrap();
+        Bus { daemon, address: address.trim().to_owned() }
+    }
+
+    pub fn connect(&self) -> Connection {
+        let mut channel = Channel::open_private(&self.address).unwrap();
+        channel.register().unwrap();
+        Connection::from(channel)
+    }
+}
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
+
+/// Regular files which stand in for USB devices.
+pub struct FileDevices(pub Vec<PathBuf>);
+
+impl Devices for FileDevices {
+    fn list(&self) -> anyhow::Result<Vec<ListedDevice>> {
+        Ok(self
+            .0
+            .iter()
+            .map(|path| ListedDevice { path: path.clone(), label: "Scratch".into(), size: 1 << 20 })
+            .collect())
+    }
+
+    fn open(
+        &self,
+        paths: &[PathBuf],
+        _image: &ImageSize,
+        _options: FlashOptions,
+    ) -> anyhow::Result<Vec<(PathBuf, File)>> {
+        executor::block_on(async move {
+            let mut files = Vec::new();
+            for path in paths {
+                if !self.0.contains(path) {
+                    return Err(anyhow::anyhow!("'{}' is not a device", path.display()));
+                }
+
+                files.push((path.clone(), open(path).await));
+            }
+
+            Ok(files)
+        })
+    }
+}
+
+/// Opens a file which stands in for a device.
+pub async fn open(path: &Path) -> File {
+    OpenOptions::new().read(true).write(true).open(path).await.unwrap()
+}
+
+pub struct NoProgress;
+
+impl Progress for NoProgress {
+    type Device = ();
+
+    fn message(&mut self, _device: &(), _kind: &str, _message: &str) {}
+
+    fn finish(&mut self) {}
+
+    fn set(&mut self, _value: u64) {}
+}
+
+/// A directory of files for a test, which is removed when dropped.
+pub struct Scratch(PathBuf);
+
+impl Scratch {
+    pub fn new(test: &str) -> Self {
+        let directory = env::temp_dir().join(format!("popsicle-{}-{}", test, process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        Scratch(directory)
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+
+    /// Creates the file `name` with `contents`, and any directories which it is in.
+    pub fn file(&self, name: &str, contents: &[u8]) -> PathBuf {
+        let path = self.path(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    /// Adds a block device to a fake sysfs in the directory, at `sys/devices/{parent}/{name}`,
+    /// along with its link in `sys/class/block` and its node in `dev`.
+    ///
+    /// Returns the sysfs directory of the device.
+    pub fn block_device(&self, parent: &str, name: &str) -> PathBuf {
+        let sys = self.path(&format!("sys/devices/{}/{}", parent, name));
+        fs::create_dir_all(&sys).unwrap();
+
+        let class = self.path("sys/class/block");
+        fs::create_dir_all(&class).unwrap();
+        symlink(&sys, class.join(name)).unwrap();
+
+        self.file(&format!("dev/{}", name), b"");
+        sys
+    }
+
+    /// Parses a mount table in the format of `/proc/mounts`, in which `ROOT` stands for the
+    /// directory.
+    pub fn mounts(&self, table: &str) -> Vec<MountEntry> {
+        table
+            .replace("ROOT", &self.0.to_string_lossy())
+            .lines()
+            .map(|line| line.parse::<MountEntry>().unwrap())
+            .collect()
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
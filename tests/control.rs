@@ -0,0 +1,131 @@
+mod common;
+
+use common::{open, Scratch};
+use futures::{
+    executor,
+    io::{AsyncRead, AsyncSeek, Cursor},
+    task::{Context, Poll},
+};
+use popsicle::{codec::Control, Progress, Task, TaskControl};
+use std::{
+    fs, io,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
+
+/// How much of the image is copied at a time.
+const CHUNK: usize = 64 * 1024;
+
+type Messages = Arc<Mutex<Vec<(String, String)>>>;
+
+/// Records the kind of each message, by the name of the device which it is about.
+struct Recorder(Messages);
+
+impl Progress for Recorder {
+    type Device = String;
+
+    fn message(&mut self, device: &String, kind: &str, _message: &str) {
+        self.0.lock().unwrap().push((device.clone(), kind.to_owned()));
+    }
+
+    fn finish(&mut self) {}
+
+    fn set(&mut self, _value: u64) {}
+}
+
+/// An image which cancels a device once the first chunk of it has been written, so that the
+/// device is cancelled in the middle of the copy.
+struct CancellingImage {
+    image: Cursor<Vec<u8>>,
+    control: TaskControl,
+    device: PathBuf,
+    reads: usize,
+}
+
+impl AsyncRead for CancellingImage {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.reads += 1;
+        if this.reads == 2 {
+            assert!(this.control.cancel_path(&this.device));
+        }
+
+        Pin::new(&mut this.image).poll_read(cx, buf)
+    }
+}
+
+impl AsyncSeek for CancellingImage {
+    fn poll_seek(self: Pin<&mut Self>, cx: &mut Context, pos: SeekFrom) -> Poll<io::Result<u64>> {
+        Pin::new(&mut self.get_mut().image).poll_seek(cx, pos)
+    }
+}
+
+#[test]
+fn control() {
+    let image: Vec<u8> = (0..5 * CHUNK as u32).map(|x| (x % 251) as u8).collect();
+    let scratch = Scratch::new("control");
+    let first = scratch.file("first", b"");
+    let second = scratch.file("second", b"");
+    let messages = Messages::default();
+
+    let control = TaskControl::default();
+    let cancelling = CancellingImage {
+        image: Cursor::new(image.clone()),
+        control: control.clone(),
+        device: first.clone(),
+        reads: 0,
+    };
+
+    let result = executor::block_on(async {
+        let mut task = Task::new(cancelling, true).with_control(control.clone());
+        for path in &[&first, &second] {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            let recorder = Recorder(messages.clone());
+            task.subscribe_path(path.to_path_buf(), open(path).await, name, recorder);
+        }
+
+        // Devices which the task is not flashing cannot be cancelled.
+        assert!(!control.apply(&Control::Cancel("/dev/popsicle-missing".into())));
+
+        task.process(&mut [0u8; CHUNK]).await
+    });
+
+    result.unwrap();
+    assert!(!control.is_all_cancelled());
+
+    // The cancelled device stops where it was, and the other device receives the whole image.
+    let written = fs::read(&first).unwrap();
+    assert!(!written.is_empty() && written.len() < image.len());
+    assert!(image.starts_with(&written));
+    assert_eq!(fs::read(&second).unwrap(), image);
+
+    let messages = messages.lock().unwrap();
+    let kinds = |device: &Path| -> Vec<&str> {
+        let name = device.file_name().unwrap().to_string_lossy();
+        messages.iter().filter(|(d, _)| *d == name).map(|(_, kind)| kind.as_str()).collect()
+    };
+
+    assert_eq!(kinds(&first), vec!["C"]);
+    assert_eq!(kinds(&second), vec!["S", "V"]);
+}
+
+#[test]
+fn control_pause() {
+    let control = TaskControl::default();
+    assert!(control.apply(&Control::Pause));
+    assert!(control.is_paused());
+    assert!(control.apply(&Control::Resume));
+    assert!(!control.is_paused());
+
+    // Cancelling everything also releases a paused task.
+    control.apply(&Control::Pause);
+    assert!(control.apply(&Control::CancelAll));
+    assert!(control.is_all_cancelled());
+    assert!(!control.is_paused());
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use common::Scratch;
+use popsicle::{Bus, Device};
+use std::{fs, os::unix::fs::symlink, path::Path};
+
+const USB_PORT: &str = "pci0000:00/0000:00:14.0/usb2/2-1/2-1.4";
+
+/// A USB drive, `sdz`, with two partitions, and another drive, `sdzz`, whose name begins with
+/// the name of the first.
+fn usb_drives(scratch: &Scratch) {
+    scratch.file(&format!("sys/devices/{}/idVendor", USB_PORT), b"0781\n");
+    scratch.file(&format!("sys/devices/{}/speed", USB_PORT), b"480\n");
+    scratch.file(&format!("sys/devices/{}/serial", USB_PORT), b"4C530001\n");
+
+    let scsi = format!("{}/2-1.4:1.0/host3/target3:0:0/3:0:0:0", USB_PORT);
+    scratch.file(&format!("sys/devices/{}/vendor", scsi), b"SanDisk \n");
+    scratch.file(&format!("sys/devices/{}/model", scsi), b"Ultra           \n");
+
+    let disk = scratch.block_device(&format!("{}/block", scsi), "sdz");
+    symlink(scratch.path(&format!("sys/devices/{}", scsi)), disk.join("device")).unwrap();
+    fs::write(disk.join("size"), "2048\n").unwrap();
+    fs::write(disk.join("removable"), "1\n").unwrap();
+
+    for &(name, number) in &[("sdz2", "2"), ("sdz1", "1")] {
+        let partition = scratch.block_device(&format!("{}/block/sdz", scsi), name);
+        fs::write(partition.join("partition"), number).unwrap();
+        fs::write(partition.join("size"), "1024\n").unwrap();
+    }
+
+    let other = scratch.block_device("pci0000:00/0000:00:14.0/usb2/2-2/block", "sdzz");
+    let partition = scratch.block_device("pci0000:00/0000:00:14.0/usb2/2-2/block/sdzz", "sdzz1");
+    fs::write(other.join("size"), "4096\n").unwrap();
+    fs::write(partition.join("partition"), "1").unwrap();
+}
+
+#[test]
+fn device_usb() {
+    let scratch = Scratch::new("device-usb");
+    usb_drives(&scratch);
+
+    let mounts = scratch.mounts(
+        "ROOT/dev/sdz1 /media/user/USB vfat rw 0 0\n\
+         ROOT/dev/sdzz1 /media/user/Other ext4 rw 0 0",
+    );
+
+    let sysfs = scratch.path("sys/class/block");
+    let path = fs::canonicalize(scratch.path("dev/sdz")).unwrap();
+    let device = Device::from_sysfs(&sysfs, &path, &mounts).unwrap();
+
+    assert_eq!(&*device.path, path.as_path());
+    assert_eq!(&*device.name, "sdz");
+    assert_eq!(device.vendor.as_deref(), Some("SanDisk"));
+    assert_eq!(device.model.as_deref(), Some("Ultra"));
+    assert_eq!(device.serial.as_deref(), Some("4C530001"));
+    assert_eq!(device.size, 1 << 20);
+    assert!(device.removable);
+    assert_eq!(device.bus, Bus::Usb);
+    assert_eq!(device.usb_speed, Some(480));
+    assert_eq!(device.port.as_deref(), Some("2-1.4"));
+    assert_eq!(device.label(), format!("SanDisk Ultra ({})", path.display()));
+
+    // Partitions are ordered by number, and the mounts of `sdzz1` are not those of `sdz1`.
+    let numbers: Vec<u32> = device.partitions.iter().map(|p| p.number).collect();
+    assert_eq!(numbers, vec![1, 2]);
+    assert_eq!(device.partitions[0].size, 512 * 1024);
+    assert_eq!(device.all_mounts().collect::<Vec<_>>(), vec![Path::new("/media/user/USB")]);
+
+    // Partitions are not whole devices.
+    assert!(Device::from_sysfs(&sysfs, &scratch.path("dev/sdz1"), &mounts).is_err());
+}
+
+#[test]
+fn device_internal() {
+    let scratch = Scratch::new("device-internal");
+    let disk = scratch.block_device("pci0000:00/0000:00:1d.0/0000:3d:00.0/nvme/nvme0", "nvme0n1");
+    fs::write(disk.join("size"), "1000215216\n").unwrap();
+    fs::write(disk.join("removable"), "0\n").unwrap();
+
+    let sysfs = scratch.path("sys/class/block");
+    let device = Device::from_sysfs(&sysfs, &scratch.path("dev/nvme0n1"), &[]).unwrap();
+    assert_eq!(device.bus, Bus::Nvme);
+    assert!(!device.removable);
+    assert_eq!((device.usb_speed, device.port.as_deref()), (None, None));
+    assert_eq!(device.label(), device.path.display().to_string());
+}
@@ -0,0 +1,161 @@
+use popsicle::hash::{
+    hash_file_cached, hash_reader, Algorithm, DigestCache, HashCancel, HashError, Hasher,
+};
+use std::{
+    env, fs,
+    io::Cursor,
+    path::Path,
+    process::{self, Command},
+    sync::Mutex,
+    thread,
+    time::Duration,
+};
+
+#[test]
+fn hash_algorithms() {
+    let mut hasher = Hasher::new(&[Algorithm::Sha256, Algorithm::Md5, Algorithm::Sha1]);
+    hasher.update(b"a");
+    hasher.update(b"bc");
+    let digests = hasher.finish();
+
+    assert_eq!(digests.get(Algorithm::Md5), Some("900150983cd24fb0d6963f7d28e17f72"));
+    assert_eq!(digests.get(Algorithm::Sha1), Some("a9993e364706816aba3e25717850c26c9cd0d89d"));
+    assert_eq!(
+        digests.get(Algorithm::Sha256),
+        Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+    );
+    assert_eq!(digests.get(Algorithm::Sha512), None);
+
+    let order: Vec<Algorithm> = digests.iter().map(|(algorithm, _)| algorithm).collect();
+    assert_eq!(order, vec![Algorithm::Md5, Algorithm::Sha1, Algorithm::Sha256]);
+
+    assert_eq!("sha-256".parse::<Algorithm>().unwrap(), Algorithm::Sha256);
+    assert_eq!("SHA512".parse::<Algorithm>().unwrap(), Algorithm::Sha512);
+    assert!("crc32".parse::<Algorithm>().is_err());
+    assert_eq!(Algorithm::Sha512.digest_len(), 64);
+}
+
+#[test]
+fn hash_modern_algorithms() {
+    let mut hasher = Hasher::new(&Algorithm::ALL);
+    hasher.update(b"abc");
+    let digests = hasher.finish();
+
+    let expected = [
+        (
+            Algorithm::Sha384,
+            "cb00753f45a35e8bb5a03d699ac65007272c32ab0eded1631a8b605a43ff5bed\
+             8086072ba1e7cc2358baeca134c825a7",
+        ),
+        (Algorithm::Sha3_256, "3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532"),
+        (
+            Algorithm::Sha3_512,
+            "b751850b1a57168a5693cd924b6b096e08f621827444f70d884f5d0240d2712e\
+             10e116e9192af3c91a7ec57647e3934057340b4cf408d5a56592f8274eec53f0",
+        ),
+        (
+            Algorithm::Blake2b,
+            "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d1\
+             7d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923",
+        ),
+        (Algorithm::Blake3, "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"),
+    ];
+
+    for (algorithm, digest) in &expected {
+        assert_eq!(digests.get(*algorithm), Some(*digest), "{}", algorithm);
+        assert_eq!(algorithm.name().parse::<Algorithm>().unwrap(), *algorithm);
+    }
+
+    assert_eq!("sha3_256".parse::<Algorithm>().unwrap(), Algorithm::Sha3_256);
+    assert_eq!("blake2b-512".parse::<Algorithm>().unwrap(), Algorithm::Blake2b);
+
+    // Digests of the same length are assumed to be of the most widely used algorithm.
+    assert_eq!(Algorithm::from_hex_len(64), Some(Algorithm::Sha256));
+    assert_eq!(Algorithm::from_hex_len(96), Some(Algorithm::Sha384));
+    assert_eq!(Algorithm::from_hex_len(128), Some(Algorithm::Sha512));
+    assert_eq!(Algorithm::from_hex_len(100), None);
+}
+
+#[test]
+fn hash_progress_and_cancel() {
+    let data = vec![0u8; 3 * 1024 * 1024 + 5];
+    let mut reported = Vec::new();
+    let digests = hash_reader(Cursor::new(&data), &[Algorithm::Md5], &HashCancel::default(), |n| {
+        reported.push(n)
+    })
+    .unwrap();
+
+    assert!(digests.get(Algorithm::Md5).is_some());
+    assert_eq!(reported.last(), Some(&(data.len() as u64)));
+    assert!(reported.windows(2).all(|pair| pair[0] < pair[1]));
+
+    // Cancelling from the progress callback stops hashing before the next read.
+    let cancel = HashCancel::default();
+    let mut calls = 0;
+    let result = hash_reader(Cursor::new(&data), &[Algorithm::Sha256], &cancel, |_| {
+        calls += 1;
+        cancel.cancel();
+    });
+
+    assert!(matches!(result, Err(HashError::Cancelled)));
+    assert_eq!(calls, 1);
+}
+
+#[test]
+fn hash_cache() {
+    let directory = env::temp_dir().join(format!("popsicle-hash-cache-{}", process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let image = directory.join("pop-os.iso");
+    let cache_path = directory.join("cache").join("digests.json");
+    fs::write(&image, b"abc").unwrap();
+
+    let hash = |cache: &Mutex<DigestCache>, algorithms: &[Algorithm]| {
+        let mut read = false;
+        let digests = hash_file_cached(&image, algorithms, cache, &HashCancel::default(), |_| {
+            read = true;
+        })
+        .unwrap();
+        (digests, read)
+    };
+
+    let cache = Mutex::new(DigestCache::open(cache_path.clone()));
+    let (digests, read) = hash(&cache, &[Algorithm::Sha256]);
+    assert!(read);
+    assert_eq!(
+        digests.get(Algorithm::Sha256),
+        Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+    );
+
+    // Digests are remembered across runs, and only missing digests are computed.
+    let cache = Mutex::new(DigestCache::open(cache_path));
+    assert_eq!(hash(&cache, &[Algorithm::Sha256]), (digests.clone(), false));
+    let (both, read) = hash(&cache, &[Algorithm::Md5, Algorithm::Sha256]);
+    assert!(read);
+    assert_eq!(both.get(Algorithm::Md5), Some("900150983cd24fb0d6963f7d28e17f72"));
+    assert_eq!(hash(&cache, &[Algorithm::Sha256, Algorithm::Md5]), (both, false));
+
+    // Changing the file invalidates its digests.
+    fs::write(&image, b"abcd").unwrap();
+    let (changed, read) = hash(&cache, &[Algorithm::Sha256]);
+    assert!(read);
+    assert_ne!(changed, digests);
+
+    // Resetting the modification time of a changed file does not hide the change.
+    let reference = directory.join("reference");
+    let touch = |from: &Path, to: &Path| {
+        let status = Command::new("touch").arg("-r").arg(from).arg(to).status().unwrap();
+        assert!(status.success());
+    };
+
+    touch(&image, &reference);
+
+    // File times are coarser than nanoseconds, so the change must come noticeably later.
+    thread::sleep(Duration::from_millis(20));
+    fs::write(&image, b"abce").unwrap();
+    touch(&reference, &image);
+    let (tampered, read) = hash(&cache, &[Algorithm::Sha256]);
+    assert!(read);
+    assert_ne!(tampered, changed);
+
+    let _ = fs::remove_dir_all(&directory);
+}
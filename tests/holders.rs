@@ -0,0 +1,107 @@
+mod common;
+
+use common::Scratch;
+use popsicle::{device_holders_in, HolderKind};
+use std::{fs, path::Path};
+
+const PORT: &str = "pci0000:00/0000:00:14.0/usb2/2-1/2-1:1.0/host3/target3:0:0/3:0:0:0/block";
+
+/// Adds the partition `name` of `disk`, which is held by each of `holders`.
+fn partition(scratch: &Scratch, disk: &str, name: &str, holders: &[&str]) {
+    let sys = scratch.block_device(&format!("{}/{}", PORT, disk), name);
+    fs::write(sys.join("partition"), &name[disk.len()..]).unwrap();
+    for holder in holders {
+        fs::create_dir_all(sys.join("holders").join(holder)).unwrap();
+    }
+}
+
+/// Adds the device-mapper target `name`, with the given name and UUID.
+fn mapping(scratch: &Scratch, name: &str, dm_name: &str, uuid: &str) {
+    let sys = scratch.block_device("virtual/block", name);
+    fs::create_dir_all(sys.join("dm")).unwrap();
+    fs::write(sys.join("dm/name"), dm_name).unwrap();
+    fs::write(sys.join("dm/uuid"), uuid).unwrap();
+}
+
+/// Lists each of `nodes` as an active swap area in `/proc/swaps`.
+fn swaps(scratch: &Scratch, nodes: &[&str]) {
+    let mut swaps = String::from("Filename\tType\tSize\tUsed\tPriority\n");
+    for node in nodes {
+        let path = scratch.path(&format!("dev/{}", node));
+        swaps.push_str(&format!("{}\tpartition\t1024\t0\t-2\n", path.display()));
+    }
+
+    scratch.file("proc/swaps", swaps.as_bytes());
+}
+
+/// The holders of the named device, as the name of the node and its holder.
+fn holders(scratch: &Scratch, device: &str, mounts: &str) -> Vec<(String, HolderKind)> {
+    let mounts = scratch.mounts(mounts);
+    let device = scratch.path(&format!("dev/{}", device));
+    let mut holders: Vec<(String, HolderKind)> =
+        device_holders_in(&scratch.path(""), &device, &mounts)
+            .unwrap()
+            .into_iter()
+            .map(|holder| {
+                (holder.device.file_name().unwrap().to_string_lossy().into_owned(), holder.kind)
+            })
+            .collect();
+
+    holders.sort_by(|a, b| a.0.cmp(&b.0));
+    holders
+}
+
+#[test]
+fn holders_kinds() {
+    let scratch = Scratch::new("holders-kinds");
+    scratch.block_device(PORT, "sda");
+    partition(&scratch, "sda", "sda1", &[]);
+    partition(&scratch, "sda", "sda2", &[]);
+    partition(&scratch, "sda", "sda3", &["dm-0"]);
+    partition(&scratch, "sda", "sda4", &["dm-1"]);
+    partition(&scratch, "sda", "sda5", &["dm-2"]);
+    partition(&scratch, "sda", "sda6", &["md127"]);
+    partition(&scratch, "sda", "sda7", &[]);
+    mapping(&scratch, "dm-0", "vg-root", "LVM-7f3a");
+    mapping(&scratch, "dm-1", "luks-2d4e", "CRYPT-LUKS2-2d4e-luks-2d4e");
+    mapping(&scratch, "dm-2", "cache", "");
+    scratch.file("sys/fs/btrfs/5b1c/devices/sda7", b"");
+    swaps(&scratch, &["sda2"]);
+
+    let holders = holders(&scratch, "sda", "ROOT/dev/sda1 /media/user/DATA ext4 rw 0 0");
+    assert_eq!(
+        holders,
+        vec![
+            ("sda1".into(), HolderKind::Mount(Path::new("/media/user/DATA").into())),
+            ("sda2".into(), HolderKind::Swap),
+            ("sda3".into(), HolderKind::Lvm("vg-root".into())),
+            ("sda4".into(), HolderKind::Crypt("luks-2d4e".into())),
+            ("sda5".into(), HolderKind::DeviceMapper("cache".into())),
+            ("sda6".into(), HolderKind::Raid("md127".into())),
+            ("sda7".into(), HolderKind::Btrfs("5b1c".into())),
+        ]
+    );
+}
+
+#[test]
+fn holders_prefix() {
+    let scratch = Scratch::new("holders-prefix");
+    scratch.block_device(PORT, "sda");
+    partition(&scratch, "sda", "sda1", &[]);
+    scratch.block_device(PORT, "sdaa");
+    partition(&scratch, "sdaa", "sdaa1", &["dm-0"]);
+    mapping(&scratch, "dm-0", "vg-home", "LVM-0c9d");
+    swaps(&scratch, &["sdaa"]);
+
+    // Nothing which holds `sdaa` or its partitions is attributed to `sda`.
+    let mounts = "ROOT/dev/sdaa1 /media/user/USB vfat rw 0 0";
+    assert_eq!(holders(&scratch, "sda", mounts), vec![]);
+    assert_eq!(
+        holders(&scratch, "sdaa", mounts),
+        vec![
+            ("sdaa".into(), HolderKind::Swap),
+            ("sdaa1".into(), HolderKind::Mount(Path::new("/media/user/USB").into())),
+            ("sdaa1".into(), HolderKind::Lvm("vg-home".into())),
+        ]
+    );
+}
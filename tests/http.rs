@@ -0,0 +1,172 @@
+mod common;
+
+use common::{FileDevices, Scratch};
+use popsicle::{
+    codec::{Message, Outcome},
+    http::Server,
+};
+use serde_json::{json, Value};
+use std::{
+    fs,
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Sends a request with a JSON body, and returns the status and JSON body of the response.
+fn request(address: SocketAddr, method: &str, path: &str, body: &[u8]) -> (u16, Value) {
+    let headers = format!("Host: {}\r\nContent-Type: application/json\r\n", address);
+    request_with(address, method, path, &headers, body)
+}
+
+/// Sends a request with the given headers, each of which ends with `\r\n`.
+fn request_with(
+    address: SocketAddr,
+    method: &str,
+    path: &str,
+    headers: &str,
+    body: &[u8],
+) -> (u16, Value) {
+    let mut stream = TcpStream::connect(address).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\n{}Connection: close\r\nContent-Length: {}\r\n\r\n",
+        method,
+        path,
+        headers,
+        body.len()
+    )
+    .unwrap();
+    stream.write_all(body).unwrap();
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    let response = String::from_utf8(response).unwrap();
+
+    let status = response[9..12].parse().unwrap();
+    let body = response.split_once("\r\n\r\n").map_or("", |(_, body)| body);
+    (status, serde_json::from_str(body).unwrap_or(Value::Null))
+}
+
+/// Polls the status of a job until it has finished.
+fn wait_for_job(address: SocketAddr, id: u64) -> Value {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while Instant::now() < deadline {
+        let (_, status) = request(address, "GET", &format!("/jobs/{}", id), b"");
+        if status["finished"] == true {
+            return status;
+        }
+
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    panic!("job {} did not finish", id);
+}
+
+#[test]
+fn http() {
+    let scratch = Scratch::new("http");
+    let images = scratch.path("images");
+    fs::create_dir_all(&images).unwrap();
+    let first = scratch.file("first", b"");
+    let second = scratch.file("second", b"");
+
+    let devices = FileDevices(vec![first.clone(), second.clone()]);
+    let server = Server::bind("127.0.0.1:0", images, devices).unwrap();
+    let address = server.local_addr().unwrap();
+    thread::spawn(move || server.run());
+
+    let (status, listed) = request(address, "GET", "/devices", b"");
+    assert_eq!(status, 200);
+    assert_eq!(listed[0]["path"], json!(first));
+    assert_eq!(listed[1]["label"], "Scratch");
+
+    // Images are uploaded, and listed by name.
+    let image_data: Vec<u8> = (0..200_000u32).map(|x| (x % 251) as u8).collect();
+    let (status, _) = request(address, "PUT", "/images/pop%20os.iso", &image_data);
+    assert_eq!(status, 201);
+    assert_eq!(request(address, "PUT", "/images/..%2Fescape", b"").0, 400);
+    assert_eq!(
+        request(address, "GET", "/images", b"").1,
+        json!([{ "name": "pop os.iso", "size": 200_000 }])
+    );
+
+    let job = json!({ "image": "pop os.iso", "devices": [first, second], "check": true });
+    let (status, started) = request(address, "POST", "/jobs", job.to_string().as_bytes());
+    assert_eq!(status, 201);
+    assert_eq!(started["id"], 0);
+
+    // The WebSocket replays the messages sent so far, and follows the job until it is done.
+    let url = format!("ws://{}/jobs/0/events", address);
+    let (mut socket, _) = tungstenite::client(&*url, TcpStream::connect(address).unwrap()).unwrap();
+    let mut messages = Vec::new();
+    while let Ok(tungstenite::Message::Text(text)) = socket.read_message() {
+        messages.push(serde_json::from_str::<Message>(&text).unwrap());
+    }
+
+    assert_eq!(messages[0], Message::Hello { version: 2 });
+    assert!(messages.contains(&Message::Size(200_000)));
+    for device in &[&first, &second] {
+        assert!(messages.contains(&Message::Result(device.to_path_buf(), Outcome::Success)));
+    }
+
+    let status = wait_for_job(address, 0);
+    assert_eq!(status["error"], Value::Null);
+    assert_eq!(status["devices"][0]["status"], "succeeded");
+    assert_eq!(status["devices"][1]["phase"], "Validating");
+    assert_eq!(fs::read(&first).unwrap(), image_data);
+    assert_eq!(fs::read(&second).unwrap(), image_data);
+
+    // A job which cannot open its devices finishes with the reason.
+    let job = json!({ "image": "pop os.iso", "devices": ["/nonexistent"] });
+    let (status, started) = request(address, "POST", "/jobs", job.to_string().as_bytes());
+    assert_eq!(status, 201);
+    let status = wait_for_job(address, started["id"].as_u64().unwrap());
+    assert!(status["error"].as_str().unwrap().contains("'/nonexistent' is not a device"));
+
+    // Invalid requests are refused.
+    let missing = json!({ "image": "missing.iso", "devices": [first] });
+    assert_eq!(request(address, "POST", "/jobs", missing.to_string().as_bytes()).0, 404);
+    assert_eq!(request(address, "POST", "/jobs", b"{}").0, 400);
+    assert_eq!(request(address, "GET", "/jobs/9", b"").0, 404);
+    assert_eq!(request(address, "POST", "/jobs/0/control", b"{\"Cancel\":\"/dev/sdz\"}").0, 400);
+    assert_eq!(request(address, "GET", "/jobs", b"").1.as_array().unwrap().len(), 2);
+
+    // Requests which a web page may have sent are refused.
+    let job = json!({ "image": "pop os.iso", "devices": [first] }).to_string();
+    let text = format!("Host: {}\r\nContent-Type: text/plain\r\n", address);
+    assert_eq!(request_with(address, "POST", "/jobs", &text, job.as_bytes()).0, 415);
+
+    let json = "Content-Type: application/json\r\n";
+    let port = address.port();
+    for host in &[format!("evil.example:{}", port), "127.0.0.1:1".into(), String::new()] {
+        let headers = format!("Host: {}\r\n{}", host, json);
+        assert_eq!(request_with(address, "POST", "/jobs", &headers, job.as_bytes()).0, 403);
+    }
+
+    let localhost = format!("Host: localhost:{}\r\n{}", port, json);
+    assert_eq!(request_with(address, "GET", "/jobs/0", &localhost, b"").0, 200);
+
+    for origin in &["http://evil.example", "null", &format!("https://{}", address)] {
+        let headers = format!("Host: {}\r\nOrigin: {}\r\n{}", address, origin, json);
+        assert_eq!(request_with(address, "POST", "/jobs", &headers, job.as_bytes()).0, 403);
+        assert_eq!(request_with(address, "GET", "/jobs/0/events", &headers, b"").0, 403);
+    }
+
+    let own = format!("Host: {}\r\nOrigin: http://{}\r\n{}", address, address, json);
+    assert_eq!(request_with(address, "GET", "/jobs/0", &own, b"").0, 200);
+
+    // Only the most recent jobs which have finished are remembered.
+    let job = json!({ "image": "pop os.iso", "devices": ["/nonexistent"] }).to_string();
+    for _ in 0..32 {
+        let (_, started) = request(address, "POST", "/jobs", job.as_bytes());
+        wait_for_job(address, started["id"].as_u64().unwrap());
+    }
+
+    let (_, started) = request(address, "POST", "/jobs", job.as_bytes());
+    assert_eq!(started["id"], 34);
+    assert_eq!(request(address, "GET", "/jobs", b"").1.as_array().unwrap().len(), 33);
+    assert_eq!(request(address, "GET", "/jobs/1", b"").0, 404);
+    assert_eq!(request(address, "GET", "/jobs/2", b"").0, 200);
+}
@@ -0,0 +1,125 @@
+use popsicle::{check_capacity, Bus, Compression, Device, DiskError, ImageSize};
+use std::{io::Cursor, path::Path};
+
+/// The fixtures are compressed from `DATA_LEN` bytes, and the second stream or frame of the
+/// `multi` fixtures from another `SECOND_LEN` bytes.
+const DATA_LEN: u64 = 300_000;
+const SECOND_LEN: u64 = 100_000;
+
+fn measure(image: &[u8]) -> ImageSize {
+    ImageSize::from_reader(&mut Cursor::new(image), image.len() as u64).unwrap()
+}
+
+fn device(size: u64) -> Device {
+    Device {
+        path: Path::new("/dev/sdz").into(),
+        name: "sdz".into(),
+        vendor: None,
+        model: None,
+        serial: None,
+        size,
+        removable: true,
+        bus: Bus::Usb,
+        usb_speed: None,
+        port: None,
+        partitions: Vec::new(),
+        mounts: Vec::new(),
+    }
+}
+
+#[test]
+fn image_size_gzip() {
+    let size = measure(include_bytes!("images/image.gz"));
+    assert_eq!(size.compression, Some(Compression::Gzip));
+    assert_eq!((size.data, size.exact), (DATA_LEN, false));
+}
+
+#[test]
+fn image_size_xz() {
+    let size = measure(include_bytes!("images/blocks.xz"));
+    assert_eq!(size.compression, Some(Compression::Xz));
+    assert_eq!((size.data, size.exact), (DATA_LEN, true));
+
+    // Concatenated streams, followed by stream padding.
+    let size = measure(include_bytes!("images/multi.xz"));
+    assert_eq!((size.data, size.exact), (DATA_LEN + SECOND_LEN, true));
+
+    let mut truncated = include_bytes!("images/multi.xz").to_vec();
+    truncated.truncate(truncated.len() - 8);
+    let mut reader = Cursor::new(&truncated);
+    assert!(ImageSize::from_reader(&mut reader, truncated.len() as u64).is_err());
+}
+
+#[test]
+fn image_size_xz_invalid() {
+    let invalid =
+        |image: &[u8]| ImageSize::from_reader(&mut Cursor::new(image), image.len() as u64).is_err();
+
+    // Only the magic, which may be followed by nothing but stream padding.
+    let magic = [0xFD, b'7', b'z', b'X', b'Z', 0x00];
+    assert!(invalid(&magic));
+    assert!(invalid(&[&magic[..], &[0; 4]].concat()));
+    assert!(invalid(&[&magic[..], &[0; 64]].concat()));
+
+    // An index of two records, whose sizes overflow when they are summed.
+    let mut index = vec![0x00, 0x02];
+    for _ in 0..4 {
+        index.extend_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F]);
+    }
+    index.resize(44, 0);
+
+    let mut footer = vec![0; 4];
+    footer.extend_from_slice(&(index.len() as u32 / 4 - 1).to_le_bytes());
+    footer.extend_from_slice(&[0x00, 0x00, b'Y', b'Z']);
+
+    assert!(invalid(&[&magic[..], &[0; 6], &index, &footer].concat()));
+}
+
+#[test]
+fn image_size_zstd() {
+    let size = measure(include_bytes!("images/image.zst"));
+    assert_eq!(size.compression, Some(Compression::Zstd));
+    assert_eq!((size.data, size.exact), (DATA_LEN, true));
+
+    let size = measure(include_bytes!("images/multi.zst"));
+    assert_eq!((size.data, size.exact), (DATA_LEN + SECOND_LEN, true));
+
+    // Frames which were compressed from a stream do not record their content size.
+    let size = measure(include_bytes!("images/nosize.zst"));
+    assert!(!size.exact);
+}
+
+#[test]
+fn image_size_capacity() {
+    let arg = async_std::path::Path::new("/dev/sdz");
+    let plain = measure(&[0; 4096]);
+    assert_eq!((plain.compression, plain.required()), (None, 4096));
+    assert!(check_capacity(arg, &device(4096), &plain).is_ok());
+
+    let too_small = check_capacity(arg, &device(4000), &plain);
+    assert!(matches!(too_small, Err(DiskError::TooSmall { size: 4000, required: 4096, .. })));
+
+    // The data of an xz image is exactly known, so it must fit as well as the file.
+    let xz = measure(include_bytes!("images/blocks.xz"));
+    assert_eq!(xz.required(), DATA_LEN);
+    assert!(check_capacity(arg, &device(DATA_LEN - 1), &xz).is_err());
+
+    // bzip2 does not record the size of its data, which is not taken to be nothing.
+    let bzip2 = include_bytes!("images/image.bz2");
+    let size = measure(bzip2);
+    assert_eq!((size.compression, size.exact), (Some(Compression::Bzip2), false));
+    assert_eq!(size.required(), bzip2.len() as u64);
+    assert!(check_capacity(arg, &device(bzip2.len() as u64 - 1), &size).is_err());
+
+    // The gzip trailer records the size modulo 4 GiB, which may be less than the file.
+    let mut gzip = vec![0; 1000];
+    gzip[..2].copy_from_slice(&[0x1F, 0x8B]);
+    gzip[996..].copy_from_slice(&10u32.to_le_bytes());
+    let size = measure(&gzip);
+    assert_eq!((size.data, size.required()), (10, 1000));
+    assert!(check_capacity(arg, &device(500), &size).is_err());
+
+    // Images which are not files, such as devices, are still measured, if they have a size.
+    let device_image = ImageSize::new("/dev/null").unwrap();
+    assert_eq!((device_image.compression, device_image.exact), (None, false));
+}
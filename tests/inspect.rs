@@ -0,0 +1,194 @@
+use popsicle::{
+    inspect::{Boot, Container, ImageReport, OsInfo, PartitionTable, PartitionType},
+    Compression,
+};
+use std::io::Cursor;
+
+const ESP_GUID: [u8; 16] = [
+    0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11, 0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B,
+];
+
+fn put(image: &mut Vec<u8>, offset: usize, bytes: &[u8]) {
+    if image.len() < offset + bytes.len() {
+        image.resize(offset + bytes.len(), 0);
+    }
+
+    image[offset..offset + bytes.len()].copy_from_slice(bytes);
+}
+
+fn mbr_entry(image: &mut Vec<u8>, index: usize, kind: u8, start: u32, sectors: u32) {
+    let entry = 446 + index * 16;
+    put(image, entry + 4, &[kind]);
+    put(image, entry + 8, &start.to_le_bytes());
+    put(image, entry + 12, &sectors.to_le_bytes());
+    put(image, 510, &[0x55, 0xAA]);
+}
+
+/// An ISO9660 image with a primary volume descriptor, and an El Torito catalog which boots
+/// both BIOS and UEFI firmware.
+fn iso(publisher: &str) -> Vec<u8> {
+    let mut image = vec![0; 24 * 2048];
+
+    let pvd = 16 * 2048;
+    put(&mut image, pvd, b"\x01CD001\x01");
+    put(&mut image, pvd + 8, format!("{:32}", "LINUX").as_bytes());
+    put(&mut image, pvd + 40, format!("{:32}", "Pop_OS 22.04 amd64").as_bytes());
+    put(&mut image, pvd + 318, format!("{:128}", publisher).as_bytes());
+
+    let boot_record = 17 * 2048;
+    put(&mut image, boot_record, b"\x00CD001\x01EL TORITO SPECIFICATION");
+    put(&mut image, boot_record + 71, &20u32.to_le_bytes());
+
+    put(&mut image, 18 * 2048, b"\xFFCD001\x01");
+
+    let catalog = 20 * 2048;
+    put(&mut image, catalog, &[0x01, 0x00]);
+    put(&mut image, catalog + 30, &[0x55, 0xAA]);
+    put(&mut image, catalog + 32, &[0x88]);
+    put(&mut image, catalog + 64, &[0x91, 0xEF, 0x01, 0x00]);
+    put(&mut image, catalog + 96, &[0x88]);
+
+    image
+}
+
+#[test]
+fn inspect_iso() {
+    let report = ImageReport::from_reader(&mut Cursor::new(iso("POP"))).unwrap();
+    assert_eq!(report.container, Container::Iso9660);
+    assert_eq!(report.partition_table, None);
+    assert_eq!(report.boot, Some(Boot { bios: true, uefi: true }));
+    assert!(!report.is_windows());
+    assert!(report.is_optical_only());
+
+    let volume = report.iso.unwrap();
+    assert_eq!(volume.system, "LINUX");
+    assert_eq!(volume.volume, "Pop_OS 22.04 amd64");
+
+    let report = ImageReport::from_reader(&mut Cursor::new(iso("MICROSOFT CORPORATION")));
+    assert!(report.unwrap().is_windows());
+}
+
+/// An ISO9660 directory record, with a Rock Ridge name if `rock_ridge` is given.
+fn record(extent: u32, size: u32, directory: bool, name: &[u8], rock_ridge: &[u8]) -> Vec<u8> {
+    let mut record = vec![0; 33 + name.len() + (1 - name.len() % 2)];
+    put(&mut record, 2, &extent.to_le_bytes());
+    put(&mut record, 10, &size.to_le_bytes());
+    put(&mut record, 25, &[if directory { 0x02 } else { 0x00 }]);
+    put(&mut record, 32, &[name.len() as u8]);
+    put(&mut record, 33, name);
+
+    if !rock_ridge.is_empty() {
+        record.extend_from_slice(&[b'N', b'M', 5 + rock_ridge.len() as u8, 1, 0]);
+        record.extend_from_slice(rock_ridge);
+    }
+
+    record[0] = record.len() as u8;
+    record
+}
+
+#[test]
+fn inspect_os() {
+    let mut image = iso("POP");
+    let report = ImageReport::from_reader(&mut Cursor::new(image.clone())).unwrap();
+    let from_volume =
+        OsInfo { name: "Pop_OS".into(), version: Some("22.04".into()), arch: Some("amd64".into()) };
+    assert_eq!(report.os, Some(from_volume));
+
+    let info = b"Pop!_OS 22.04 LTS \"Jammy Jellyfish\" - Release amd64 (20230530)\n";
+    put(&mut image, 16 * 2048 + 156, &record(21, 2048, true, b"\0", b""));
+
+    let mut root = record(21, 2048, true, b"\0", b"");
+    root.extend(record(21, 2048, true, b"\x01", b""));
+    root.extend(record(22, 2048, true, b"_DISK", b".disk"));
+    put(&mut image, 21 * 2048, &root);
+    put(&mut image, 22 * 2048, &record(23, info.len() as u32, false, b"INFO.;1", b""));
+    put(&mut image, 23 * 2048, info);
+
+    let report = ImageReport::from_reader(&mut Cursor::new(image)).unwrap();
+    let os = report.os.unwrap();
+    assert_eq!(os.to_string(), "Pop!_OS 22.04 (amd64)");
+}
+
+#[test]
+fn inspect_hybrid_iso() {
+    let mut image = iso("POP");
+    put(&mut image, 0, &[0xEB, 0x63, 0x90]);
+    mbr_entry(&mut image, 0, 0x00, 0, 0);
+    mbr_entry(&mut image, 1, 0x17, 0, 24 * 4);
+    mbr_entry(&mut image, 2, 0xEF, 64, 32);
+
+    let report = ImageReport::from_reader(&mut Cursor::new(image)).unwrap();
+    assert_eq!(report.container, Container::HybridIso);
+    assert!(!report.is_optical_only());
+    assert_eq!(report.partition_table, Some(PartitionTable::Mbr));
+    assert_eq!(report.partitions.len(), 2);
+    assert_eq!(report.partitions[1].number, 3);
+    assert_eq!(report.partitions[1].start, 64 * 512);
+    assert!(report.partitions[1].kind.is_efi_system());
+}
+
+#[test]
+fn inspect_gpt() {
+    let mut image = vec![0; 64 * 512];
+    mbr_entry(&mut image, 0, 0xEE, 1, 63);
+
+    put(&mut image, 512, b"EFI PART");
+    put(&mut image, 512 + 72, &2u64.to_le_bytes());
+    put(&mut image, 512 + 80, &4u32.to_le_bytes());
+    put(&mut image, 512 + 84, &128u32.to_le_bytes());
+
+    let entry = 2 * 512 + 128;
+    put(&mut image, entry, &ESP_GUID);
+    put(&mut image, entry + 32, &34u64.to_le_bytes());
+    put(&mut image, entry + 40, &41u64.to_le_bytes());
+    let name: Vec<u8> = "EFI".encode_utf16().flat_map(|unit| unit.to_le_bytes().to_vec()).collect();
+    put(&mut image, entry + 56, &name);
+
+    let report = ImageReport::from_reader(&mut Cursor::new(image)).unwrap();
+    assert_eq!(report.container, Container::DiskImage);
+    assert_eq!(report.partition_table, Some(PartitionTable::Gpt));
+    assert_eq!(report.boot, Some(Boot { bios: false, uefi: true }));
+
+    let partition = &report.partitions[0];
+    assert_eq!(report.partitions.len(), 1);
+    assert_eq!(partition.number, 2);
+    assert_eq!(partition.kind, PartitionType::Gpt("C12A7328-F81F-11D2-BA4B-00A0C93EC93B".into()));
+    assert_eq!((partition.start, partition.size), (34 * 512, 8 * 512));
+    assert_eq!(partition.name.as_deref(), Some("EFI"));
+}
+
+#[test]
+fn inspect_gpt_overflow() {
+    let gpt = |entries_lba: u64, first: u64, last: u64| {
+        let mut image = vec![0; 64 * 512];
+        mbr_entry(&mut image, 0, 0xEE, 1, 63);
+        put(&mut image, 512, b"EFI PART");
+        put(&mut image, 512 + 72, &entries_lba.to_le_bytes());
+        put(&mut image, 512 + 80, &1u32.to_le_bytes());
+        put(&mut image, 512 + 84, &128u32.to_le_bytes());
+        put(&mut image, 2 * 512, &ESP_GUID);
+        put(&mut image, 2 * 512 + 32, &first.to_le_bytes());
+        put(&mut image, 2 * 512 + 40, &last.to_le_bytes());
+        ImageReport::from_reader(&mut Cursor::new(image)).unwrap()
+    };
+
+    assert_eq!(gpt(2, 34, 41).partition_table, Some(PartitionTable::Gpt));
+
+    // Tables whose offsets do not fit in 64 bits are not taken to be GPTs.
+    for &(entries_lba, first, last) in &[(u64::MAX / 8, 34, 41), (2, u64::MAX / 8, 0), (2, 0, !0)] {
+        let report = gpt(entries_lba, first, last);
+        assert_eq!(report.partition_table, Some(PartitionTable::Mbr));
+        assert_eq!(report.partitions[0].kind, PartitionType::Mbr(0xEE));
+    }
+}
+
+#[test]
+fn inspect_other() {
+    let report = ImageReport::from_reader(&mut Cursor::new(vec![0x1F, 0x8B, 8, 0])).unwrap();
+    assert_eq!(report.container, Container::Compressed(Compression::Gzip));
+    assert_eq!(report.boot, None);
+
+    let report = ImageReport::from_reader(&mut Cursor::new(vec![0; 4096])).unwrap();
+    assert_eq!(report.container, Container::Unknown);
+    assert_eq!(report.boot, Some(Boot::default()));
+}
@@ -1,9 +1,10 @@
 use futures::{executor, io::AllowStdIo, prelude::*};
-use futures_codec::FramedRead;
+use futures_codec::{BytesMut, Decoder, Encoder, FramedRead};
 use popsicle::codec::*;
 use std::io::Cursor;
 
 const SAMPLE: &[u8] = include_bytes!("ipc.ron");
+const SAMPLE_V2: &[u8] = include_bytes!("ipc-v2.ron");
 
 #[test]
 fn ipc() {
@@ -44,5 +45,234 @@ fn ipc() {
         }
 
         assert_eq!(matched, expected.len());
+        assert_eq!(stream.decoder().version(), 1);
     });
 }
+
+#[test]
+fn ipc_v2() {
+    executor::block_on(async move {
+        let failure = "unexpected EOF";
+        let expected = vec![
+            Message::Hello { version: PROTOCOL_VERSION },
+            Message::Size(2229190656),
+            Message::Device("/dev/sdb".into()),
+            Message::Device("/dev/sda".into()),
+            Message::Phase("/dev/sdb".into(), Phase::Writing),
+            Message::Phase("/dev/sda".into(), Phase::Writing),
+            Message::Set("/dev/sdb".into(), 589824),
+            Message::Set("/dev/sda".into(), 589824),
+            Message::Rate("/dev/sda".into(), 31457280),
+            Message::Message("/dev/sda".into(), ["E ", failure].concat()),
+            Message::Error {
+                device: "/dev/sda".into(),
+                phase: Phase::Writing,
+                message: failure.into(),
+            },
+            Message::Result("/dev/sda".into(), Outcome::Failure(failure.into())),
+            Message::Finished("/dev/sda".into()),
+            Message::Set("/dev/sdb".into(), 2229190656),
+            Message::Message("/dev/sdb".into(), "S".into()),
+            Message::Phase("/dev/sdb".into(), Phase::Seeking),
+            Message::Message("/dev/sdb".into(), "V".into()),
+            Message::Phase("/dev/sdb".into(), Phase::Validating),
+            Message::Digest {
+                device: None,
+                algorithm: "SHA256".into(),
+                digest: "0123456789abcdef".into(),
+            },
+            Message::Result("/dev/sdb".into(), Outcome::Success),
+            Message::Finished("/dev/sdb".into()),
+        ];
+
+        let input = AllowStdIo::new(Cursor::new(SAMPLE_V2));
+        let mut stream = FramedRead::new(input, PopsicleDecoder::default());
+
+        let mut decoded = Vec::new();
+        while let Some(message) = stream.next().await {
+            decoded.push(message.unwrap());
+        }
+
+        assert_eq!(decoded, expected);
+        assert_eq!(stream.decoder().version(), 2);
+
+        let mut encoded = BytesMut::new();
+        for message in expected {
+            PopsicleEncoder::default().encode(message, &mut encoded).unwrap();
+        }
+
+        assert_eq!(&encoded[..], SAMPLE_V2);
+    });
+}
+
+#[test]
+fn ipc_unsupported_version() {
+    let mut buffer = BytesMut::from(&b"Hello(version:1000)\n"[..]);
+    match PopsicleDecoder::default().decode(&mut buffer) {
+        Err(Error::UnsupportedVersion(1000)) => (),
+        other => panic!("expected an unsupported version error, found {:?}", other),
+    }
+}
+
+#[test]
+fn ipc_round_trip() {
+    let awkward = [
+        "plain",
+        "quoted \"path\"",
+        "back\\slash\\",
+        "multi\nline\r\nmessage",
+        "tab\tand nul\0",
+        "unicode: ünïcödé ✓ \u{301}",
+        "closing paren) and comma, \",\"",
+        "",
+    ];
+
+    let mut messages = vec![Message::Size(u64::max_value())];
+    for string in awkward.iter() {
+        messages.push(Message::Device(string.into()));
+        messages.push(Message::Message(string.into(), string.to_string()));
+        messages.push(Message::Set(string.into(), 42));
+        messages.push(Message::Finished(string.into()));
+    }
+
+    for &format in &[Format::Ron, Format::Json] {
+        let mut buffer = BytesMut::new();
+        let mut encoder = PopsicleEncoder::new(format);
+        for message in &messages {
+            encoder.encode(message.clone(), &mut buffer).unwrap();
+        }
+
+        assert_eq!(buffer.iter().filter(|&&byte| byte == b'\n').count(), messages.len());
+
+        let mut decoder = PopsicleDecoder::new(format);
+        let mut decoded = Vec::new();
+        while let Some(message) = decoder.decode(&mut buffer).unwrap() {
+            decoded.push(message);
+        }
+
+        assert_eq!(decoded, messages, "{:?}", format);
+    }
+}
+
+#[test]
+fn ipc_json() {
+    let messages = vec![
+        Message::Hello { version: PROTOCOL_VERSION },
+        Message::Set("/dev/sdb".into(), 589824),
+        Message::Result("/dev/sdb".into(), Outcome::Success),
+    ];
+
+    let mut buffer = BytesMut::new();
+    let mut encoder = PopsicleEncoder::new(Format::Json);
+    for message in messages.clone() {
+        encoder.encode(message, &mut buffer).unwrap();
+    }
+
+    assert_eq!(
+        &buffer[..],
+        &b"{\"Hello\":{\"version\":2}}\n\
+           {\"Set\":[\"/dev/sdb\",589824]}\n\
+           {\"Result\":[\"/dev/sdb\",\"Success\"]}\n"[..]
+    );
+
+    let mut decoder = PopsicleDecoder::new(Format::Json);
+    let mut decoded = Vec::new();
+    while let Some(message) = decoder.decode(&mut buffer).unwrap() {
+        decoded.push(message);
+    }
+
+    assert_eq!(decoded, messages);
+    assert_eq!(decoder.version(), 2);
+}
+
+#[test]
+fn ipc_control() {
+    let controls = vec![
+        Control::Cancel("/dev/sdb".into()),
+        Control::Pause,
+        Control::Resume,
+        Control::CancelAll,
+    ];
+
+    for &format in &[Format::Ron, Format::Json] {
+        let mut buffer = BytesMut::new();
+        let mut encoder = ControlEncoder::new(format);
+        for control in &controls {
+            encoder.encode(control.clone(), &mut buffer).unwrap();
+        }
+
+        let mut decoder = ControlDecoder::new(format);
+        let mut decoded = Vec::new();
+        while let Some(control) = decoder.decode(&mut buffer).unwrap() {
+            decoded.push(control);
+        }
+
+        assert_eq!(decoded, controls, "{:?}", format);
+    }
+
+    let mut buffer = BytesMut::from(&b"Cancel(\"/dev/sdc\")\nCancelAll\n"[..]);
+    let mut decoder = ControlDecoder::default();
+    assert_eq!(decoder.decode(&mut buffer).unwrap(), Some(Control::Cancel("/dev/sdc".into())));
+    assert_eq!(decoder.decode(&mut buffer).unwrap(), Some(Control::CancelAll));
+    assert_eq!(decoder.decode(&mut buffer).unwrap(), None);
+}
+
+#[test]
+fn ipc_lenient() {
+    executor::block_on(async move {
+        let input: &[u8] = b"Hello(version:2)\n\
+            Device(\"/dev/sdb\")\n\
+            garbage\n\
+            Set(\"/dev/sdb\",\n\
+            Set(\"/dev/sdb\",589824)\n\
+            Message(\"/dev/sdb\",\"this line is far too long to be buffered\")\n\
+            Set(\"/dev/sdb\",1179648)\n\
+            Finished(\"/dev/sdb\")";
+
+        let decoder = PopsicleDecoder::default().max_line_length(32).lenient();
+        let mut stream = FramedRead::new(AllowStdIo::new(Cursor::new(input)), decoder);
+
+        let mut messages = Vec::new();
+        let (mut malformed, mut overlong) = (0, 0);
+        while let Some(item) = stream.next().await {
+            match item.unwrap() {
+                Decoded::Message(message) => messages.push(message),
+                Decoded::Malformed(Error::Decode { .. }) => malformed += 1,
+                Decoded::Malformed(Error::LineTooLong(32)) => overlong += 1,
+                Decoded::Malformed(why) => panic!("unexpected error: {}", why),
+            }
+        }
+
+        assert_eq!(
+            messages,
+            vec![
+                Message::Hello { version: 2 },
+                Message::Device("/dev/sdb".into()),
+                Message::Set("/dev/sdb".into(), 589824),
+                Message::Set("/dev/sdb".into(), 1179648),
+                Message::Finished("/dev/sdb".into()),
+            ]
+        );
+
+        assert_eq!((malformed, overlong), (2, 1));
+        assert_eq!(stream.decoder().version(), 2);
+    });
+}
+
+#[test]
+fn ipc_line_too_long() {
+    let mut decoder = PopsicleDecoder::default().max_line_length(16);
+
+    // The line is discarded as it arrives, without waiting for its newline.
+    let mut buffer = BytesMut::from(&b"Message(\"/dev/sdb\",\"overlong"[..]);
+    match decoder.decode(&mut buffer) {
+        Err(Error::LineTooLong(16)) => (),
+        other => panic!("expected a line too long error, found {:?}", other),
+    }
+
+    assert!(buffer.is_empty());
+
+    buffer.extend_from_slice(b" message\")\nSize(42)\n");
+    assert_eq!(decoder.decode(&mut buffer).unwrap(), Some(Message::Size(42)));
+    assert_eq!(decoder.decode(&mut buffer).unwrap(), None);
+}
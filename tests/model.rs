@@ -0,0 +1,123 @@
+use futures::{executor, io::AllowStdIo, prelude::*};
+use futures_codec::FramedRead;
+use popsicle::{
+    codec::*,
+    hash::{Algorithm, Hasher},
+    DeviceStatus, MessageProgress, ProgressChange, ProgressModel,
+};
+use std::{
+    io::Cursor,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+fn model_of(sample: &[u8]) -> ProgressModel {
+    executor::block_on(async move {
+        let input = AllowStdIo::new(Cursor::new(sample));
+        let mut stream = FramedRead::new(input, PopsicleDecoder::default());
+        let mut model = ProgressModel::default();
+
+        while let Some(message) = stream.next().await {
+            model.apply(&message.unwrap());
+        }
+
+        model
+    })
+}
+
+#[test]
+fn model_v1() {
+    let model = model_of(include_bytes!("ipc.ron"));
+
+    assert_eq!(model.version(), 1);
+    assert_eq!(model.size(), Some(2229190656));
+    assert!(model.is_finished());
+
+    let paths: Vec<&Path> = model.devices().iter().map(|device| &*device.path).collect();
+    assert_eq!(paths, [Path::new("/dev/sdb"), Path::new("/dev/sda")]);
+
+    for device in model.devices() {
+        assert_eq!(device.phase, Phase::Validating);
+        assert_eq!(device.status, DeviceStatus::Succeeded);
+        assert_eq!(device.rate(), None);
+    }
+}
+
+#[test]
+fn model_v2() {
+    let model = model_of(include_bytes!("ipc-v2.ron"));
+
+    assert_eq!(model.version(), 2);
+    assert!(model.is_finished());
+
+    let sda = model.device(Path::new("/dev/sda")).unwrap();
+    assert_eq!(sda.phase, Phase::Writing);
+    assert_eq!(sda.status, DeviceStatus::Failed("unexpected EOF".into()));
+
+    let sdb = model.device(Path::new("/dev/sdb")).unwrap();
+    assert_eq!(sdb.phase, Phase::Validating);
+    assert_eq!(sdb.status, DeviceStatus::Succeeded);
+
+    assert_eq!(model.digests().len(), 1);
+    assert_eq!(model.digests()[0].algorithm, "SHA256");
+}
+
+#[test]
+fn model_digests() {
+    let mut hasher = Hasher::new(&[Algorithm::Sha256]);
+    hasher.update(b"abc");
+
+    let mut model = ProgressModel::default();
+    for message in MessageProgress::digests(&hasher.finish()) {
+        assert_eq!(model.apply(&message), Some(ProgressChange::Digest));
+    }
+
+    let digest = &model.digests()[0];
+    assert_eq!((model.digests().len(), digest.device.as_deref()), (1, None));
+    assert_eq!(digest.algorithm, "SHA256");
+    assert_eq!(digest.digest, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+}
+
+#[test]
+fn model_rate() {
+    let start = Instant::now();
+    let at = |secs| start + Duration::from_secs(secs);
+    let sdb = || "/dev/sdb".into();
+
+    let mut model = ProgressModel::default();
+    assert_eq!(model.apply_at(&Message::Size(1000), at(0)), Some(ProgressChange::Size(1000)));
+    assert_eq!(model.apply_at(&Message::Device(sdb()), at(0)), Some(ProgressChange::Added(sdb())));
+    assert_eq!(model.apply_at(&Message::Device(sdb()), at(0)), None);
+
+    // Version 1 streams have their rate averaged over the phase.
+    model.apply_at(&Message::Set(sdb(), 200), at(2));
+    let device = model.device(Path::new("/dev/sdb")).unwrap();
+    assert_eq!(device.percentage(), 20.0);
+    assert_eq!(device.rate(), Some(100));
+    assert_eq!(device.eta(), Some(Duration::from_secs(8)));
+
+    // Reported rates take precedence.
+    model.apply_at(&Message::Rate(sdb(), 400), at(2));
+    let device = model.device(Path::new("/dev/sdb")).unwrap();
+    assert_eq!(device.eta(), Some(Duration::from_secs(2)));
+
+    assert_eq!(
+        model.apply_at(&Message::Message(sdb(), "V".into()), at(3)),
+        Some(ProgressChange::Phase(sdb()))
+    );
+
+    let device = model.device(Path::new("/dev/sdb")).unwrap();
+    assert_eq!(device.phase, Phase::Validating);
+    assert_eq!(device.written, 0);
+    assert_eq!(device.rate(), None);
+
+    assert_eq!(
+        model.apply_at(&Message::Message(sdb(), "C".into()), at(4)),
+        Some(ProgressChange::Status(sdb()))
+    );
+    assert_eq!(model.apply_at(&Message::Finished(sdb()), at(4)), None);
+
+    let device = model.device(Path::new("/dev/sdb")).unwrap();
+    assert_eq!(device.status, DeviceStatus::Cancelled);
+    assert!(model.is_finished());
+}
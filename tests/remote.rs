@@ -0,0 +1,138 @@
+mod common;
+
+use common::{open, NoProgress, Scratch};
+use futures::{executor, io::AllowStdIo};
+use popsicle::{
+    hash::{Algorithm, Checksum, ChecksumMismatch, Hasher},
+    ImageSize, RemoteImage, Task,
+};
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+/// Serves `image` over HTTP, recording the `Range` of each request.
+///
+/// The first response is cut off halfway, as though the connection was lost, and ranges are
+/// only honoured if `ranges` is set.
+fn serve(image: Vec<u8>, ranges: bool) -> (String, Arc<Mutex<Vec<Option<String>>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/pop.iso", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(Vec::new()));
+
+    let recorded = requests.clone();
+    thread::spawn(move || {
+        for (index, stream) in listener.incoming().enumerate() {
+            let range = respond(stream.unwrap(), &image, ranges, index == 0);
+            recorded.lock().unwrap().push(range);
+        }
+    });
+
+    (url, requests)
+}
+
+fn respond(mut stream: TcpStream, image: &[u8], ranges: bool, cut_off: bool) -> Option<String> {
+    let mut range = None;
+    for line in BufReader::new(stream.try_clone().unwrap()).lines() {
+        let line = line.unwrap();
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some(value) = line.strip_prefix("Range: ") {
+            range = Some(value.to_owned());
+        }
+    }
+
+    let start = match range.as_ref().and_then(|range| range.strip_prefix("bytes=")) {
+        Some(start) if ranges => start.trim_end_matches('-').parse::<usize>().unwrap(),
+        _ => 0,
+    };
+
+    let status = if start > 0 { "206 Partial Content" } else { "200 OK" };
+    let body = &image[start..];
+    let sent = if cut_off { &body[..body.len() / 2] } else { body };
+
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        body.len()
+    );
+    let _ = stream.write_all(sent);
+    range
+}
+
+fn sha256(data: &[u8]) -> Checksum {
+    let mut hasher = Hasher::new(&[Algorithm::Sha256]);
+    hasher.update(data);
+    Checksum::new(Algorithm::Sha256, hasher.finish().get(Algorithm::Sha256).unwrap()).unwrap()
+}
+
+#[test]
+fn remote_resume() {
+    let data: Vec<u8> = (0..300_000u32).map(|x| (x % 251) as u8).collect();
+    let (url, requests) = serve(data.clone(), true);
+
+    let mut image = RemoteImage::open(&url).unwrap().with_checksum(sha256(&data));
+    assert_eq!(image.size(), Some(300_000));
+
+    let mut read = Vec::new();
+    image.read_to_end(&mut read).unwrap();
+    assert_eq!(read, data);
+    assert_eq!(*requests.lock().unwrap(), vec![None, Some("bytes=150000-".to_owned())]);
+
+    // Seeking is performed with ranges.
+    image.seek(SeekFrom::End(-10)).unwrap();
+    let mut tail = Vec::new();
+    image.read_to_end(&mut tail).unwrap();
+    assert_eq!(tail, &data[data.len() - 10..]);
+}
+
+#[test]
+fn remote_checksum_mismatch() {
+    let data: Vec<u8> = (0..100_000u32).map(|x| (x % 13) as u8).collect();
+    let (url, _) = serve(data, true);
+
+    let mut image = RemoteImage::open(&url).unwrap().with_checksum(sha256(b"other"));
+    let why = image.read_to_end(&mut Vec::new()).unwrap_err();
+    assert!(why.to_string().contains("SHA256 checksum mismatch"));
+    assert!(why.get_ref().unwrap().is::<ChecksumMismatch>());
+
+    assert!(Checksum::new(Algorithm::Sha1, sha256(b"abc").digest()).is_err());
+}
+
+#[test]
+fn remote_without_ranges() {
+    let data = vec![7u8; 100_000];
+    let (url, _) = serve(data, false);
+
+    let mut image = RemoteImage::open(&url).unwrap();
+    let why = image.read_to_end(&mut Vec::new()).unwrap_err();
+    assert!(why.to_string().contains("does not support resuming"));
+}
+
+#[test]
+fn remote_flash() {
+    let data: Vec<u8> = (0..200_000u32).map(|x| (x % 251) as u8).collect();
+    let (url, _) = serve(data.clone(), true);
+
+    let mut image = RemoteImage::open(&url).unwrap().with_checksum(sha256(&data));
+    let len = image.size().unwrap();
+    let size = ImageSize::from_reader(&mut image, len).unwrap();
+    assert_eq!(size.data, 200_000);
+    image.seek(SeekFrom::Start(0)).unwrap();
+
+    let scratch = Scratch::new("remote");
+    let device = scratch.file("device", b"");
+    executor::block_on(async {
+        let mut task = Task::new(AllowStdIo::new(image), true);
+        task.subscribe(open(&device).await, (), NoProgress);
+        task.process(&mut [0u8; 64 * 1024]).await.unwrap();
+    });
+
+    assert_eq!(fs::read(&device).unwrap(), data);
+}
@@ -0,0 +1,135 @@
+mod common;
+
+use async_std::path::Path as AsyncPath;
+use common::Scratch;
+use popsicle::{check_device_in, Bus, Device, DiskError, SafetyPolicy, DEFAULT_MAX_SIZE};
+use std::{fs, os::unix::fs::symlink, path::Path};
+
+const ATA: &str = "pci0000:00/0000:00:17.0/ata1/host0/target0:0:0/0:0:0:0/block";
+const MMC: &str = "platform/fe320000.mmc/mmc_host";
+
+fn device(name: &str, bus: Bus, removable: bool, size: u64) -> Device {
+    Device {
+        path: Path::new("/dev").join(name).into(),
+        name: name.into(),
+        vendor: None,
+        model: None,
+        serial: None,
+        size,
+        removable,
+        bus,
+        usb_speed: None,
+        port: None,
+        partitions: Vec::new(),
+        mounts: Vec::new(),
+    }
+}
+
+/// A system with its root filesystem on `sda2`, `/boot` on a RAID array of `sdb1` and
+/// `sdc1`, and swap on `sdd1`. The disk `sdaa` is not in use.
+fn system(scratch: &Scratch) {
+    for disk in &["sda", "sdaa", "sdb", "sdc", "sdd"] {
+        scratch.block_device(ATA, disk);
+        let partition = scratch.block_device(&format!("{}/{}", ATA, disk), &format!("{}1", disk));
+        fs::write(partition.join("partition"), "1").unwrap();
+    }
+
+    let partition = scratch.block_device(&format!("{}/sda", ATA), "sda2");
+    fs::write(partition.join("partition"), "2").unwrap();
+
+    let raid = scratch.block_device("virtual/block", "md0");
+    fs::create_dir_all(raid.join("slaves")).unwrap();
+    for member in &["sdb1", "sdc1"] {
+        let member_sys = scratch.path(&format!("sys/class/block/{}", member));
+        symlink(fs::canonicalize(member_sys).unwrap(), raid.join("slaves").join(member)).unwrap();
+    }
+
+    let swap = scratch.path("dev/sdd1");
+    let swaps =
+        format!("Filename Type Size Used Priority\n{} partition 1024 0 -2\n", swap.display());
+    scratch.file("proc/swaps", swaps.as_bytes());
+}
+
+fn check(scratch: &Scratch, device: &Device, policy: &SafetyPolicy) -> Result<(), DiskError> {
+    let mounts = scratch.mounts(
+        "ROOT/dev/sda2 / ext4 rw 0 0\n\
+         ROOT/dev/md0 /boot ext4 rw 0 0\n\
+         ROOT/dev/sdaa1 /media/user/DATA ext4 rw 0 0",
+    );
+
+    check_device_in(&scratch.path(""), AsyncPath::new(&*device.path), device, &mounts, policy)
+}
+
+#[test]
+fn safety_system_disks() {
+    let scratch = Scratch::new("safety-system");
+    system(&scratch);
+
+    // Disks the running system depends upon are refused, even when unsafe devices are allowed.
+    let policy = SafetyPolicy { allow_unsafe: true, max_size: None };
+    let refused = |name: &str| check(&scratch, &device(name, Bus::Usb, true, 1 << 30), &policy);
+
+    let system_mount = |name: &str| match refused(name) {
+        Err(DiskError::SystemDisk { mount, .. }) => Some(mount.display().to_string()),
+        _ => None,
+    };
+
+    assert_eq!(system_mount("sda").as_deref(), Some("/"));
+    assert_eq!(system_mount("sdb").as_deref(), Some("/boot"));
+    assert_eq!(system_mount("sdc").as_deref(), Some("/boot"));
+
+    // Swap on an internal disk is the system's, while swap on a USB drive is left to be disabled.
+    let internal = device("sdd", Bus::Ata, false, 1 << 30);
+    assert!(matches!(check(&scratch, &internal, &policy), Err(DiskError::SystemSwap { .. })));
+    assert!(refused("sdd").is_ok());
+
+    // A disk whose name begins with the name of a system disk is not a system disk.
+    assert!(refused("sdaa").is_ok());
+}
+
+#[test]
+fn safety_policy() {
+    let scratch = Scratch::new("safety-policy");
+    system(&scratch);
+
+    let internal = device("sdaa", Bus::Ata, false, 1 << 30);
+    let usb = device("sdaa", Bus::Usb, false, 1 << 30);
+    let large = device("sdaa", Bus::Usb, true, DEFAULT_MAX_SIZE + 1);
+
+    let default = SafetyPolicy::default();
+    assert!(matches!(check(&scratch, &internal, &default), Err(DiskError::NotRemovable { .. })));
+    assert!(check(&scratch, &usb, &default).is_ok());
+    assert!(matches!(check(&scratch, &large, &default), Err(DiskError::TooLarge { .. })));
+
+    // Allowing unsafe devices does not lift the size limit, which has its own setting.
+    let allow_unsafe = SafetyPolicy { allow_unsafe: true, ..default };
+    assert!(check(&scratch, &internal, &allow_unsafe).is_ok());
+    assert!(matches!(check(&scratch, &large, &allow_unsafe), Err(DiskError::TooLarge { .. })));
+
+    let unlimited = SafetyPolicy { max_size: None, ..default };
+    assert!(check(&scratch, &large, &unlimited).is_ok());
+}
+
+#[test]
+fn safety_mmc() {
+    let scratch = Scratch::new("safety-mmc");
+    system(&scratch);
+
+    // The kernel reports the kind of card which each MMC block device is on.
+    for &(host, name, kind) in &[("mmc0", "mmcblk0", "MMC"), ("mmc1", "mmcblk1", "SD")] {
+        let card = format!("{}/{}/{}:0001", MMC, host, host);
+        let disk = scratch.block_device(&format!("{}/block", card), name);
+        symlink(scratch.path(&format!("sys/devices/{}", card)), disk.join("device")).unwrap();
+        scratch.file(&format!("sys/devices/{}/type", card), format!("{}\n", kind).as_bytes());
+    }
+
+    // Soldered eMMC is refused like any other internal disk, while SD cards are not.
+    let default = SafetyPolicy::default();
+    let emmc = device("mmcblk0", Bus::Mmc, false, 1 << 30);
+    let sd = device("mmcblk1", Bus::Mmc, false, 1 << 30);
+    assert!(matches!(check(&scratch, &emmc, &default), Err(DiskError::NotRemovable { .. })));
+    assert!(check(&scratch, &sd, &default).is_ok());
+
+    let allow_unsafe = SafetyPolicy { allow_unsafe: true, ..default };
+    assert!(check(&scratch, &emmc, &allow_unsafe).is_ok());
+}
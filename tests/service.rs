@@ -0,0 +1,174 @@
+mod common;
+
+use common::{Bus, FileDevices, Scratch};
+use dbus::{
+    arg::{PropMap, RefArg, Variant},
+    blocking::Connection,
+    channel::MatchingReceiver,
+    message::{MatchRule, MessageType},
+    Path as ObjectPath,
+};
+use popsicle::service;
+use std::{
+    fs,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The options of a flash which validates each device.
+fn check() -> PropMap {
+    let mut options = PropMap::new();
+    options.insert("check".into(), Variant(Box::new(true) as Box<dyn RefArg>));
+    options
+}
+
+type Signals = Arc<Mutex<Vec<(String, Vec<String>)>>>;
+
+/// Records every signal of the job interface.
+fn record_signals(connection: &Connection) -> Signals {
+    let signals = Signals::default();
+    let rule =
+        MatchRule::new().with_type(MessageType::Signal).with_interface(service::JOB_INTERFACE);
+
+    connection.add_match_no_cb(&rule.match_str()).unwrap();
+
+    let recorded = signals.clone();
+    connection.start_receive(
+        rule,
+        Box::new(move |message, _| {
+            let member = message.member().unwrap().to_string();
+            let args = message
+                .iter_init()
+                .map(|arg| match arg.as_str() {
+                    Some(text) => text.to_owned(),
+                    None => arg.as_u64().unwrap().to_string(),
+                })
+                .collect();
+
+            recorded.lock().unwrap().push((member, args));
+            true
+        }),
+    );
+
+    signals
+}
+
+/// Processes messages until the job has emitted `Finished`, returning its arguments.
+fn wait_for_finish(connection: &Connection, signals: &Signals) -> Vec<String> {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while Instant::now() < deadline {
+        connection.process(Duration::from_millis(50)).unwrap();
+        let signals = signals.lock().unwrap();
+        if let Some((_, args)) = signals.iter().find(|(member, _)| member == "Finished") {
+            return args.clone();
+        }
+    }
+
+    panic!("the job did not finish");
+}
+
+// Requires `dbus-daemon`, so it is only run by `cargo test -- --ignored`.
+#[test]
+#[ignore]
+fn service() {
+    let bus = Bus::start();
+
+    let image_data: Vec<u8> = (0..200_000u32).map(|x| (x % 251) as u8).collect();
+    let scratch = Scratch::new("service");
+    let image = scratch.file("image", &image_data);
+    let first = scratch.file("first", b"");
+    let second = scratch.file("second", b"");
+
+    let server = bus.connect();
+    let devices = FileDevices(vec![first.clone(), second.clone()]);
+    thread::spawn(move || service::serve(server, devices));
+
+    let client = bus.connect();
+    let signals = record_signals(&client);
+    let manager = client.with_proxy(service::NAME, service::PATH, Duration::from_secs(5));
+
+    let listed: (Vec<(String, String, u64)>,) = (0..50)
+        .find_map(|_| {
+            let listed = manager.method_call(service::MANAGER_INTERFACE, "ListDevices", ());
+            if listed.is_err() {
+                thread::sleep(Duration::from_millis(20));
+            }
+
+            listed.ok()
+        })
+        .expect("the service did not start");
+
+    assert_eq!(listed.0.len(), 2);
+    assert_eq!(listed.0[0].0, first.display().to_string());
+
+    let targets = vec![first.display().to_string(), second.display().to_string()];
+    let (job,): (ObjectPath<'static>,) = manager
+        .method_call(
+            service::MANAGER_INTERFACE,
+            "Flash",
+            (image.display().to_string(), targets.clone(), check()),
+        )
+        .unwrap();
+
+    assert_eq!(&*job, "/com/system76/Popsicle1/Job/0");
+    assert_eq!(wait_for_finish(&client, &signals), vec!["1".to_owned(), String::new()]);
+
+    // Finished jobs are removed.
+    let finished: Result<(), _> =
+        manager.method_call(service::MANAGER_INTERFACE, "Cancel", (job.clone(),));
+    assert!(finished.is_err());
+    let job_proxy = client.with_proxy(service::NAME, job, Duration::from_secs(5));
+    let paused: Result<(), _> = job_proxy.method_call(service::JOB_INTERFACE, "Pause", ());
+    assert!(paused.is_err());
+    assert_eq!(fs::read(&first).unwrap(), image_data);
+    assert_eq!(fs::read(&second).unwrap(), image_data);
+
+    {
+        let signals = signals.lock().unwrap();
+        let members: Vec<&str> = signals.iter().map(|(member, _)| member.as_str()).collect();
+        assert!(members.contains(&"Size"));
+        assert!(members.contains(&"Progress"));
+        assert!(signals.contains(&(
+            "Phase".to_owned(),
+            vec![first.display().to_string(), "validating".to_owned()]
+        )));
+
+        for target in &targets {
+            assert!(signals.contains(&(
+                "Result".to_owned(),
+                vec![target.clone(), "success".to_owned(), String::new()]
+            )));
+        }
+    }
+
+    // A job which cannot open its devices finishes with the reason.
+    signals.lock().unwrap().clear();
+    let missing = vec!["/nonexistent".to_owned()];
+    let _: (ObjectPath<'static>,) = manager
+        .method_call(
+            service::MANAGER_INTERFACE,
+            "Flash",
+            (image.display().to_string(), missing, check()),
+        )
+        .unwrap();
+
+    let finished = wait_for_finish(&client, &signals);
+    assert_eq!(finished[0], "0");
+    assert!(finished[1].contains("'/nonexistent' is not a device"));
+
+    // Invalid requests are refused.
+    let relative: Result<(ObjectPath<'static>,), _> = manager.method_call(
+        service::MANAGER_INTERFACE,
+        "Flash",
+        ("image.iso".to_owned(), targets, check()),
+    );
+    assert!(relative.is_err());
+
+    let unknown: Result<(), _> = manager.method_call(
+        service::MANAGER_INTERFACE,
+        "Cancel",
+        (ObjectPath::from("/com/system76/Popsicle1/Job/9"),),
+    );
+    assert!(unknown.is_err());
+}
@@ -0,0 +1,132 @@
+use popsicle::{
+    checksums,
+    signature::{self, Keys, SignatureKind, Verifiers},
+};
+use std::{env, fs, os::unix::fs::PermissionsExt, path::Path, process, process::Command};
+
+const SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+fn gpg(home: &Path, args: &[&str]) -> bool {
+    Command::new("gpg")
+        .env("GNUPGHOME", home)
+        .arg("--batch")
+        .arg("--quiet")
+        .args(args)
+        .status()
+        .map_or(false, |status| status.success())
+}
+
+#[test]
+// Requires `gpg` and `gpgv`, so it is only run by `cargo test -- --ignored`.
+#[ignore]
+fn signature_gpg() {
+    let directory = env::temp_dir().join(format!("popsicle-signature-{}", process::id()));
+    let home = directory.join("gnupg");
+    fs::create_dir_all(&home).unwrap();
+    fs::set_permissions(&home, fs::Permissions::from_mode(0o700)).unwrap();
+
+    let generated = gpg(
+        &home,
+        &["--passphrase", "", "--quick-gen-key", "Popsicle Test <test@example.com>", "ed25519"],
+    );
+    assert!(generated, "failed to generate a key with gpg");
+
+    let sums = directory.join("SHA256SUMS");
+    let keyring = directory.join("trusted.gpg");
+    fs::write(&sums, format!("{}  pop-os.iso\n", SHA256)).unwrap();
+    let keyring_arg = keyring.to_str().unwrap();
+    assert!(gpg(&home, &["--output", keyring_arg, "--export"]));
+
+    // Unsigned files are not an error, unless a key is configured, which they must be signed by.
+    let keys = Keys { keyring: Some(keyring.clone()), minisign_key: None };
+    assert_eq!(signature::check(&sums, &Keys::default()).unwrap(), None);
+    assert!(signature::check(&sums, &keys).unwrap_err().is_rejected());
+
+    assert!(gpg(&home, &["--detach-sign", sums.to_str().unwrap()]));
+    let found = signature::find(&sums, &keys).unwrap();
+    assert_eq!(found.kind, SignatureKind::Gpg);
+    assert_eq!(found.path, directory.join("SHA256SUMS.sig"));
+
+    let signer = signature::check(&sums, &keys).unwrap().unwrap();
+    assert_eq!(signer.name, "Popsicle Test <test@example.com>");
+
+    let why = signature::check(&sums, &Keys::default()).unwrap_err();
+    assert!(!why.is_rejected());
+
+    // Signatures by keys which are not in the configured keyring are rejected.
+    let empty = directory.join("empty.gpg");
+    fs::write(&empty, b"").unwrap();
+    let untrusted = Keys { keyring: Some(empty), minisign_key: None };
+    assert!(signature::check(&sums, &untrusted).unwrap_err().is_rejected());
+
+    // A checksum which was changed after signing is rejected.
+    fs::write(&sums, format!("{}  pop-os.iso\n", SHA256.replace('b', "c"))).unwrap();
+    assert!(signature::check(&sums, &keys).unwrap_err().is_rejected());
+
+    // Only the signed text of a clearsigned file is trusted.
+    let clearsigned = directory.join("CHECKSUM");
+    fs::write(&sums, format!("{}  pop-os.iso\n", SHA256)).unwrap();
+    let clearsigned_arg = clearsigned.to_str().unwrap();
+    assert!(gpg(&home, &["--output", clearsigned_arg, "--clearsign", sums.to_str().unwrap()]));
+    let mut contents = fs::read_to_string(&clearsigned).unwrap();
+    contents.push_str(&format!("{}  other.iso\n", SHA256));
+    fs::write(&clearsigned, contents).unwrap();
+
+    assert_eq!(signature::find(&clearsigned, &keys).unwrap().path, clearsigned);
+    assert!(signature::check(&clearsigned, &keys).unwrap().is_some());
+    assert!(checksums::checksums_for(&clearsigned, "pop-os.iso").is_ok());
+    assert!(checksums::checksums_for(&clearsigned, "other.iso").is_err());
+
+    let _ = fs::remove_dir_all(&directory);
+}
+
+/// minisign verifies this signature, if its file is a copy of the signed file.
+const MINISIGN: &str = "#!/bin/sh
+while getopts Vqp:m:x: option; do
+    case $option in
+        m) message=$OPTARG ;;
+        x) signature=$OPTARG ;;
+    esac
+done
+
+cmp -s \"$message\" \"$signature\" || { echo 'Signature verification failed' >&2; exit 1; }
+";
+
+#[test]
+fn signature_minisign() {
+    let directory = env::temp_dir().join(format!("popsicle-minisign-{}", process::id()));
+    let bin = directory.join("bin");
+    fs::create_dir_all(&bin).unwrap();
+
+    // minisign is rarely installed, so a stand-in is run in its place.
+    let minisign = bin.join("minisign");
+    fs::write(&minisign, MINISIGN).unwrap();
+    fs::set_permissions(&minisign, fs::Permissions::from_mode(0o755)).unwrap();
+    let verifiers = Verifiers { minisign, ..Verifiers::default() };
+
+    let sums = directory.join("SHA256SUMS");
+    let key = directory.join("minisign.pub");
+    fs::write(&sums, format!("{}  pop-os.iso\n", SHA256)).unwrap();
+    fs::write(&key, "untrusted comment: minisign public key 5C0A7EE0\nRWQ=\n").unwrap();
+    fs::copy(&sums, directory.join("SHA256SUMS.minisig")).unwrap();
+    fs::write(directory.join("SHA256SUMS.sig"), b"").unwrap();
+
+    let keys = Keys { keyring: None, minisign_key: Some(key) };
+    let signer = signature::check_with(&sums, &keys, &verifiers).unwrap().unwrap();
+    assert_eq!(
+        (signer.kind, signer.name.as_str()),
+        (SignatureKind::Minisign, "minisign public key 5C0A7EE0")
+    );
+
+    // The signature which a configured key can verify is preferred.
+    let keyring = Keys { keyring: Some(directory.join("trusted.gpg")), minisign_key: None };
+    assert_eq!(signature::find(&sums, &keyring).unwrap().kind, SignatureKind::Gpg);
+    assert_eq!(signature::find(&sums, &Keys::default()).unwrap().kind, SignatureKind::Minisign);
+
+    fs::write(&sums, format!("{}  pop-os.iso\n", SHA256.replace('b', "c"))).unwrap();
+    let why = signature::check_with(&sums, &keys, &verifiers).unwrap_err();
+    assert!(why.is_rejected());
+    assert!(why.to_string().contains("Signature verification failed"));
+
+    let _ = fs::remove_dir_all(&directory);
+}
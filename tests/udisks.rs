@@ -0,0 +1,191 @@
+mod common;
+
+use common::{Bus, Scratch};
+use dbus::{arg::OwnedFd, arg::PropMap, blocking::Connection, Path as ObjectPath};
+use dbus_crossroads::{Context, Crossroads};
+use popsicle::UDisks2;
+use std::{
+    fs,
+    io::{Read, Write},
+    os::unix::io::IntoRawFd,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+/// The state of an object exported by the mock UDisks2 service.
+#[derive(Default)]
+struct MockObject {
+    device: &'static str,
+    size: u64,
+    drive: &'static str,
+    table: &'static str,
+    bus: &'static str,
+    mount_points: Vec<&'static str>,
+    backing: Option<PathBuf>,
+    calls: Arc<Mutex<Vec<String>>>,
+}
+
+fn bytes(path: &str) -> Vec<u8> {
+    let mut bytes = path.as_bytes().to_vec();
+    bytes.push(0);
+    bytes
+}
+
+/// Serves a USB drive with one mounted partition, and an internal SATA drive.
+fn serve_mock(connection: Connection, backing: &Path, calls: Arc<Mutex<Vec<String>>>) {
+    connection.request_name("org.freedesktop.UDisks2", false, true, false).unwrap();
+
+    let mut cr = Crossroads::new();
+
+    let drive = cr.register("org.freedesktop.UDisks2.Drive", |b| {
+        b.property("ConnectionBus").get(|_, mock: &mut MockObject| Ok(mock.bus.to_owned()));
+    });
+
+    let block = cr.register("org.freedesktop.UDisks2.Block", |b| {
+        b.property("Device").get(|_, mock: &mut MockObject| Ok(bytes(mock.device)));
+        b.property("Size").get(|_, mock: &mut MockObject| Ok(mock.size));
+        b.property("HintIgnore").get(|_, _: &mut MockObject| Ok(false));
+        b.property("Drive")
+            .get(|_, mock: &mut MockObject| Ok(ObjectPath::from(mock.drive.to_owned())));
+        b.method(
+            "OpenDevice",
+            ("mode", "options"),
+            ("fd",),
+            |_: &mut Context, mock: &mut MockObject, (mode, _): (String, PropMap)| {
+                mock.calls.lock().unwrap().push(format!("open {} {}", mock.device, mode));
+                let file = fs::OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(mock.backing.as_ref().unwrap())
+                    .unwrap();
+
+                Ok((unsafe { OwnedFd::new(file.into_raw_fd()) },))
+            },
+        );
+    });
+
+    let partition = cr.register("org.freedesktop.UDisks2.Partition", |b| {
+        b.property("Table")
+            .get(|_, mock: &mut MockObject| Ok(ObjectPath::from(mock.table.to_owned())));
+    });
+
+    let filesystem = cr.register("org.freedesktop.UDisks2.Filesystem", |b| {
+        b.property("MountPoints").get(|_, mock: &mut MockObject| {
+            Ok(mock.mount_points.iter().map(|point| bytes(point)).collect::<Vec<_>>())
+        });
+        b.method(
+            "Unmount",
+            ("options",),
+            (),
+            |_: &mut Context, mock: &mut MockObject, (options,): (PropMap,)| {
+                let force = options.get("force").and_then(|force| force.0.as_u64()) == Some(1);
+                mock.calls.lock().unwrap().push(format!("unmount {} {}", mock.device, force));
+                mock.mount_points.clear();
+                Ok(())
+            },
+        );
+    });
+
+    let manager = cr.object_manager();
+    cr.insert("/org/freedesktop/UDisks2", &[manager], MockObject::default());
+
+    let objects = vec![
+        ("drives/usb", vec![drive], MockObject { bus: "usb", ..MockObject::default() }),
+        ("drives/sata", vec![drive], MockObject { bus: "sata", ..MockObject::default() }),
+        (
+            "block_devices/sda",
+            vec![block],
+            MockObject {
+                device: "/dev/sda",
+                size: 1 << 40,
+                drive: "/org/freedesktop/UDisks2/drives/sata",
+                ..MockObject::default()
+            },
+        ),
+        (
+            "block_devices/sdb",
+            vec![block],
+            MockObject {
+                device: "/dev/sdb",
+                size: 1 << 30,
+                drive: "/org/freedesktop/UDisks2/drives/usb",
+                backing: Some(backing.to_path_buf()),
+                calls: calls.clone(),
+                ..MockObject::default()
+            },
+        ),
+        (
+            "block_devices/sdb1",
+            vec![block, partition, filesystem],
+            MockObject {
+                device: "/dev/sdb1",
+                size: 1 << 29,
+                drive: "/org/freedesktop/UDisks2/drives/usb",
+                table: "/org/freedesktop/UDisks2/block_devices/sdb",
+                mount_points: vec!["/media/user/USB"],
+                calls,
+                ..MockObject::default()
+            },
+        ),
+    ];
+
+    for (name, ifaces, object) in objects {
+        cr.insert(format!("/org/freedesktop/UDisks2/{}", name), &ifaces, object);
+    }
+
+    let _ = cr.serve(&connection);
+}
+
+// Requires `dbus-daemon`, so it is only run by `cargo test -- --ignored`.
+#[test]
+#[ignore]
+fn udisks() {
+    let bus = Bus::start();
+
+    let scratch = Scratch::new("udisks");
+    let backing = scratch.file("backing", b"");
+
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let (connection, mock_backing, mock_calls) = (bus.connect(), backing.clone(), calls.clone());
+    thread::spawn(move || serve_mock(connection, &mock_backing, mock_calls));
+
+    let udisks = (0..50)
+        .find_map(|_| {
+            let udisks = UDisks2::connect(&bus.address).unwrap();
+            if udisks.blocks().map_or(false, |blocks| !blocks.is_empty()) {
+                Some(udisks)
+            } else {
+                thread::sleep(std::time::Duration::from_millis(20));
+                None
+            }
+        })
+        .expect("the mock UDisks2 service did not start");
+
+    let disks = udisks.usb_disks().unwrap();
+    assert_eq!(disks.len(), 1);
+    assert_eq!(disks[0].device, Path::new("/dev/sdb"));
+    assert_eq!(disks[0].size, 1 << 30);
+    assert_eq!(disks[0].table, None);
+
+    let partition = udisks.block(Path::new("/dev/sdb1")).unwrap();
+    assert_eq!(partition.table, Some(disks[0].object.clone()));
+    assert_eq!(partition.mount_points, vec![PathBuf::from("/media/user/USB")]);
+    assert!(udisks.block(Path::new("/dev/sdz")).is_err());
+
+    udisks.teardown(&disks[0], true).unwrap();
+    assert!(udisks.block(Path::new("/dev/sdb1")).unwrap().mount_points.is_empty());
+
+    let mut file = udisks.open(&disks[0]).unwrap();
+    file.write_all(b"flashed").unwrap();
+    drop(file);
+
+    let mut written = String::new();
+    fs::File::open(&backing).unwrap().read_to_string(&mut written).unwrap();
+    assert_eq!(written, "flashed");
+
+    assert_eq!(
+        *calls.lock().unwrap(),
+        vec!["unmount /dev/sdb1 true".to_owned(), "open /dev/sdb rw".to_owned()]
+    );
+}
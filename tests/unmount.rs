@@ -0,0 +1,50 @@
+use async_std::path::Path as AsyncPath;
+use popsicle::{
+    mnt::MountEntry, teardown, unmount_order, DiskError, Holder, HolderKind, UnmountFlags,
+};
+use std::path::{Path, PathBuf};
+
+fn mounts(table: &str) -> Vec<MountEntry> {
+    table.lines().map(|line| line.parse::<MountEntry>().unwrap()).collect()
+}
+
+#[test]
+fn unmount_stacked() {
+    let mounts = mounts(
+        "/dev/sdb1 /media/user/USB vfat rw 0 0\n\
+         tmpfs /media/user/USB/tmp tmpfs rw 0 0\n\
+         /dev/sdc1 /media/user/USB2 ext4 rw 0 0\n\
+         /dev/sdd1 /media/user/USB ext4 rw 0 0\n\
+         /dev/sdd2 /media/user/USB/tmp/deep ext4 rw 0 0",
+    );
+
+    // The mounts beneath the root go first, and the mount stacked on top of the root is
+    // detached before the root itself. `/media/user/USB2` is not beneath the root.
+    let order = unmount_order(&[Path::new("/media/user/USB")], &mounts);
+    let expected: Vec<PathBuf> =
+        ["/media/user/USB/tmp/deep", "/media/user/USB/tmp", "/media/user/USB", "/media/user/USB"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+
+    assert_eq!(order, expected);
+    assert!(unmount_order(&[], &mounts).is_empty());
+}
+
+#[test]
+fn unmount_refuses_other_holders() {
+    let holders = [
+        Holder {
+            device: Path::new("/dev/sdb1").into(),
+            kind: HolderKind::Mount(Path::new("/media/user/USB").into()),
+        },
+        Holder { device: Path::new("/dev/sdb2").into(), kind: HolderKind::Lvm("vg-root".into()) },
+    ];
+
+    // Nothing is detached when any of the holders cannot be torn down.
+    let result = teardown(AsyncPath::new("/dev/sdb"), &holders, &[], UnmountFlags::default());
+    match result {
+        Err(DiskError::Held { holder, .. }) => assert_eq!(holder, holders[1]),
+        other => panic!("expected the LVM volume to be refused, got {:?}", other),
+    }
+}